@@ -0,0 +1,6 @@
+-- Track when a command was cached, independently of when it was last checked
+ALTER TABLE cached_cmd
+ADD COLUMN created_at INTEGER NOT NULL DEFAULT 0;
+
+UPDATE cached_cmd
+SET created_at = updated_at;
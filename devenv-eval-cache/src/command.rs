@@ -5,7 +5,7 @@ use std::io::{self, BufRead, BufReader};
 use std::path::{Path, PathBuf};
 use std::process::{self, Command, Stdio};
 use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 use tracing::{debug, trace};
 
@@ -29,6 +29,7 @@ type OnStderr = Box<dyn Fn(&InternalLog) + Send>;
 pub struct CachedCommand<'a> {
     pool: &'a sqlx::SqlitePool,
     force_refresh: bool,
+    ttl: Option<Duration>,
     extra_paths: Vec<PathBuf>,
     excluded_paths: Vec<PathBuf>,
     on_stderr: Option<OnStderr>,
@@ -39,6 +40,7 @@ impl<'a> CachedCommand<'a> {
         Self {
             pool,
             force_refresh: false,
+            ttl: None,
             extra_paths: Vec::new(),
             excluded_paths: Vec::new(),
             on_stderr: None,
@@ -67,6 +69,12 @@ impl<'a> CachedCommand<'a> {
         self
     }
 
+    /// Expire the cached output after the given duration, even if none of its inputs changed.
+    pub fn ttl(&mut self, ttl: Duration) -> &mut Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
     pub fn on_stderr<F>(&mut self, f: F) -> &mut Self
     where
         F: Fn(&InternalLog) + Send + 'static,
@@ -77,8 +85,11 @@ impl<'a> CachedCommand<'a> {
 
     /// Run a (Nix) command with caching enabled.
     ///
-    /// If the command has been run before and the files it depends on have not been modified,
-    /// the cached output will be returned.
+    /// If the command has been run before, the files it depends on have not been modified,
+    /// and the cached output has not outlived its TTL, the cached output will be returned.
+    ///
+    /// Commands that perform untrackable operations, or that emit a `devenv uncacheable:` trace,
+    /// are never cached. See [Output::uncacheable].
     pub async fn output(mut self, cmd: &'a mut Command) -> Result<Output, CommandError> {
         let raw_cmd = format!("{:?}", cmd);
         let cmd_hash = compute_string_hash(&raw_cmd);
@@ -86,7 +97,7 @@ impl<'a> CachedCommand<'a> {
         // Check whether the command has been previously run and the files it depends on have not been changed.
         if !self.force_refresh {
             if let Ok(Some(output)) =
-                query_cached_output(self.pool, &cmd_hash, &self.extra_paths).await
+                query_cached_output(self.pool, &cmd_hash, &self.extra_paths, self.ttl).await
             {
                 return Ok(output);
             }
@@ -162,26 +173,47 @@ impl<'a> CachedCommand<'a> {
 
         let mut env_inputs = Vec::new();
         let mut sources = Vec::new();
+        let mut uncacheable = None;
+
+        // Filter out paths that don't impact caching
+        let is_tracked = |source: &Path| {
+            source.starts_with("/")
+                && !source.starts_with("/nix/store")
+                && !self
+                    .excluded_paths
+                    .iter()
+                    .any(|path| source.starts_with(path))
+        };
 
         for op in ops.into_iter() {
+            if uncacheable.is_none() {
+                uncacheable = op.uncacheable_reason();
+            }
+
             match op {
                 Op::CopiedSource { source, .. }
                 | Op::EvaluatedFile { source }
                 | Op::ReadFile { source }
                 | Op::ReadDir { source }
                 | Op::PathExists { source }
+                | Op::HashFile { source }
                 | Op::TrackedPath { source }
-                    // Filter out paths that don't impact caching
-                    if source.starts_with("/")
-                        && !source.starts_with("/nix/store")
-                        && !self
-                            .excluded_paths
-                            .iter()
-                            .any(|path| source.starts_with(path)) =>
+                    if is_tracked(&source) =>
                 {
                     sources.push(source);
                 }
 
+                // The filter may select any file under the source, so track all of them.
+                Op::FilteredSource { source } if is_tracked(&source) => {
+                    sources.extend(collect_source_paths(&source, is_tracked));
+                }
+
+                Op::Fetched { url, .. } => {
+                    if let Some(source) = Op::local_fetch_path(&url).filter(|s| is_tracked(s)) {
+                        sources.extend(collect_source_paths(&source, is_tracked));
+                    }
+                }
+
                 Op::GetEnv { name } => {
                     if let Ok(env_input) = EnvInputDesc::new(name) {
                         env_inputs.push(env_input);
@@ -206,6 +238,24 @@ impl<'a> CachedCommand<'a> {
         inputs.sort();
         inputs.dedup_by(Input::dedup);
 
+        if let Some(reason) = &uncacheable {
+            debug!(reason, "Command is not cacheable, skipping the cache");
+
+            // Drop any entry cached before the command became uncacheable.
+            db::delete_command(self.pool, &cmd_hash)
+                .await
+                .map_err(CommandError::Sqlx)?;
+
+            return Ok(Output {
+                status,
+                stdout,
+                stderr,
+                inputs,
+                uncacheable,
+                ..Default::default()
+            });
+        }
+
         let input_hash = Input::compute_input_hash(&inputs);
 
         let _ = db::insert_command_with_inputs(
@@ -246,6 +296,8 @@ pub struct Output {
     pub inputs: Vec<Input>,
     /// Whether the output was returned from the cache or not.
     pub cache_hit: bool,
+    /// Why the output could not be cached, if it depends on state that can't be tracked.
+    pub uncacheable: Option<String>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
@@ -435,12 +487,24 @@ async fn query_cached_output(
     pool: &SqlitePool,
     cmd_hash: &str,
     extra_paths: &[PathBuf],
+    ttl: Option<Duration>,
 ) -> Result<Option<Output>, CommandError> {
     let cached_cmd = db::get_command_by_hash(pool, cmd_hash)
         .await
         .map_err(CommandError::Sqlx)?;
 
     if let Some(cmd) = cached_cmd {
+        if let Some(ttl) = ttl {
+            if is_expired(cmd.created_at, ttl) {
+                debug!(
+                    command_hash = cmd_hash,
+                    ttl = ?ttl,
+                    "Cached output has expired. Refreshing command."
+                );
+                return Ok(None);
+            }
+        }
+
         trace!(
             command_hash = cmd_hash,
             "Found cached command, checking input states"
@@ -563,6 +627,7 @@ async fn query_cached_output(
                 stderr: Vec::new(),
                 inputs: Arc::try_unwrap(inputs).unwrap_or_else(|arc| (*arc).clone()),
                 cache_hit: true,
+                uncacheable: None,
             }))
         }
     } else {
@@ -591,6 +656,40 @@ async fn query_file_inputs(sources: &[PathBuf]) -> Vec<FileInputDesc> {
         .collect::<Vec<_>>()
 }
 
+/// Collect a source path and everything below it that `is_tracked`.
+///
+/// Directories are included so that added or removed entries are picked up.
+/// Untracked directories, like the `.devenv` directory of a project, aren't walked at all.
+/// Git metadata is skipped: it changes far more often than the sources themselves.
+fn collect_source_paths(root: &Path, is_tracked: impl Fn(&Path) -> bool) -> Vec<PathBuf> {
+    let mut paths = vec![root.to_path_buf()];
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            if entry.file_name() == ".git" {
+                continue;
+            }
+
+            let path = entry.path();
+            if !is_tracked(&path) {
+                continue;
+            }
+            // Don't follow symlinks to avoid cycles.
+            if entry.file_type().map(|t| t.is_dir()).unwrap_or(false) {
+                pending.push(path.clone());
+            }
+            paths.push(path);
+        }
+    }
+
+    paths
+}
+
 /// Convert a parse log line into into an `Op`.
 fn extract_op_from_log_line(log: &InternalLog) -> Option<Op> {
     match log {
@@ -689,6 +788,15 @@ fn check_env_state(env: &EnvInputDesc) -> io::Result<FileState> {
     }
 }
 
+/// Check whether a cached entry created at `created_at` has outlived its TTL.
+fn is_expired(created_at: SystemTime, ttl: Duration) -> bool {
+    SystemTime::now()
+        .duration_since(created_at)
+        .map(|age| age > ttl)
+        // The entry was created in the future. Don't trust it.
+        .unwrap_or(true)
+}
+
 fn truncate_to_seconds(time: SystemTime) -> io::Result<SystemTime> {
     let duration_since_epoch = time
         .duration_since(UNIX_EPOCH)
@@ -791,6 +899,130 @@ mod test {
         ));
     }
 
+    #[test]
+    fn test_collect_source_paths() {
+        let temp_dir = TempDir::with_prefix("test_collect_source_paths").unwrap();
+        let root = temp_dir.path();
+        std::fs::create_dir_all(root.join("nested/deeper")).unwrap();
+        std::fs::create_dir_all(root.join(".git")).unwrap();
+        std::fs::write(root.join("a.nix"), "{}").unwrap();
+        std::fs::write(root.join("nested/deeper/b.nix"), "{}").unwrap();
+        std::fs::write(root.join(".git/HEAD"), "ref: refs/heads/main").unwrap();
+        std::fs::create_dir_all(root.join(".devenv/gc")).unwrap();
+        std::fs::write(root.join(".devenv/nix-eval-cache.db"), "").unwrap();
+
+        let excluded = root.join(".devenv");
+        let mut paths = collect_source_paths(root, |path| !path.starts_with(&excluded));
+        paths.sort();
+
+        let mut expected = vec![
+            root.to_path_buf(),
+            root.join("a.nix"),
+            root.join("nested"),
+            root.join("nested/deeper"),
+            root.join("nested/deeper/b.nix"),
+        ];
+        expected.sort();
+
+        assert_eq!(paths, expected);
+    }
+
+    /// A command that logs the given messages like `nix --log-format internal-json` does.
+    fn command_logging(dir: &TempDir, msgs: &[String]) -> Command {
+        let log = dir.path().join("log");
+        let lines = msgs
+            .iter()
+            .map(|msg| {
+                let log = serde_json::json!({ "action": "msg", "level": 0, "msg": msg });
+                format!("@nix {log}\n")
+            })
+            .collect::<String>();
+        std::fs::write(&log, lines).unwrap();
+
+        // The logging flags that `output` appends end up as ignored script arguments.
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg("cat \"$0\" >&2; echo output").arg(&log);
+        cmd
+    }
+
+    #[sqlx::test]
+    async fn test_fetched_local_sources_are_tracked(pool: SqlitePool) {
+        let temp_dir = TempDir::with_prefix("test_fetched_local_sources").unwrap();
+        let repo = temp_dir.path().join("repo");
+        std::fs::create_dir_all(&repo).unwrap();
+        std::fs::write(repo.join("default.nix"), "{}").unwrap();
+
+        let msgs = [
+            format!("trace: devenv fetchGit: 'git+file://{}'", repo.display()),
+            format!(
+                "trace: devenv hashFile: '{}'",
+                repo.join("default.nix").display()
+            ),
+        ];
+        let mut cmd = command_logging(&temp_dir, &msgs);
+        let output = CachedCommand::new(&pool).output(&mut cmd).await.unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.uncacheable, None);
+        assert!(!output.cache_hit);
+
+        let paths = output
+            .inputs
+            .iter()
+            .filter_map(|input| match input {
+                Input::File(desc) => Some(desc.path.clone()),
+                Input::Env(_) => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(paths, vec![repo.clone(), repo.join("default.nix")]);
+
+        let mut cmd = command_logging(&temp_dir, &msgs);
+        let output = CachedCommand::new(&pool).output(&mut cmd).await.unwrap();
+        assert!(output.cache_hit);
+        assert_eq!(output.stdout, b"output\n");
+    }
+
+    #[sqlx::test]
+    async fn test_remote_fetches_and_current_time_are_not_cached(pool: SqlitePool) {
+        let temp_dir = TempDir::with_prefix("test_remote_fetches").unwrap();
+
+        for msg in [
+            "trace: devenv fetchTarball: 'https://example.com/source.tar.gz'",
+            "trace: devenv currentTime",
+        ] {
+            let msgs = [msg.to_string()];
+            for _ in 0..2 {
+                let mut cmd = command_logging(&temp_dir, &msgs);
+                let output = CachedCommand::new(&pool).output(&mut cmd).await.unwrap();
+                assert!(output.status.success());
+                assert!(output.uncacheable.is_some(), "{msg} should not be cached");
+                assert!(!output.cache_hit);
+            }
+        }
+    }
+
+    #[sqlx::test]
+    async fn test_expired_output_is_reevaluated(pool: SqlitePool) {
+        let temp_dir = TempDir::with_prefix("test_expired_output").unwrap();
+        let run = |ttl: Duration| {
+            let mut cmd = command_logging(&temp_dir, &[]);
+            let mut cached_cmd = CachedCommand::new(&pool);
+            cached_cmd.ttl(ttl);
+            async move { cached_cmd.output(&mut cmd).await.unwrap() }
+        };
+
+        let hour = Duration::from_secs(60 * 60);
+        assert!(!run(hour).await.cache_hit);
+        assert!(run(hour).await.cache_hit);
+
+        std::thread::sleep(Duration::from_millis(1100));
+        let output = run(Duration::from_secs(1)).await;
+        assert!(!output.cache_hit);
+        assert_eq!(output.stdout, b"output\n");
+
+        // The re-evaluated output is cached again.
+        assert!(run(hour).await.cache_hit);
+    }
+
     #[test]
     fn test_input_dedup_by() {
         let path = PathBuf::from("test.txt");
@@ -815,6 +1047,20 @@ mod test {
         assert_eq!(inputs[0], file2);
     }
 
+    #[test]
+    fn test_is_expired() {
+        let ttl = Duration::from_secs(60);
+        assert!(!is_expired(SystemTime::now(), ttl));
+        assert!(is_expired(
+            SystemTime::now() - Duration::from_secs(120),
+            ttl
+        ));
+        assert!(is_expired(
+            SystemTime::now() + Duration::from_secs(120),
+            ttl
+        ));
+    }
+
     #[test]
     fn test_truncate_system_time_to_seconds() {
         let time = SystemTime::now();
@@ -25,6 +25,8 @@ pub struct CommandRow {
     pub output: Vec<u8>,
     /// The time the cached command was checked or created
     pub updated_at: SystemTime,
+    /// The time the command was cached
+    pub created_at: SystemTime,
 }
 
 impl sqlx::FromRow<'_, SqliteRow> for CommandRow {
@@ -35,6 +37,7 @@ impl sqlx::FromRow<'_, SqliteRow> for CommandRow {
         let input_hash: String = row.get("input_hash");
         let output: Vec<u8> = row.get("output");
         let updated_at: i64 = row.get("updated_at");
+        let created_at: i64 = row.get("created_at");
         Ok(Self {
             id,
             raw,
@@ -42,6 +45,7 @@ impl sqlx::FromRow<'_, SqliteRow> for CommandRow {
             input_hash,
             output,
             updated_at: time::system_time_from_unix_seconds(updated_at),
+            created_at: time::system_time_from_unix_seconds(created_at),
         })
     }
 }
@@ -111,8 +115,8 @@ where
 
     let record = sqlx::query(
         r#"
-        INSERT INTO cached_cmd (raw, cmd_hash, input_hash, output)
-        VALUES (?, ?, ?, ?)
+        INSERT INTO cached_cmd (raw, cmd_hash, input_hash, output, created_at)
+        VALUES (?, ?, ?, ?, strftime('%s', 'now'))
         RETURNING id
         "#,
     )
@@ -127,7 +131,7 @@ where
     Ok(id)
 }
 
-async fn delete_command<'a, A>(conn: A, cmd_hash: &str) -> Result<(), sqlx::Error>
+pub async fn delete_command<'a, A>(conn: A, cmd_hash: &str) -> Result<(), sqlx::Error>
 where
     A: Acquire<'a, Database = Sqlite>,
 {
@@ -487,6 +491,7 @@ mod tests {
         assert_eq!(retrieved_command.raw, raw_cmd);
         assert_eq!(retrieved_command.cmd_hash, cmd_hash);
         assert_eq!(retrieved_command.output, output);
+        assert_eq!(retrieved_command.created_at, retrieved_command.updated_at);
 
         let files = get_files_by_command_id(&pool, command_id).await.unwrap();
         assert_eq!(files.len(), 2);
@@ -20,6 +20,16 @@ pub enum Op {
     PathExists { source: PathBuf },
     /// Used a tracked devenv string path.
     TrackedPath { source: PathBuf },
+    /// Fetched a source with `config.lib.fetchGit`, `config.lib.fetchTarball` or `config.lib.fetchurl`.
+    Fetched { builtin: String, url: String },
+    /// Copied a path to the store through a filter with `config.lib.filterSource` or `config.lib.path`.
+    FilteredSource { source: PathBuf },
+    /// Hashed a file's contents with `config.lib.hashFile`.
+    HashFile { source: PathBuf },
+    /// Read the current time with `config.lib.currentTime`.
+    CurrentTime,
+    /// Explicitly opted out of caching with a `devenv uncacheable:` trace.
+    Uncacheable { reason: String },
 }
 
 impl Op {
@@ -40,6 +50,16 @@ impl Op {
                 Regex::new("^devenv pathExists: '(?P<source>.*)'$").expect("invalid regex");
             static ref TRACKED_PATH: Regex =
                 Regex::new("^trace: devenv path: '(?P<source>.*)'$").expect("invalid regex");
+            static ref FETCHED: Regex =
+                Regex::new("^trace: devenv (?P<builtin>fetchGit|fetchTarball|fetchurl): '(?P<url>.*)'$").expect("invalid regex");
+            static ref FILTERED_SOURCE: Regex =
+                Regex::new("^trace: devenv filterSource: '(?P<source>.*)'$").expect("invalid regex");
+            static ref HASH_FILE: Regex =
+                Regex::new("^trace: devenv hashFile: '(?P<source>.*)'$").expect("invalid regex");
+            static ref CURRENT_TIME: Regex =
+                Regex::new("^trace: devenv currentTime$").expect("invalid regex");
+            static ref UNCACHEABLE: Regex =
+                Regex::new("^trace: devenv uncacheable:(?P<reason>.*)$").expect("invalid regex");
         }
 
         match log {
@@ -70,6 +90,21 @@ impl Op {
                 } else if let Some(matches) = TRACKED_PATH.captures(msg) {
                     let source = PathBuf::from(&matches["source"]);
                     Some(Op::TrackedPath { source })
+                } else if let Some(matches) = FETCHED.captures(msg) {
+                    let builtin = matches["builtin"].to_string();
+                    let url = matches["url"].to_string();
+                    Some(Op::Fetched { builtin, url })
+                } else if let Some(matches) = FILTERED_SOURCE.captures(msg) {
+                    let source = PathBuf::from(&matches["source"]);
+                    Some(Op::FilteredSource { source })
+                } else if let Some(matches) = HASH_FILE.captures(msg) {
+                    let source = PathBuf::from(&matches["source"]);
+                    Some(Op::HashFile { source })
+                } else if CURRENT_TIME.is_match(msg) {
+                    Some(Op::CurrentTime)
+                } else if let Some(matches) = UNCACHEABLE.captures(msg) {
+                    let reason = matches["reason"].trim().to_string();
+                    Some(Op::Uncacheable { reason })
                 } else {
                     None
                 }
@@ -77,6 +112,39 @@ impl Op {
             _ => None,
         }
     }
+
+    /// The local path behind a fetched URL, if any.
+    ///
+    /// Fetches of local paths can be tracked like any other source.
+    /// Remote fetches can't, because the contents behind the URL may change at any time.
+    pub fn local_fetch_path(url: &str) -> Option<PathBuf> {
+        let path = url
+            .strip_prefix("git+file://")
+            .or_else(|| url.strip_prefix("file://"))
+            .or_else(|| url.strip_prefix("path:"))
+            .unwrap_or(url);
+
+        if path.starts_with('/') {
+            Some(PathBuf::from(path))
+        } else {
+            None
+        }
+    }
+
+    /// Explain why this operation prevents the command from being cached, if it does.
+    pub fn uncacheable_reason(&self) -> Option<String> {
+        match self {
+            Op::Fetched { builtin, url } if Op::local_fetch_path(url).is_none() => {
+                Some(format!("builtins.{builtin} fetched the remote URL '{url}'"))
+            }
+            Op::CurrentTime => Some("builtins.currentTime was used".to_string()),
+            Op::Uncacheable { reason } if reason.is_empty() => {
+                Some("the evaluation was marked as uncacheable".to_string())
+            }
+            Op::Uncacheable { reason } => Some(reason.clone()),
+            _ => None,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -177,6 +245,103 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_fetched() {
+        let log = create_log("trace: devenv fetchTarball: 'https://example.com/source.tar.gz'");
+        let op = Op::from_internal_log(&log);
+        assert_eq!(
+            op,
+            Some(Op::Fetched {
+                builtin: "fetchTarball".to_string(),
+                url: "https://example.com/source.tar.gz".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_filtered_source() {
+        let log = create_log("trace: devenv filterSource: '/path/to/dir'");
+        let op = Op::from_internal_log(&log);
+        assert_eq!(
+            op,
+            Some(Op::FilteredSource {
+                source: PathBuf::from("/path/to/dir"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_hash_file() {
+        let log = create_log("trace: devenv hashFile: '/path/to/file'");
+        let op = Op::from_internal_log(&log);
+        assert_eq!(
+            op,
+            Some(Op::HashFile {
+                source: PathBuf::from("/path/to/file"),
+            })
+        );
+    }
+
+    #[test]
+    fn test_current_time() {
+        let log = create_log("trace: devenv currentTime");
+        let op = Op::from_internal_log(&log);
+        assert_eq!(op, Some(Op::CurrentTime));
+        assert!(op.unwrap().uncacheable_reason().is_some());
+    }
+
+    #[test]
+    fn test_uncacheable() {
+        let log = create_log("trace: devenv uncacheable: reads the latest release");
+        let op = Op::from_internal_log(&log);
+        assert_eq!(
+            op,
+            Some(Op::Uncacheable {
+                reason: "reads the latest release".to_string(),
+            })
+        );
+        assert_eq!(
+            op.unwrap().uncacheable_reason(),
+            Some("reads the latest release".to_string())
+        );
+
+        let log = create_log("trace: devenv uncacheable:");
+        let op = Op::from_internal_log(&log).unwrap();
+        assert!(op.uncacheable_reason().is_some());
+    }
+
+    #[test]
+    fn test_local_fetch_path() {
+        assert_eq!(
+            Op::local_fetch_path("/path/to/repo"),
+            Some(PathBuf::from("/path/to/repo"))
+        );
+        assert_eq!(
+            Op::local_fetch_path("git+file:///path/to/repo"),
+            Some(PathBuf::from("/path/to/repo"))
+        );
+        assert_eq!(
+            Op::local_fetch_path("file:///path/to/source.tar.gz"),
+            Some(PathBuf::from("/path/to/source.tar.gz"))
+        );
+        assert_eq!(Op::local_fetch_path("https://example.com/repo.git"), None);
+    }
+
+    #[test]
+    fn test_remote_fetch_is_uncacheable() {
+        let local = Op::Fetched {
+            builtin: "fetchGit".to_string(),
+            url: "/path/to/repo".to_string(),
+        };
+        assert_eq!(local.uncacheable_reason(), None);
+
+        let remote = Op::Fetched {
+            builtin: "fetchurl".to_string(),
+            url: "https://example.com/file".to_string(),
+        };
+        assert!(remote.uncacheable_reason().is_some());
+    }
+
     #[test]
     fn test_unmatched_log() {
         let log = create_log("some unrelated message");
@@ -0,0 +1,19 @@
+-- The package sets and option declarations that have been indexed
+CREATE TABLE IF NOT EXISTS search_source (
+  id INTEGER PRIMARY KEY,
+  key TEXT NOT NULL UNIQUE,
+  indexed_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now')),
+  used_at INTEGER NOT NULL DEFAULT (strftime('%s', 'now'))
+);
+
+-- The trigram tokenizer matches any substring of at least three characters
+CREATE VIRTUAL TABLE IF NOT EXISTS search_entry USING fts5(
+  name,
+  description,
+  source_id UNINDEXED,
+  kind UNINDEXED,
+  version UNINDEXED,
+  type UNINDEXED,
+  default_value UNINDEXED,
+  tokenize = 'trigram'
+);
@@ -0,0 +1,174 @@
+//! Check that Nix can build for a system, either locally or on a remote builder.
+
+use miette::{bail, IntoDiagnostic, Result, WrapErr};
+use nix_conf_parser::NixConf;
+use std::path::Path;
+
+const DISTRIBUTED_BUILDS_DOCS: &str =
+    "https://nix.dev/manual/nix/latest/advanced-topics/distributed-builds";
+
+/// A remote builder from the `builders` setting or a machines file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Machine {
+    pub uri: String,
+    pub systems: Vec<String>,
+}
+
+/// Parse the `builders` setting, reading the machines files that it refers to with `@`.
+///
+/// Machines are separated by newlines or `;`, and a system of `-` means the local system.
+pub fn parse_machines(builders: &str, local_system: &str) -> Result<Vec<Machine>> {
+    let mut machines = Vec::new();
+    for entry in builders.split([';', '\n']) {
+        let entry = entry.split('#').next().unwrap_or_default().trim();
+        if entry.is_empty() {
+            continue;
+        }
+        if let Some(file) = entry.strip_prefix('@') {
+            let path = Path::new(file.trim());
+            let contents = match std::fs::read_to_string(path) {
+                Ok(contents) => contents,
+                // Nix ignores machines files that don't exist
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(e)
+                        .into_diagnostic()
+                        .wrap_err_with(|| format!("Failed to read {}", path.display()))
+                }
+            };
+            machines.extend(parse_machines(&contents, local_system)?);
+            continue;
+        }
+
+        let mut fields = entry.split_whitespace();
+        let uri = fields.next().unwrap_or_default().to_string();
+        let systems = match fields.next() {
+            None | Some("-") => vec![local_system.to_string()],
+            Some(systems) => systems.split(',').map(str::to_string).collect(),
+        };
+        machines.push(Machine { uri, systems });
+    }
+    Ok(machines)
+}
+
+/// Check that `system` can be built with the given Nix config, and explain what is missing if not.
+pub fn check_can_build(system: &str, config: &NixConf) -> Result<()> {
+    let local_system = config
+        .get("system")
+        .cloned()
+        .unwrap_or_else(crate::default_system);
+    let extra_platforms = config
+        .get("extra-platforms")
+        .map(|platforms| platforms.split_whitespace().collect::<Vec<_>>())
+        .unwrap_or_default();
+    if system == local_system || extra_platforms.contains(&system) {
+        return Ok(());
+    }
+
+    let builders = config
+        .get("builders")
+        .map(String::as_str)
+        .unwrap_or_default();
+    let machines = parse_machines(builders, &local_system)?;
+    if machines
+        .iter()
+        .any(|machine| machine.systems.iter().any(|s| s == system))
+    {
+        return Ok(());
+    }
+
+    let help = if machines.is_empty() {
+        let linux_builder = if local_system.ends_with("-darwin") {
+            "enable the linux-builder of nix-darwin with `nix.linux-builder.enable = true;`, or "
+        } else {
+            ""
+        };
+        format!(
+            "No remote builders are configured. To build for {system}, {linux_builder}add a {system} machine to `builders` in nix.conf: {DISTRIBUTED_BUILDS_DOCS}"
+        )
+    } else {
+        let available = machines
+            .iter()
+            .map(|machine| format!("  {} ({})", machine.uri, machine.systems.join(", ")))
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!(
+            "None of the configured remote builders support {system}:\n{available}\nAdd a {system} machine to `builders` in nix.conf: {DISTRIBUTED_BUILDS_DOCS}"
+        )
+    };
+    bail!("This machine is {local_system} and can't build for {system}.\n{help}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(settings: &str) -> NixConf {
+        NixConf::parse_str(settings).unwrap()
+    }
+
+    #[test]
+    fn parses_machines() {
+        let dir = tempfile::tempdir().unwrap();
+        let machines_file = dir.path().join("machines");
+        std::fs::write(
+            &machines_file,
+            "# linux-builder\nssh-ng://builder@linux-builder aarch64-linux /etc/nix/builder_ed25519 4 1 kvm,benchmark,big-parallel - -\n",
+        )
+        .unwrap();
+        let builders = format!(
+            "ssh://big x86_64-linux,i686-linux - 8 ; ssh://local - ; @{} ; @/does/not/exist",
+            machines_file.display()
+        );
+
+        assert_eq!(
+            parse_machines(&builders, "aarch64-darwin").unwrap(),
+            vec![
+                Machine {
+                    uri: "ssh://big".to_string(),
+                    systems: vec!["x86_64-linux".to_string(), "i686-linux".to_string()],
+                },
+                Machine {
+                    uri: "ssh://local".to_string(),
+                    systems: vec!["aarch64-darwin".to_string()],
+                },
+                Machine {
+                    uri: "ssh-ng://builder@linux-builder".to_string(),
+                    systems: vec!["aarch64-linux".to_string()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_the_local_system_and_extra_platforms() {
+        let linux = config("system = x86_64-linux\nextra-platforms = i686-linux\nbuilders =\n");
+        assert!(check_can_build("x86_64-linux", &linux).is_ok());
+        assert!(check_can_build("i686-linux", &linux).is_ok());
+
+        let error = check_can_build("aarch64-linux", &linux)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("This machine is x86_64-linux and can't build for aarch64-linux"));
+        assert!(error.contains("No remote builders are configured"));
+        assert!(!error.contains("linux-builder"));
+    }
+
+    #[test]
+    fn checks_remote_builders() {
+        let darwin = config("system = aarch64-darwin\nbuilders = ssh://x86 x86_64-linux\n");
+        assert!(check_can_build("x86_64-linux", &darwin).is_ok());
+
+        let error = check_can_build("aarch64-linux", &darwin)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("None of the configured remote builders support aarch64-linux"));
+        assert!(error.contains("ssh://x86 (x86_64-linux)"));
+
+        let darwin = config("system = aarch64-darwin\n");
+        let error = check_can_build("aarch64-linux", &darwin)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("nix.linux-builder.enable = true;"));
+    }
+}
@@ -1,4 +1,5 @@
 use crate::log::LogFormat;
+use crate::oci::ExportFormat;
 use clap::{crate_version, Parser, Subcommand};
 use devenv_tasks::RunMode;
 use std::path::PathBuf;
@@ -62,6 +63,16 @@ pub struct GlobalOptions {
     )]
     pub log_format: LogFormat,
 
+    #[arg(
+        long,
+        global = true,
+        env = "DEVENV_OTLP_ENDPOINT",
+        value_name = "URL",
+        help = "Export traces to an OpenTelemetry collector.",
+        long_help = "Export traces to an OpenTelemetry collector.\n\nURL is the base URL of the collector's OTLP/HTTP receiver, e.g. http://localhost:4318.\nRequires devenv to be built with the `otel` feature."
+    )]
+    pub otlp_endpoint: Option<String>,
+
     #[arg(short = 'j', long,
         global = true, help = "Maximum number of Nix builds at any time.",
         default_value_t = max_jobs())]
@@ -114,6 +125,16 @@ pub struct GlobalOptions {
     )]
     pub refresh_eval_cache: bool,
 
+    #[arg(
+        long,
+        global = true,
+        value_name = "AGE",
+        value_parser = crate::gc::parse_age,
+        help = "Re-evaluate cached Nix evaluations older than AGE, like 30m, 12h or 1d.",
+        long_help = "Re-evaluate cached Nix evaluations older than AGE, like 30m, 12h or 1d.\n\nUseful with --impure, when an evaluation reads state that devenv can't track, like the latest version from a network input."
+    )]
+    pub eval_cache_ttl: Option<std::time::Duration>,
+
     #[arg(
         long,
         global = true,
@@ -166,9 +187,43 @@ pub struct GlobalOptions {
         num_args = 2,
         value_names = ["OPTION", "VALUE"],
         help = "Override configuration options with typed values",
-        long_help = "Override configuration options with typed values.\n\nOPTION must include a type: <attribute>:<type>\nSupported types: string, int, float, bool, path, pkg, pkgs\n\nExamples:\n  --option languages.rust.channel:string beta\n  --option services.postgres.enable:bool true\n  --option languages.python.version:string 3.10\n  --option packages:pkgs \"ncdu git\""
+        long_help = "Override configuration options with typed values.\n\nOPTION must include a type: <attribute>:<type>\nSupported types: string, int, float, bool, path, pkg, pkgs, json\n\nRelative paths are resolved against the project root.\nUse json for lists, attribute sets and null.\n\nExamples:\n  --option languages.rust.channel:string beta\n  --option services.postgres.enable:bool true\n  --option languages.python.version:string 3.10\n  --option packages:pkgs \"ncdu git\"\n  --option services.postgres.initialDatabases:json '[{\"name\": \"app\"}]'"
     )]
     pub option: Vec<String>,
+
+    #[arg(
+        long,
+        global = true,
+        requires = "option",
+        help = "Save the --option overrides to devenv.local.yaml."
+    )]
+    pub persist_options: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "NAME",
+        help = "Use a profile from devenv.yaml",
+        long_help = "Use a profile from devenv.yaml.\n\nProfiles add imports and option overrides on top of the default environment.\nEach profile keeps its own GC roots and evaluation cache in .devenv/profiles/<NAME>.\n\nExamples:\n  --profile ci\n  --profile gpu"
+    )]
+    pub profile: Option<String>,
+
+    #[arg(
+        long,
+        global = true,
+        help = "Run the command in every project below the current directory",
+        long_help = "Run the command in every project below the current directory.\n\nA project is a directory with a devenv.nix. Hidden directories and the directories that a project imports in its devenv.yaml are skipped.\n\nExamples:\n  devenv --all test\n  devenv --all tasks run lint"
+    )]
+    pub all: bool,
+
+    #[arg(
+        long,
+        global = true,
+        value_name = "N",
+        default_value_t = 4,
+        help = "Maximum number of projects to run at once with --all."
+    )]
+    pub max_parallel: usize,
 }
 
 impl Default for GlobalOptions {
@@ -178,6 +233,7 @@ impl Default for GlobalOptions {
             verbose: false,
             quiet: false,
             log_format: LogFormat::default(),
+            otlp_endpoint: None,
             max_jobs: max_jobs(),
             cores: 2,
             system: default_system(),
@@ -185,12 +241,17 @@ impl Default for GlobalOptions {
             eval_cache: true,
             no_eval_cache: false,
             refresh_eval_cache: false,
+            eval_cache_ttl: None,
             offline: false,
             clean: None,
             nix_debugger: false,
             nix_option: vec![],
             override_input: vec![],
             option: vec![],
+            persist_options: false,
+            profile: None,
+            all: false,
+            max_parallel: 4,
         }
     }
 }
@@ -241,9 +302,60 @@ pub enum Commands {
     Update { name: Option<String> },
 
     #[command(
-        about = "Search for packages and options in nixpkgs. https://devenv.sh/packages/#searching-for-a-file"
+        about = "Search for packages and options in nixpkgs. https://devenv.sh/packages/#searching-for-a-file",
+        long_about = "Search for packages and options in nixpkgs. https://devenv.sh/packages/#searching-for-a-file\n\nPackages and options are searched in a local index, which is built the first time and again whenever the locked revision of nixpkgs changes. Misspelled queries still find close matches."
     )]
-    Search { name: String },
+    Search {
+        name: String,
+
+        #[arg(long, help = "Print the results as JSON.")]
+        json: bool,
+
+        #[arg(long, conflicts_with = "packages_only", help = "Only search options.")]
+        options_only: bool,
+
+        #[arg(long, help = "Only search packages.")]
+        packages_only: bool,
+
+        #[arg(
+            long,
+            default_value_t = 50,
+            help = "The maximum number of packages and of options to show."
+        )]
+        limit: usize,
+
+        #[arg(
+            long,
+            help = "Show everything about the packages that are found, like `devenv show-package`."
+        )]
+        details: bool,
+    },
+
+    #[command(
+        about = "Show everything about a package: its license, homepage, platforms, outputs and whether it's cached. https://devenv.sh/packages/"
+    )]
+    ShowPackage {
+        #[arg(help = "The attribute path of the package, like python3Packages.pytest.")]
+        attr: String,
+
+        #[arg(long, help = "Print the details as JSON.")]
+        json: bool,
+    },
+
+    #[command(about = "Add a package, language or service to devenv.nix, keeping its formatting.")]
+    Add {
+        #[command(subcommand)]
+        command: AddCommand,
+    },
+
+    #[command(
+        hide = true,
+        about = "Add a package to packages in devenv.nix. Same as `devenv add package`."
+    )]
+    AddPackage {
+        #[arg(help = "The attribute path of the package, like python3Packages.pytest.")]
+        attr: String,
+    },
 
     #[command(
         alias = "show",
@@ -302,6 +414,12 @@ pub enum Commands {
         command: InputsCommand,
     },
 
+    #[command(about = "Inspect and edit the configuration in devenv.yaml and its overrides.")]
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+
     #[command(
         about = "Launch an interactive environment for inspecting the devenv configuration."
     )]
@@ -310,7 +428,42 @@ pub enum Commands {
     #[command(
         about = "Delete previous shell generations. See https://devenv.sh/garbage-collection"
     )]
-    Gc {},
+    Gc {
+        #[arg(
+            long,
+            help = "List what would be deleted and how much space it would free."
+        )]
+        dry_run: bool,
+
+        #[arg(
+            long,
+            value_name = "N",
+            help = "Keep the newest N environments of each kind."
+        )]
+        keep_generations: Option<usize>,
+
+        #[arg(
+            long,
+            value_name = "AGE",
+            value_parser = crate::gc::parse_age,
+            help = "Only delete environments older than AGE, like 30d, 12h or 2w."
+        )]
+        older_than: Option<std::time::Duration>,
+
+        #[arg(
+            long,
+            help = "Only delete previous environments of the current project in .devenv/gc."
+        )]
+        project: bool,
+    },
+
+    #[command(
+        about = "List the projects devenv has been used in and the disk space their environments use."
+    )]
+    Projects {
+        #[command(subcommand)]
+        command: ProjectsCommand,
+    },
 
     #[command(about = "Build any attribute in devenv.nix.")]
     Build {
@@ -383,18 +536,69 @@ pub enum TasksCommand {
     arg_required_else_help(true)
 )]
 pub enum ContainerCommand {
-    #[command(about = "Build a container.")]
-    Build { name: String },
+    #[command(
+        about = "Build a container.",
+        long_about = "Build a container.\n\nWith --systems, the container is built for each system and written to an OCI layout with an image index, whose path is printed.\n\nExamples:\n  devenv container build processes\n  devenv container build processes --systems x86_64-linux,aarch64-linux"
+    )]
+    Build {
+        name: String,
 
-    #[command(about = "Copy a container to registry.")]
-    Copy { name: String },
+        #[arg(
+            long,
+            value_delimiter = ',',
+            help = "Build an image for each of these systems, like x86_64-linux,aarch64-linux."
+        )]
+        systems: Vec<String>,
+    },
+
+    #[command(
+        about = "Copy a container to registry.",
+        long_about = "Copy a container to registry.\n\nImages are pushed to docker:// registries directly, with the credentials from `docker login` or from DEVENV_REGISTRY_USERNAME and DEVENV_REGISTRY_PASSWORD. Other destinations are copied with skopeo.\n\nExamples:\n  devenv container copy processes --registry docker://ghcr.io/owner/\n  devenv container copy processes --registry docker://ghcr.io/owner/ --tag 1.0 --tag latest\n  devenv container copy processes --registry docker://ghcr.io/owner/ --systems x86_64-linux,aarch64-linux"
+    )]
+    Copy {
+        name: String,
+
+        #[arg(
+            long = "tag",
+            value_name = "TAG",
+            help = "Tag to push the image as. Can be repeated. Defaults to the container version."
+        )]
+        tags: Vec<String>,
+
+        #[arg(
+            long,
+            value_delimiter = ',',
+            help = "Push an image index with an image for each of these systems, like x86_64-linux,aarch64-linux."
+        )]
+        systems: Vec<String>,
+    },
 
     #[command(about = "Run a container.")]
     Run { name: String },
+
+    #[command(about = "List the containers of the project.")]
+    List,
+
+    #[command(about = "Build a container and show its environment, layers and store paths.")]
+    Inspect { name: String },
+
+    #[command(
+        about = "Export a container to an OCI layout or a tarball, without Docker.",
+        long_about = "Export a container to an OCI layout or a tarball, without Docker.\n\nPrints the digest of the image manifest once the export is done.\n\nExamples:\n  devenv container export shell --output shell\n  devenv container export shell --format docker-archive --output shell.tar"
+    )]
+    Export {
+        name: String,
+
+        #[arg(long, value_enum, default_value_t = ExportFormat::OciDir)]
+        format: ExportFormat,
+
+        #[arg(short, long, help = "The directory or file to write the image to.")]
+        output: PathBuf,
+    },
 }
 
 #[derive(Subcommand, Clone)]
-#[clap(about = "Add an input to devenv.yaml. https://devenv.sh/inputs/")]
+#[clap(about = "Manage the inputs in devenv.yaml. https://devenv.sh/inputs/")]
 pub enum InputsCommand {
     #[command(about = "Add an input to devenv.yaml.")]
     Add {
@@ -409,6 +613,124 @@ pub enum InputsCommand {
         #[arg(short, long, help = "What inputs should follow your inputs?")]
         follows: Vec<String>,
     },
+
+    #[command(about = "List the inputs in devenv.yaml and their locked revisions.")]
+    List {},
+
+    #[command(about = "Make inputs follow the top-level inputs they duplicate, like nixpkgs.")]
+    Dedupe {},
+
+    #[command(about = "Show how the locked inputs changed since a git revision.")]
+    Diff {
+        #[arg(help = "The git revision to compare devenv.lock with, e.g. HEAD~1 or main.")]
+        git_ref: String,
+    },
+
+    #[command(
+        about = "Remove an input from devenv.yaml, along with any follows that point to it."
+    )]
+    Remove {
+        #[arg(help = "The name of the input.")]
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+#[clap(
+    about = "Find projects whose environments take up space. https://devenv.sh/garbage-collection/"
+)]
+pub enum ProjectsCommand {
+    #[command(
+        about = "List known projects with when they were last used, their GC roots and disk usage."
+    )]
+    List,
+
+    #[command(
+        about = "Forget a project and remove its GC roots from devenv's data home, so that `devenv gc` can free them."
+    )]
+    Forget { path: PathBuf },
+}
+
+#[derive(Subcommand, Clone)]
+#[clap(about = "Add a package, language or service to devenv.nix, keeping its formatting.")]
+pub enum AddCommand {
+    #[command(about = "Add a package to packages. https://devenv.sh/packages/")]
+    Package {
+        #[arg(help = "The attribute path of the package, like python3Packages.pytest.")]
+        attr: String,
+    },
+
+    #[command(about = "Enable a language, like python. https://devenv.sh/languages/")]
+    Language {
+        name: String,
+
+        #[arg(
+            long,
+            help = "The version of the language, for languages that have a version option."
+        )]
+        version: Option<String>,
+    },
+
+    #[command(about = "Enable a service, like postgres. https://devenv.sh/services/")]
+    Service { name: String },
+}
+
+#[derive(Subcommand, Clone)]
+#[clap(about = "Inspect the merged configuration. https://devenv.sh/files-and-variables/")]
+pub enum ConfigCommand {
+    #[command(
+        about = "Print the configuration merged from the user config, devenv.yaml and devenv.local.yaml."
+    )]
+    Show {
+        #[arg(long, help = "Show which file each value came from.")]
+        origin: bool,
+    },
+
+    #[command(
+        about = "Print the value of a configuration key, e.g. allowUnfree or inputs.nixpkgs.url."
+    )]
+    Get { key: String },
+
+    #[command(about = "Set a configuration key in devenv.yaml.")]
+    Set {
+        key: String,
+
+        #[arg(
+            help = "The value, parsed as YAML, e.g. true, github:NixOS/nixpkgs or [HOME, USER]."
+        )]
+        value: String,
+
+        #[arg(
+            long,
+            conflicts_with = "user",
+            help = "Write to devenv.local.yaml instead."
+        )]
+        local: bool,
+
+        #[arg(
+            long,
+            help = "Write to the user config shared by all projects instead."
+        )]
+        user: bool,
+    },
+
+    #[command(about = "Remove a configuration key from devenv.yaml.")]
+    Unset {
+        key: String,
+
+        #[arg(
+            long,
+            conflicts_with = "user",
+            help = "Remove from devenv.local.yaml instead."
+        )]
+        local: bool,
+
+        #[arg(
+            long,
+            help = "Remove from the user config shared by all projects instead."
+        )]
+        user: bool,
+    },
 }
 
 pub fn default_system() -> String {
@@ -432,6 +754,14 @@ pub fn default_system() -> String {
     format!("{arch}-{os}")
 }
 
+/// The system to build containers for: containers run Linux, so macOS builds for Linux on the same architecture.
+pub fn container_system(system: &str) -> String {
+    match system.strip_suffix("-darwin") {
+        Some(arch) => format!("{arch}-linux"),
+        None => system.to_string(),
+    }
+}
+
 fn max_jobs() -> u8 {
     let num_cpus = std::thread::available_parallelism().unwrap_or_else(|e| {
         error!("Failed to get number of logical CPUs: {}", e);
@@ -442,11 +772,18 @@ fn max_jobs() -> u8 {
 
 #[cfg(test)]
 mod tests {
-    use super::Cli;
+    use super::{container_system, Cli};
 
     #[test]
     fn verify_cli() {
         use clap::CommandFactory;
         Cli::command().debug_assert()
     }
+
+    #[test]
+    fn containers_are_built_for_linux() {
+        assert_eq!(container_system("aarch64-darwin"), "aarch64-linux");
+        assert_eq!(container_system("x86_64-darwin"), "x86_64-linux");
+        assert_eq!(container_system("aarch64-linux"), "aarch64-linux");
+    }
 }
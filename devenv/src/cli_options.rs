@@ -0,0 +1,547 @@
+//! Option overrides given with `--option <attribute>:<type> <value>`.
+//!
+//! The overrides are written to `cli-options.nix`, a module that's imported after devenv.nix.
+
+use crate::util;
+use miette::{bail, miette, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+pub const SUPPORTED_TYPES: &[&str] = &[
+    "string", "int", "float", "bool", "path", "pkg", "pkgs", "json",
+];
+
+const NIX_KEYWORDS: &[&str] = &[
+    "assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with",
+];
+
+/// A value that can be written as a Nix expression.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NixValue {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    /// A path, relative to the project root unless it's absolute.
+    Path(String),
+    /// A package from `pkgs`, by its attribute path.
+    Package(Vec<String>),
+    List(Vec<NixValue>),
+    AttrSet(BTreeMap<String, NixValue>),
+}
+
+impl NixValue {
+    /// Write the value as a Nix expression.
+    ///
+    /// `root` is a Nix expression for the project root that relative paths are resolved against.
+    pub fn to_nix(&self, root: &str) -> String {
+        match self {
+            NixValue::Null => "null".to_string(),
+            NixValue::Bool(b) => b.to_string(),
+            NixValue::Int(i) if *i < 0 => format!("({i})"),
+            NixValue::Int(i) => i.to_string(),
+            NixValue::Float(f) => {
+                let mut float = f.to_string();
+                // Without a decimal point, Nix would read the number as an integer.
+                if !float.contains(['.', 'e', 'E']) {
+                    float.push_str(".0");
+                }
+                if *f < 0.0 {
+                    format!("({float})")
+                } else {
+                    float
+                }
+            }
+            NixValue::String(s) => quote(s),
+            // Appending a string to a path keeps any character in the path literal.
+            NixValue::Path(path) if path.starts_with('/') => format!("(/. + {})", quote(path)),
+            NixValue::Path(path) => {
+                let path = path.trim_start_matches("./");
+                format!("({root} + {})", quote(&format!("/{path}")))
+            }
+            NixValue::Package(path) => std::iter::once("pkgs".to_string())
+                .chain(path.iter().map(|name| attr_name(name)))
+                .collect::<Vec<_>>()
+                .join("."),
+            NixValue::List(items) if items.is_empty() => "[ ]".to_string(),
+            NixValue::List(items) => format!(
+                "[ {} ]",
+                items
+                    .iter()
+                    .map(|item| item.to_nix(root))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            NixValue::AttrSet(attrs) if attrs.is_empty() => "{ }".to_string(),
+            NixValue::AttrSet(attrs) => format!(
+                "{{ {} }}",
+                attrs
+                    .iter()
+                    .map(|(name, value)| format!("{} = {};", attr_name(name), value.to_nix(root)))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+        }
+    }
+
+    /// A description of the kind of value for error messages.
+    fn kind(&self) -> &'static str {
+        match self {
+            NixValue::Null => "null",
+            NixValue::Bool(_) => "a boolean",
+            NixValue::Int(_) => "an integer",
+            NixValue::Float(_) => "a float",
+            NixValue::String(_) => "a string",
+            NixValue::Path(_) => "a path",
+            NixValue::Package(_) => "a package",
+            NixValue::List(_) => "a list",
+            NixValue::AttrSet(_) => "an attribute set",
+        }
+    }
+}
+
+impl From<serde_json::Value> for NixValue {
+    fn from(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Null => NixValue::Null,
+            serde_json::Value::Bool(b) => NixValue::Bool(b),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(i) => NixValue::Int(i),
+                None => NixValue::Float(n.as_f64().unwrap_or_default()),
+            },
+            serde_json::Value::String(s) => NixValue::String(s),
+            serde_json::Value::Array(items) => {
+                NixValue::List(items.into_iter().map(NixValue::from).collect())
+            }
+            serde_json::Value::Object(attrs) => NixValue::AttrSet(
+                attrs
+                    .into_iter()
+                    .map(|(name, value)| (name, NixValue::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// A Nix string literal.
+fn quote(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            // Escape interpolation, but leave a lone `$` alone.
+            '$' if chars.peek() == Some(&'{') => quoted.push_str("\\$"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// An attribute name, quoted if it isn't a valid identifier.
+pub fn attr_name(name: &str) -> String {
+    let mut chars = name.chars();
+    let is_identifier = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '\'' | '-'))
+        && !NIX_KEYWORDS.contains(&name);
+    if is_identifier {
+        name.to_string()
+    } else {
+        quote(name)
+    }
+}
+
+/// Split an attribute path like `env."FOO.BAR"` into its names.
+fn parse_attr_path(path: &str) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    let mut chars = path.chars().peekable();
+    loop {
+        let mut name = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => name.extend(chars.next()),
+                    Some(c) => name.push(c),
+                    None => bail!("Invalid attribute path '{path}': unterminated quote"),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != '.') {
+                name.push(c);
+            }
+            if name.is_empty() {
+                bail!("Invalid attribute path '{path}': empty attribute name");
+            }
+        }
+        names.push(name);
+
+        match chars.next() {
+            None => return Ok(names),
+            Some('.') => {}
+            Some(c) => bail!("Invalid attribute path '{path}': unexpected '{c}' after a quote"),
+        }
+    }
+}
+
+/// An option override, from `--option <attribute>:<type> <value>`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OptionOverride {
+    /// The attribute path of the option.
+    pub path: Vec<String>,
+    pub type_name: String,
+    pub value: NixValue,
+}
+
+impl OptionOverride {
+    pub fn parse(option: &str, value: &str) -> Result<Self> {
+        let Some((attribute, type_name)) = option.rsplit_once(':') else {
+            bail!(
+                "Invalid option format: '{option}'. Must include type, e.g. 'languages.rust.version:string'. Supported types: {}",
+                SUPPORTED_TYPES.join(", ")
+            );
+        };
+        let path = parse_attr_path(attribute)?;
+        let invalid = |expected: &str| {
+            miette!("Invalid value '{value}' for {attribute}: expected {expected}")
+        };
+
+        let value = match type_name {
+            "string" => NixValue::String(value.to_string()),
+            "int" => NixValue::Int(value.parse().map_err(|_| invalid("an integer"))?),
+            "float" => match value.parse::<f64>() {
+                Ok(f) if f.is_finite() => NixValue::Float(f),
+                _ => return Err(invalid("a number")),
+            },
+            "bool" => match value {
+                "true" => NixValue::Bool(true),
+                "false" => NixValue::Bool(false),
+                _ => return Err(invalid("true or false")),
+            },
+            "path" if value.is_empty() => return Err(invalid("a path")),
+            "path" => NixValue::Path(value.to_string()),
+            "pkg" => NixValue::Package(parse_attr_path(value)?),
+            "pkgs" => NixValue::List(
+                value
+                    .split_whitespace()
+                    .map(|package| parse_attr_path(package).map(NixValue::Package))
+                    .collect::<Result<_>>()?,
+            ),
+            "json" => serde_json::from_str::<serde_json::Value>(value)
+                .map(NixValue::from)
+                .map_err(|e| miette!("Invalid JSON for {attribute}: {e}"))?,
+            _ => bail!(
+                "Unsupported type: '{type_name}'. Supported types: {}",
+                SUPPORTED_TYPES.join(", ")
+            ),
+        };
+
+        Ok(Self {
+            path,
+            type_name: type_name.to_string(),
+            value,
+        })
+    }
+
+    /// The dotted attribute path, as written in Nix.
+    pub fn attribute(&self) -> String {
+        self.path
+            .iter()
+            .map(|name| attr_name(name))
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+}
+
+/// Parse `--option` arguments, given as pairs of `<attribute>:<type>` and the value.
+///
+/// Defining an attribute twice is an error in Nix, so the last value of an option wins.
+pub fn parse_overrides(args: &[String]) -> Result<Vec<OptionOverride>> {
+    let mut overrides: Vec<OptionOverride> = Vec::new();
+    for chunk in args.chunks_exact(2) {
+        let option = OptionOverride::parse(&chunk[0], &chunk[1])?;
+        overrides.retain(|existing| existing.path != option.path);
+        overrides.push(option);
+    }
+    Ok(overrides)
+}
+
+/// A Nix expression for the project root, relative to the directory of cli-options.nix.
+pub fn root_expr(root: &Path, dotfile: &Path) -> String {
+    match dotfile.strip_prefix(root) {
+        Ok(relative) if relative.components().count() > 0 => {
+            let parents = vec![".."; relative.components().count()];
+            format!("./{}", parents.join("/"))
+        }
+        _ => NixValue::Path(root.to_string_lossy().to_string()).to_nix(""),
+    }
+}
+
+/// Render the cli-options.nix module.
+pub fn render_module(overrides: &[OptionOverride], root: &str) -> String {
+    let mut module = String::from("{ pkgs, lib, config, ... }: {\n");
+    for option in overrides {
+        let value = option.value.to_nix(root);
+        // Lists of packages are merged with the packages from devenv.nix. Anything else is forced.
+        let value = if option.type_name == "pkgs" {
+            value
+        } else {
+            format!("lib.mkForce {value}")
+        };
+        module.push_str(&format!("  {} = {};\n", option.attribute(), value));
+    }
+    module.push_str("}\n");
+    module
+}
+
+/// Check the overrides against the options declared by the environment, from options.json.
+///
+/// `declarations` maps option names, like `processes.<name>.exec`, to their types.
+pub fn validate(
+    overrides: &[OptionOverride],
+    declarations: &BTreeMap<String, String>,
+) -> Result<()> {
+    let declarations = declarations
+        .iter()
+        .map(|(name, type_)| (name.split('.').collect::<Vec<_>>(), name, type_))
+        .collect::<Vec<_>>();
+    let matches = |pattern: &[&str], path: &[String]| {
+        pattern.len() == path.len()
+            && pattern
+                .iter()
+                .zip(path)
+                .all(|(p, name)| p == name || *p == "*" || (p.starts_with('<') && p.ends_with('>')))
+    };
+
+    for option in overrides {
+        let attribute = option.attribute();
+        if let Some((_, _, type_)) = declarations
+            .iter()
+            .find(|(pattern, _, _)| matches(pattern, &option.path))
+        {
+            if !accepts(type_, &option.value) {
+                bail!(
+                    "Option {attribute} has type `{type_}`, but the value is {}.",
+                    option.value.kind()
+                );
+            }
+            continue;
+        }
+
+        // Values of options like `env` don't have declarations of their own.
+        let freeform = (1..option.path.len()).rev().any(|depth| {
+            let prefix = &option.path[..depth];
+            let is_declared = declarations
+                .iter()
+                .any(|(pattern, _, _)| matches(pattern, prefix));
+            let has_suboptions = declarations
+                .iter()
+                .any(|(pattern, _, _)| pattern.len() > depth && matches(&pattern[..depth], prefix));
+            is_declared && !has_suboptions
+        });
+        if freeform {
+            continue;
+        }
+
+        let names = declarations
+            .iter()
+            .map(|(_, name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        let hint = util::closest_match(&attribute, &names)
+            .map(|name| format!(" Did you mean {name}?"))
+            .unwrap_or_default();
+        bail!("Option {attribute} does not exist.{hint}");
+    }
+    Ok(())
+}
+
+/// Whether a value can be assigned to an option of the given type, as described in options.json.
+///
+/// Option types are only described in prose, so this errs on the side of accepting values.
+fn accepts(type_: &str, value: &NixValue) -> bool {
+    let type_ = type_.to_lowercase();
+    let nullable = type_.starts_with("null or ");
+    let base = type_.trim_start_matches("null or ");
+    let any_of = |names: &[&str]| names.iter().any(|name| base.contains(name));
+
+    match value {
+        NixValue::Null => nullable || any_of(&["null", "anything", "unspecified"]),
+        _ if base.starts_with("list of") => matches!(value, NixValue::List(_)),
+        _ if base.starts_with("attribute set of") || base.starts_with("lazy attribute set of") => {
+            matches!(value, NixValue::AttrSet(_))
+        }
+        _ if any_of(&[
+            "anything",
+            "unspecified",
+            "raw value",
+            "json value",
+            "yaml value",
+            "toml value",
+        ]) =>
+        {
+            true
+        }
+        NixValue::Bool(_) => base.contains("boolean"),
+        NixValue::Int(_) => {
+            base.contains("integer") || (base.contains("number") && !base.contains("floating"))
+        }
+        NixValue::Float(_) => any_of(&["floating point", "number"]),
+        NixValue::String(_) => any_of(&["string", "str", "one of", "lines", "path", "package"]),
+        NixValue::Path(_) => any_of(&["path", "string", "str"]),
+        NixValue::Package(_) => any_of(&["package", "path"]),
+        NixValue::List(_) => base.contains("list"),
+        NixValue::AttrSet(_) => any_of(&["attribute set", "submodule", "attrs"]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(option: &str, value: &str) -> Result<OptionOverride> {
+        OptionOverride::parse(option, value)
+    }
+
+    #[test]
+    fn encodes_strings() {
+        let option = parse("env.GREETING:string", "say \"hi\" to ${USER}\\n$HOME").unwrap();
+        assert_eq!(
+            option.value.to_nix("./.."),
+            r#""say \"hi\" to \${USER}\\n$HOME""#
+        );
+    }
+
+    #[test]
+    fn encodes_typed_values() {
+        assert_eq!(parse("a:int", "-3").unwrap().value.to_nix(""), "(-3)");
+        assert_eq!(parse("a:float", "3").unwrap().value.to_nix(""), "3.0");
+        assert_eq!(parse("a:float", "0.5").unwrap().value.to_nix(""), "0.5");
+        assert_eq!(parse("a:bool", "true").unwrap().value.to_nix(""), "true");
+        assert_eq!(
+            parse("a:pkg", "python3Packages.requests")
+                .unwrap()
+                .value
+                .to_nix(""),
+            "pkgs.python3Packages.requests"
+        );
+        assert_eq!(
+            parse("packages:pkgs", "ncdu  git")
+                .unwrap()
+                .value
+                .to_nix(""),
+            "[ pkgs.ncdu pkgs.git ]"
+        );
+
+        assert!(parse("a:int", "1.5").is_err());
+        assert!(parse("a:float", "NaN").is_err());
+        assert!(parse("a:bool", "yes").is_err());
+        assert!(parse("a:color", "red").is_err());
+        assert!(parse("a", "b").is_err());
+    }
+
+    #[test]
+    fn encodes_paths_relative_to_the_root() {
+        let root = root_expr(Path::new("/project"), Path::new("/project/.devenv"));
+        assert_eq!(root, "./..");
+        assert_eq!(
+            parse("a:path", "./data dir").unwrap().value.to_nix(&root),
+            r#"(./.. + "/data dir")"#
+        );
+        assert_eq!(
+            parse("a:path", "/etc/hosts").unwrap().value.to_nix(&root),
+            r#"(/. + "/etc/hosts")"#
+        );
+        assert_eq!(
+            root_expr(Path::new("/project"), Path::new("/elsewhere/.devenv")),
+            r#"(/. + "/project")"#
+        );
+    }
+
+    #[test]
+    fn encodes_json() {
+        let option = parse(
+            "services.postgres.settings:json",
+            r#"{"log_min_duration_statement": -1, "shared_preload_libraries": ["pg_stat_statements"], "port": null, "in": true}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            option.value.to_nix(""),
+            r#"{ "in" = true; log_min_duration_statement = (-1); port = null; shared_preload_libraries = [ "pg_stat_statements" ]; }"#
+        );
+        assert!(parse("a:json", "{").is_err());
+    }
+
+    #[test]
+    fn quotes_attribute_names() {
+        let option = parse(r#"env."FOO.BAR":string"#, "baz").unwrap();
+        assert_eq!(option.path, vec!["env", "FOO.BAR"]);
+        assert_eq!(option.attribute(), r#"env."FOO.BAR""#);
+        assert!(parse("env..FOO:string", "baz").is_err());
+    }
+
+    #[test]
+    fn last_override_wins() {
+        let args = [
+            "services.postgres.enable:bool",
+            "false",
+            "languages.python.version:string",
+            "3.11",
+            "services.postgres.enable:bool",
+            "true",
+        ]
+        .map(String::from);
+        let overrides = parse_overrides(&args).unwrap();
+        assert_eq!(
+            render_module(&overrides, "./.."),
+            indoc::indoc! {r#"
+                { pkgs, lib, config, ... }: {
+                  languages.python.version = lib.mkForce "3.11";
+                  services.postgres.enable = lib.mkForce true;
+                }
+            "#}
+        );
+    }
+
+    #[test]
+    fn validates_against_declarations() {
+        let declarations = BTreeMap::from_iter(
+            [
+                ("services.postgres.enable", "boolean"),
+                ("languages.python.version", "null or string"),
+                ("processes", "attribute set of (submodule)"),
+                ("processes.<name>.exec", "string"),
+                ("env", "lazy attribute set of anything"),
+                ("packages", "list of package"),
+            ]
+            .map(|(name, type_)| (name.to_string(), type_.to_string())),
+        );
+        let check =
+            |option: &str, value: &str| validate(&[parse(option, value).unwrap()], &declarations);
+
+        assert!(check("services.postgres.enable:bool", "true").is_ok());
+        assert!(check("languages.python.version:json", "null").is_ok());
+        assert!(check("processes.web.exec:string", "serve").is_ok());
+        assert!(check("env.FOO:string", "bar").is_ok());
+        assert!(check("packages:pkgs", "git").is_ok());
+
+        let err = check("services.postgres.enable:string", "true").unwrap_err();
+        assert!(err.to_string().contains("has type `boolean`"));
+        let err = check("services.postgres.enabel:bool", "true").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Did you mean services.postgres.enable?"));
+        assert!(check("processes.web.exce:string", "serve").is_err());
+        assert!(check("packages:pkg", "git").is_err());
+        assert!(check("env:string", "FOO=bar").is_err());
+    }
+}
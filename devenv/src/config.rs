@@ -1,10 +1,18 @@
+use crate::yaml_edit::YamlDocument;
+use crate::{cli_options, util};
 use miette::{IntoDiagnostic, Result, WrapErr};
 use schemars::{schema_for, JsonSchema};
 use schematic::ConfigLoader;
 use serde::{Deserialize, Serialize};
-use std::{collections::BTreeMap, fmt, path::Path};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
 
-const YAML_CONFIG: &str = "devenv.yaml";
+pub const YAML_CONFIG: &str = "devenv.yaml";
+pub const YAML_LOCAL_CONFIG: &str = "devenv.local.yaml";
+const USER_CONFIG: &str = "config.yaml";
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema, schematic::Schematic)]
 #[serde(rename_all = "lowercase")]
@@ -40,6 +48,7 @@ pub struct Input {
     pub url: Option<String>,
     #[serde(skip_serializing_if = "is_true", default = "true_default")]
     #[setting(default = true)]
+    #[schemars(schema_with = "flake_schema")]
     pub flake: bool,
     #[serde(skip_serializing_if = "Option::is_none", default)]
     pub follows: Option<String>,
@@ -49,6 +58,13 @@ pub struct Input {
     pub overlays: Vec<String>,
 }
 
+// schemars leaves out defaults that are skipped when serializing, so add the default back in.
+fn flake_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+    let mut schema = gen.subschema_for::<bool>().into_object();
+    schema.metadata().default = Some(serde_json::Value::Bool(true));
+    schema.into()
+}
+
 #[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct FlakeInput {
     #[serde(skip_serializing_if = "Option::is_none", default)]
@@ -133,6 +149,19 @@ pub struct Nixpkgs {
     pub per_platform: BTreeMap<String, NixpkgsConfig>,
 }
 
+/// A named variant of the environment, selected with `devenv --profile <name>`.
+#[derive(schematic::Config, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[config(rename_all = "camelCase")]
+#[serde(rename_all = "camelCase")]
+pub struct Profile {
+    /// Extra imports, added after the top-level imports.
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub imports: Vec<String>,
+    /// Option overrides in the same `<attribute>:<type>` form as `--option`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub options: BTreeMap<String, String>,
+}
+
 #[derive(schematic::Config, Clone, Serialize, Debug, JsonSchema)]
 #[config(rename_all = "camelCase", allow_unknown_fields)]
 #[serde(rename_all = "camelCase")]
@@ -158,6 +187,11 @@ pub struct Config {
     pub impure: bool,
     #[serde(default, skip_serializing_if = "is_default")]
     pub backend: NixBackendType,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub options: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    #[setting(nested)]
+    pub profiles: BTreeMap<String, Profile>,
 }
 
 // TODO: https://github.com/moonrepo/schematic/issues/105
@@ -179,28 +213,107 @@ impl Config {
         Self::load_from("./")
     }
 
+    /// Load the config for the project in the given directory, merging in all of the layers
+    /// returned by [config_paths].
     pub fn load_from<P>(path: P) -> Result<Self>
     where
         P: AsRef<Path>,
     {
-        let file = path.as_ref().join(YAML_CONFIG);
+        Self::load_from_files(&config_paths(path))
+    }
+
+    /// Load the config from a list of files. Later files take precedence.
+    pub fn load_from_files(files: &[PathBuf]) -> Result<Self> {
+        let merged = merge_layers(&read_layers(files)?);
         let mut loader = ConfigLoader::<Config>::new();
-        let _ = loader.file_optional(file);
+        if merged
+            .as_mapping()
+            .is_some_and(|mapping| !mapping.is_empty())
+        {
+            let yaml = serde_yaml::to_string(&merged)
+                .into_diagnostic()
+                .wrap_err("Failed to serialize config to YAML")?;
+            loader
+                .code(yaml, schematic::Format::Yaml)
+                .into_diagnostic()?;
+        }
         let result = loader.load().into_diagnostic();
         Ok(result?.config)
     }
 
     pub async fn write(&self) -> Result<()> {
-        let yaml = serde_yaml::to_string(&self)
+        self.write_to("./").await
+    }
+
+    /// Write the config to devenv.yaml in the given directory.
+    ///
+    /// Only the values that differ from the file are rewritten,
+    /// so comments, formatting and unknown fields are preserved.
+    pub async fn write_to<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let file = path.as_ref().join(YAML_CONFIG);
+        let contents = match tokio::fs::read_to_string(&file).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e)
+                    .into_diagnostic()
+                    .wrap_err("Failed to read devenv.yaml")
+            }
+        };
+
+        // Compare against the config as loaded from disk, so that defaults, unknown fields and
+        // values from the other layers don't show up as changes. This holds even when devenv.yaml
+        // is missing, since the user config and devenv.local.yaml are still merged in.
+        let old = serde_yaml::to_value(Self::load_from(path.as_ref())?)
             .into_diagnostic()
             .wrap_err("Failed to serialize config to YAML")?;
-        tokio::fs::write(YAML_CONFIG, yaml)
+        let new = serde_yaml::to_value(self)
+            .into_diagnostic()
+            .wrap_err("Failed to serialize config to YAML")?;
+
+        let mut document = YamlDocument::parse(&contents);
+        document.apply_diff(&old, &new)?;
+
+        tokio::fs::write(&file, document.to_string())
             .await
             .into_diagnostic()
             .wrap_err("Failed to write devenv.yaml")?;
         Ok(())
     }
 
+    /// Get the effective value of a config key, like `allowUnfree` or `inputs.nixpkgs.url`.
+    ///
+    /// Returns `None` if the key is valid but not set and has no default,
+    /// like the URL of an input or an entry that doesn't exist.
+    pub fn get(&self, key: &str) -> Result<Option<serde_yaml::Value>> {
+        let path = key_path(key)?;
+        let schema = config_schema();
+        let key_schema = find_key(&schema, &path)?;
+        let value = serde_yaml::to_value(self)
+            .into_diagnostic()
+            .wrap_err("Failed to serialize config to YAML")?;
+        if let Some(value) = get_in_yaml(&value, &path) {
+            return Ok(Some(value.clone()));
+        }
+
+        // Values that are equal to their defaults aren't serialized, but a missing input or
+        // profile doesn't have defaults.
+        for depth in 0..path.len() {
+            let parent = resolve_schema(&schema, find_key(&schema, &path[..depth])?);
+            let is_entry = parent
+                .get("properties")
+                .and_then(|properties| properties.get(path[depth]))
+                .is_none();
+            if is_entry && get_in_yaml(&value, &path[..=depth]).is_none() {
+                return Ok(None);
+            }
+        }
+        Ok(default_value(resolve_schema(&schema, key_schema)))
+    }
+
     /// Add a new input, overwriting any existing input with the same name.
     pub fn add_input(&mut self, name: &str, url: &str, follows: &[String]) -> Result<()> {
         // A set of inputs built from the follows list.
@@ -244,6 +357,583 @@ impl Config {
             ))
         }
     }
+
+    /// Check that a profile from devenv.yaml can be used.
+    ///
+    /// Returns the profile's option overrides as `--option` arguments.
+    pub fn profile_options(&self, name: &str) -> Result<Vec<String>> {
+        let Some(profile) = self.profiles.get(name) else {
+            if self.profiles.is_empty() {
+                miette::bail!(
+                    "Profile {name} does not exist. Define profiles under `profiles` in devenv.yaml."
+                );
+            }
+            miette::bail!(
+                "Profile {name} does not exist. Available profiles: {}",
+                self.profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+            );
+        };
+        if name.contains('/') || name == "." || name == ".." {
+            miette::bail!("Profile name {name} can't be used as a directory name.");
+        }
+        Ok(option_args(&profile.options))
+    }
+
+    /// A copy of the config with the imports of a profile added after the top-level imports.
+    ///
+    /// The copy is only meant for evaluation. It must not be written back,
+    /// or the profile's imports would end up in devenv.yaml.
+    pub fn with_profile(&self, name: Option<&str>) -> Config {
+        let mut config = self.clone();
+        if let Some(profile) = name.and_then(|name| self.profiles.get(name)) {
+            for import in &profile.imports {
+                if !config.imports.contains(import) {
+                    config.imports.push(import.clone());
+                }
+            }
+        }
+        config
+    }
+
+    /// The option overrides from `options` in the config files, as `--option` arguments.
+    pub fn option_args(&self) -> Vec<String> {
+        option_args(&self.options)
+    }
+
+    /// The imports that reach outside of the project with `../`,
+    /// along with the module file that each of them loads.
+    pub fn parent_imports(&self, root: &Path) -> Vec<(&str, PathBuf)> {
+        self.imports
+            .iter()
+            .filter(|import| import.starts_with("../"))
+            .map(|import| {
+                let path = root.join(import);
+                let module = if import.ends_with(".nix") {
+                    path
+                } else {
+                    path.join("devenv.nix")
+                };
+                (import.as_str(), module)
+            })
+            .collect()
+    }
+
+    /// Make a nested input of an existing input follow a top-level input.
+    ///
+    /// Returns `false` if the nested input already follows something or has its own URL,
+    /// in which case the user's choice is left alone.
+    pub fn add_follows(&mut self, name: &str, nested: &str, follows: &str) -> Result<bool> {
+        let Some(input) = self.inputs.get_mut(name) else {
+            return Err(miette::miette!(
+                "Input {name} does not exist in devenv.yaml."
+            ));
+        };
+        if let Some(existing) = input.inputs.get(nested) {
+            if existing.url.is_some() || existing.follows.is_some() {
+                return Ok(false);
+            }
+        }
+        input.inputs.insert(
+            nested.to_string(),
+            Input {
+                follows: Some(follows.to_string()),
+                ..Default::default()
+            },
+        );
+        Ok(true)
+    }
+
+    /// Remove an input and any nested `follows` that point to it.
+    ///
+    /// Returns the dotted paths of the nested inputs that were removed.
+    pub fn remove_input(&mut self, name: &str) -> Result<Vec<String>> {
+        if !self.inputs.contains_key(name) {
+            return Err(miette::miette!(
+                "Input {name} does not exist so it can't be removed."
+            ));
+        }
+
+        let aliases = self
+            .inputs
+            .iter()
+            .filter(|(_, input)| {
+                input
+                    .follows
+                    .as_deref()
+                    .is_some_and(|f| follows_input(f, name))
+            })
+            .map(|(alias, _)| alias.as_str())
+            .collect::<Vec<_>>();
+        if !aliases.is_empty() {
+            return Err(miette::miette!(
+                "Input {name} can't be removed because {} follow it. Remove them first.",
+                aliases.join(", ")
+            ));
+        }
+
+        self.inputs.remove(name);
+
+        fn remove_follows(prefix: &str, input: &mut Input, name: &str, removed: &mut Vec<String>) {
+            input.inputs.retain(|nested_name, nested| {
+                let path = format!("{prefix}.inputs.{nested_name}");
+                if nested
+                    .follows
+                    .as_deref()
+                    .is_some_and(|f| follows_input(f, name))
+                {
+                    removed.push(path);
+                    false
+                } else {
+                    remove_follows(&path, nested, name, removed);
+                    true
+                }
+            });
+        }
+
+        let mut removed = Vec::new();
+        for (input_name, input) in self.inputs.iter_mut() {
+            remove_follows(input_name, input, name, &mut removed);
+        }
+        Ok(removed)
+    }
+}
+
+/// The user-level config with defaults for all projects, e.g. `~/.config/devenv/config.yaml`.
+pub fn user_config_path() -> Option<PathBuf> {
+    xdg::BaseDirectories::with_prefix("devenv")
+        .ok()
+        .map(|dirs| dirs.get_config_file(USER_CONFIG))
+}
+
+/// The config files of a project in the order they're merged in:
+/// the user-level config, devenv.yaml, and the uncommitted devenv.local.yaml.
+pub fn config_paths<P>(path: P) -> Vec<PathBuf>
+where
+    P: AsRef<Path>,
+{
+    user_config_path()
+        .into_iter()
+        .chain([
+            path.as_ref().join(YAML_CONFIG),
+            path.as_ref().join(YAML_LOCAL_CONFIG),
+        ])
+        .collect()
+}
+
+/// Read the config files that exist, in order.
+pub fn read_layers(files: &[PathBuf]) -> Result<Vec<(PathBuf, serde_yaml::Value)>> {
+    let mut layers = Vec::new();
+    for file in files {
+        let contents = match std::fs::read_to_string(file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                return Err(e)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to read {}", file.display()))
+            }
+        };
+        let value: serde_yaml::Value = serde_yaml::from_str(&contents)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to parse {}", file.display()))?;
+        match value {
+            serde_yaml::Value::Null => {}
+            serde_yaml::Value::Mapping(_) => layers.push((file.clone(), value)),
+            _ => miette::bail!("{} must contain a mapping", file.display()),
+        }
+    }
+    Ok(layers)
+}
+
+/// Merge config layers. Mappings are merged key by key, anything else is replaced.
+pub fn merge_layers(layers: &[(PathBuf, serde_yaml::Value)]) -> serde_yaml::Value {
+    fn merge(base: &mut serde_yaml::Value, layer: &serde_yaml::Value) {
+        match (base, layer) {
+            (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(layer)) => {
+                for (key, value) in layer {
+                    match base.get_mut(key) {
+                        Some(existing) => merge(existing, value),
+                        None => {
+                            base.insert(key.clone(), value.clone());
+                        }
+                    }
+                }
+            }
+            (base, layer) => *base = layer.clone(),
+        }
+    }
+
+    let mut merged = serde_yaml::Value::Mapping(Default::default());
+    for (_, layer) in layers {
+        merge(&mut merged, layer);
+    }
+    merged
+}
+
+/// The file that set each value, keyed by the dotted path of the value.
+pub fn value_origins(layers: &[(PathBuf, serde_yaml::Value)]) -> BTreeMap<String, PathBuf> {
+    let mut origins = BTreeMap::new();
+    for (file, layer) in layers {
+        for (path, _) in flatten_value(layer) {
+            origins.insert(path, file.clone());
+        }
+    }
+    origins
+}
+
+/// Flatten nested mappings into `(dotted.path, value)` pairs.
+/// Sequences and empty mappings are kept as single values.
+pub fn flatten_value(value: &serde_yaml::Value) -> Vec<(String, serde_yaml::Value)> {
+    fn flatten(
+        prefix: &str,
+        value: &serde_yaml::Value,
+        out: &mut Vec<(String, serde_yaml::Value)>,
+    ) {
+        match value {
+            serde_yaml::Value::Mapping(mapping) if !mapping.is_empty() => {
+                for (key, value) in mapping {
+                    let Some(key) = key.as_str() else {
+                        continue;
+                    };
+                    let path = if prefix.is_empty() {
+                        key.to_string()
+                    } else {
+                        format!("{prefix}.{key}")
+                    };
+                    flatten(&path, value, out);
+                }
+            }
+            _ if !prefix.is_empty() => out.push((prefix.to_string(), value.clone())),
+            _ => {}
+        }
+    }
+
+    let mut out = Vec::new();
+    flatten("", value, &mut out);
+    out
+}
+
+/// Set a config key, like `allowUnfree` or `inputs.nixpkgs.url`, in a config file.
+///
+/// The value is parsed as YAML and checked against the JSON schema of [Config].
+pub fn set_in_file(file: &Path, key: &str, value: &str) -> Result<()> {
+    let schema = config_schema();
+    let path = key_path(key)?;
+    let key_schema = find_key(&schema, &path)?;
+    let value = parse_value(key, value, key_schema)?;
+
+    let mut document = YamlDocument::parse(&read_config_file(file)?);
+    document.set(&path, &value)?;
+    let contents = document.to_string();
+    validate(&contents).wrap_err_with(|| format!("Failed to set {key}"))?;
+
+    if let Some(parent) = file.parent() {
+        std::fs::create_dir_all(parent)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to create {}", parent.display()))?;
+    }
+    std::fs::write(file, contents)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to write {}", file.display()))
+}
+
+/// Remove a config key from a config file.
+///
+/// Returns `false` if the file didn't set the key.
+pub fn unset_in_file(file: &Path, key: &str) -> Result<bool> {
+    let path = key_path(key)?;
+    find_key(&config_schema(), &path)?;
+
+    let mut document = YamlDocument::parse(&read_config_file(file)?);
+    if get_in_yaml(&document.value()?, &path).is_none() {
+        return Ok(false);
+    }
+    document.remove(&path)?;
+    std::fs::write(file, document.to_string())
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to write {}", file.display()))?;
+    Ok(true)
+}
+
+/// Save `--option` overrides to the `options` of a config file.
+pub fn persist_options(file: &Path, args: &[String]) -> Result<()> {
+    cli_options::parse_overrides(args)?;
+
+    let mut document = YamlDocument::parse(&read_config_file(file)?);
+    for chunk in args.chunks_exact(2) {
+        document.set(
+            &["options", &chunk[0]],
+            &serde_yaml::Value::String(chunk[1].clone()),
+        )?;
+    }
+    std::fs::write(file, document.to_string())
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to write {}", file.display()))
+}
+
+fn option_args(options: &BTreeMap<String, String>) -> Vec<String> {
+    options
+        .iter()
+        .flat_map(|(option, value)| [option.clone(), value.clone()])
+        .collect()
+}
+
+fn read_config_file(file: &Path) -> Result<String> {
+    match std::fs::read_to_string(file) {
+        Ok(contents) => Ok(contents),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read {}", file.display())),
+    }
+}
+
+/// Check that a config file can be loaded.
+fn validate(contents: &str) -> Result<()> {
+    let mut loader = ConfigLoader::<Config>::new();
+    loader
+        .code(contents.to_string(), schematic::Format::Yaml)
+        .into_diagnostic()?;
+    loader.load().into_diagnostic()?;
+    Ok(())
+}
+
+fn get_in_yaml<'a>(value: &'a serde_yaml::Value, path: &[&str]) -> Option<&'a serde_yaml::Value> {
+    path.iter().try_fold(value, |value, key| value.get(key))
+}
+
+fn config_schema() -> serde_json::Value {
+    serde_json::to_value(schema_for!(Config)).expect("Failed to serialize JSON schema")
+}
+
+/// Split a key into its segments.
+///
+/// Segments that contain dots, like the attributes under `options`, can be quoted:
+/// `options."languages.rust.enable:bool"`.
+fn key_path(key: &str) -> Result<Vec<&str>> {
+    let invalid = || {
+        miette::miette!(
+            "Invalid key `{key}`. Keys are dot-separated, like `inputs.nixpkgs.url`. Quote segments that contain dots, like `options.\"languages.rust.enable:bool\"`."
+        )
+    };
+    let mut path = Vec::new();
+    let mut rest = key;
+    loop {
+        let (segment, after) = match rest.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').ok_or_else(invalid)?;
+                (&quoted[..end], &quoted[end + 1..])
+            }
+            None => rest.split_at(rest.find('.').unwrap_or(rest.len())),
+        };
+        if segment.is_empty() {
+            return Err(invalid());
+        }
+        path.push(segment);
+        if after.is_empty() {
+            return Ok(path);
+        }
+        rest = after.strip_prefix('.').ok_or_else(invalid)?;
+    }
+}
+
+/// The value of a key that isn't set: its schema default,
+/// or the default of its type for the fields that serde leaves out.
+fn default_value(schema: &serde_json::Value) -> Option<serde_yaml::Value> {
+    if let Some(default) = schema.get("default") {
+        return serde_yaml::to_value(default).ok();
+    }
+    match schema.get("type").and_then(|t| t.as_str())? {
+        "boolean" => Some(serde_yaml::Value::Bool(false)),
+        "array" => Some(serde_yaml::Value::Sequence(Default::default())),
+        "object" => Some(serde_yaml::Value::Mapping(Default::default())),
+        "string" => schema
+            .get("enum")
+            .and_then(|variants| variants.get(0))
+            .and_then(|variant| serde_yaml::to_value(variant).ok()),
+        _ => None,
+    }
+}
+
+/// Find the schema of the value at a key path.
+fn find_key<'a>(root: &'a serde_json::Value, path: &[&str]) -> Result<&'a serde_json::Value> {
+    let mut field = root;
+    for (depth, segment) in path.iter().enumerate() {
+        let schema = resolve_schema(root, field);
+        if let Some(next) = schema
+            .get("properties")
+            .and_then(|properties| properties.get(segment))
+            .or_else(|| schema.get("additionalProperties").filter(|s| s.is_object()))
+        {
+            field = next;
+            continue;
+        }
+
+        let key = path.join(".");
+        let parent = &path[..depth];
+        let known = schema
+            .get("properties")
+            .and_then(|properties| properties.as_object())
+            .map(|properties| properties.keys().map(String::as_str).collect::<Vec<_>>())
+            .unwrap_or_default();
+        if known.is_empty() {
+            miette::bail!(
+                "Unknown key `{key}`: `{}` doesn't have any nested keys.",
+                parent.join(".")
+            );
+        }
+        let prefix = parent.iter().map(|p| format!("{p}.")).collect::<String>();
+        let hint = util::closest_match(segment, &known)
+            .map(|k| format!(" Did you mean `{prefix}{k}`?"))
+            .unwrap_or_default();
+        let keys = known
+            .iter()
+            .map(|k| format!("{prefix}{k}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        miette::bail!("Unknown key `{key}`.{hint}\nValid keys are: {keys}");
+    }
+    Ok(resolve_schema(root, field))
+}
+
+/// Follow `$ref`s and unwrap optional values, i.e. `anyOf` with `null`.
+fn resolve_schema<'a>(
+    root: &'a serde_json::Value,
+    schema: &'a serde_json::Value,
+) -> &'a serde_json::Value {
+    if let Some(reference) = schema.get("$ref").and_then(|r| r.as_str()) {
+        let pointer = reference.trim_start_matches('#');
+        if let Some(definition) = root.pointer(pointer) {
+            return resolve_schema(root, definition);
+        }
+    }
+    for combinator in ["anyOf", "oneOf", "allOf"] {
+        let Some(schemas) = schema.get(combinator).and_then(|s| s.as_array()) else {
+            continue;
+        };
+        let mut non_null = schemas
+            .iter()
+            .filter(|s| s.get("type").and_then(|t| t.as_str()) != Some("null"));
+        if let (Some(only), None) = (non_null.next(), non_null.next()) {
+            return resolve_schema(root, only);
+        }
+    }
+    schema
+}
+
+/// The JSON types a schema accepts, apart from `null`.
+fn schema_types(schema: &serde_json::Value) -> Vec<&str> {
+    match schema.get("type") {
+        Some(serde_json::Value::String(t)) => vec![t.as_str()],
+        Some(serde_json::Value::Array(types)) => types.iter().filter_map(|t| t.as_str()).collect(),
+        _ => Vec::new(),
+    }
+    .into_iter()
+    .filter(|t| *t != "null")
+    .collect()
+}
+
+fn matches_type(t: &str, value: &serde_yaml::Value) -> bool {
+    match (t, value) {
+        ("boolean", serde_yaml::Value::Bool(_)) => true,
+        ("string", serde_yaml::Value::String(_)) => true,
+        ("integer", serde_yaml::Value::Number(n)) => n.is_i64() || n.is_u64(),
+        ("number", serde_yaml::Value::Number(_)) => true,
+        ("array", serde_yaml::Value::Sequence(_)) => true,
+        ("object", serde_yaml::Value::Mapping(_)) => true,
+        _ => false,
+    }
+}
+
+fn describe_type(t: &str) -> &str {
+    match t {
+        "boolean" => "a boolean (true or false)",
+        "string" => "a string",
+        "integer" => "an integer",
+        "number" => "a number",
+        "array" => "a list, like [a, b]",
+        "object" => "a mapping, like {key: value}",
+        t => t,
+    }
+}
+
+/// Parse a value given on the command line as YAML and check it against the schema of the key.
+///
+/// Scalars are accepted as strings where a string is expected, so `1.0` or `true` don't need quoting.
+fn parse_value(key: &str, raw: &str, schema: &serde_json::Value) -> Result<serde_yaml::Value> {
+    if let Some(variants) = schema.get("enum").and_then(|e| e.as_array()) {
+        if variants.iter().any(|v| v.as_str() == Some(raw)) {
+            return Ok(serde_yaml::Value::String(raw.to_string()));
+        }
+        let variants = variants
+            .iter()
+            .map(|v| v.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        miette::bail!("Invalid value `{raw}` for `{key}`. Expected one of: {variants}");
+    }
+
+    let types = schema_types(schema);
+    let value = serde_yaml::from_str::<serde_yaml::Value>(raw)
+        .unwrap_or_else(|_| serde_yaml::Value::String(raw.to_string()));
+    if value.is_null() {
+        miette::bail!(
+            "Invalid value `{raw}` for `{key}`. Use `devenv config unset {key}` to remove it."
+        );
+    }
+
+    if types.contains(&"string")
+        && !matches!(
+            value,
+            serde_yaml::Value::Sequence(_) | serde_yaml::Value::Mapping(_)
+        )
+    {
+        return Ok(serde_yaml::Value::String(raw.to_string()));
+    }
+
+    if !types.is_empty() && !types.iter().any(|t| matches_type(t, &value)) {
+        let expected = types
+            .iter()
+            .map(|t| describe_type(t))
+            .collect::<Vec<_>>()
+            .join(" or ");
+        miette::bail!("Invalid value `{raw}` for `{key}`. Expected {expected}.");
+    }
+
+    match value {
+        serde_yaml::Value::Sequence(items) => {
+            let item_schema = schema.get("items").unwrap_or(&serde_json::Value::Null);
+            let item_types = schema_types(item_schema);
+            let items = items
+                .into_iter()
+                .map(|item| {
+                    if item_types.contains(&"string") && !item.is_sequence() && !item.is_mapping() {
+                        let item = serde_yaml::to_string(&item).into_diagnostic()?;
+                        return Ok(serde_yaml::Value::String(item.trim_end().to_string()));
+                    }
+                    if !item_types.is_empty() && !item_types.iter().any(|t| matches_type(t, &item))
+                    {
+                        let expected = item_types
+                            .iter()
+                            .map(|t| describe_type(t))
+                            .collect::<Vec<_>>()
+                            .join(" or ");
+                        miette::bail!(
+                            "Invalid value `{raw}` for `{key}`. Expected a list of {expected}."
+                        );
+                    }
+                    Ok(item)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(serde_yaml::Value::Sequence(items))
+        }
+        value => Ok(value),
+    }
+}
+
+/// Whether a `follows` path such as `nixpkgs` or `git-hooks/nixpkgs` starts at the given input.
+fn follows_input(follows: &str, name: &str) -> bool {
+    follows.split('/').next() == Some(name)
 }
 
 #[cfg(test)]
@@ -348,6 +1038,503 @@ mod tests {
         );
     }
 
+    #[test]
+    fn remove_input_with_follows() {
+        let mut config = Config::default();
+        config
+            .add_input("other", "github:org/other", &[])
+            .expect("Failed to add input");
+        config
+            .add_input(
+                "input-with-follows",
+                "github:org/repo",
+                &["nixpkgs".to_string(), "other".to_string()],
+            )
+            .expect("Failed to add input with follows");
+
+        let removed = config
+            .remove_input("other")
+            .expect("Failed to remove input");
+        assert_eq!(removed, vec!["input-with-follows.inputs.other".to_string()]);
+        assert!(!config.inputs.contains_key("other"));
+        let input = &config.inputs["input-with-follows"];
+        assert_eq!(input.inputs.len(), 1);
+        assert!(input.inputs.contains_key("nixpkgs"));
+    }
+
+    #[test]
+    fn remove_input_followed_by_top_level_input() {
+        let mut config = Config::default();
+        config
+            .add_input("nixpkgs", "github:NixOS/nixpkgs/nixpkgs-unstable", &[])
+            .expect("Failed to add input");
+        config.inputs.insert(
+            "pkgs".to_string(),
+            Input {
+                follows: Some("nixpkgs".to_string()),
+                ..Default::default()
+            },
+        );
+        assert!(config.remove_input("nixpkgs").is_err());
+        assert!(config.remove_input("missing").is_err());
+        assert_eq!(config.inputs.len(), 2);
+    }
+
+    #[test]
+    fn add_follows() {
+        let mut config = Config::default();
+        config
+            .add_input("nixpkgs", "github:NixOS/nixpkgs/nixpkgs-unstable", &[])
+            .expect("Failed to add input");
+        config
+            .add_input("other", "github:org/repo", &[])
+            .expect("Failed to add input");
+
+        assert!(config
+            .add_follows("other", "nixpkgs", "nixpkgs")
+            .expect("Failed to add follows"));
+        assert_eq!(
+            config.inputs["other"].inputs["nixpkgs"].follows,
+            Some("nixpkgs".to_string())
+        );
+
+        // Existing follows are left alone.
+        assert!(!config
+            .add_follows("other", "nixpkgs", "something-else")
+            .expect("Failed to add follows"));
+        assert_eq!(
+            config.inputs["other"].inputs["nixpkgs"].follows,
+            Some("nixpkgs".to_string())
+        );
+
+        assert!(config.add_follows("missing", "nixpkgs", "nixpkgs").is_err());
+    }
+
+    #[test]
+    fn profiles() {
+        let config = Config {
+            imports: vec!["./backend".to_string()],
+            profiles: BTreeMap::from_iter([(
+                "ci".to_string(),
+                Profile {
+                    imports: vec!["./backend".to_string(), "./ci".to_string()],
+                    options: BTreeMap::from_iter([(
+                        "services.postgres.enable:bool".to_string(),
+                        "false".to_string(),
+                    )]),
+                },
+            )]),
+            ..Default::default()
+        };
+
+        let err = config.profile_options("gpu").unwrap_err();
+        assert!(err.to_string().contains("Available profiles: ci"));
+
+        let options = config.profile_options("ci").expect("Failed to use profile");
+        assert_eq!(options, vec!["services.postgres.enable:bool", "false"]);
+        assert_eq!(
+            config.with_profile(Some("ci")).imports,
+            vec!["./backend", "./ci"]
+        );
+        assert_eq!(config.with_profile(None).imports, vec!["./backend"]);
+        assert_eq!(config.imports, vec!["./backend"]);
+    }
+
+    #[test]
+    fn parent_imports() {
+        let config = Config {
+            imports: vec![
+                "./backend".to_string(),
+                "../common".to_string(),
+                "../lib/postgres.nix".to_string(),
+                "devenv/examples/scripts".to_string(),
+            ],
+            ..Default::default()
+        };
+
+        let root = Path::new("/repo/services/api");
+        assert_eq!(
+            config.parent_imports(root),
+            vec![
+                ("../common", root.join("../common/devenv.nix")),
+                ("../lib/postgres.nix", root.join("../lib/postgres.nix")),
+            ]
+        );
+    }
+
+    #[test]
+    fn persist_options() {
+        let dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let file = dir.path().join(YAML_LOCAL_CONFIG);
+        std::fs::write(
+            &file,
+            "options:\n  languages.python.version:string: \"3.10\"\n",
+        )
+        .unwrap();
+
+        let args = [
+            "languages.python.version:string",
+            "3.12",
+            "services.postgres.enable:bool",
+            "true",
+        ]
+        .map(String::from);
+        super::persist_options(&file, &args).expect("Failed to persist options");
+
+        let config = Config::load_from_files(&[file.clone()]).expect("Failed to load config");
+        assert_eq!(config.option_args(), args.to_vec());
+
+        let invalid = ["services.postgres.enable".to_string(), "true".to_string()];
+        assert!(super::persist_options(&file, &invalid).is_err());
+    }
+
+    #[test]
+    fn load_merges_layers_in_order() {
+        let dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let user = dir.path().join("user.yaml");
+        let project = dir.path().join(YAML_CONFIG);
+        let local = dir.path().join(YAML_LOCAL_CONFIG);
+        std::fs::write(&user, "allowUnfree: true\nimpure: true\n").unwrap();
+        std::fs::write(
+            &project,
+            indoc::indoc! {"
+                impure: false
+                inputs:
+                  nixpkgs:
+                    url: github:cachix/devenv-nixpkgs/rolling
+                  other:
+                    url: github:org/other
+            "},
+        )
+        .unwrap();
+        std::fs::write(&local, "inputs:\n  other:\n    url: path:../other\n").unwrap();
+
+        let files = vec![user.clone(), project.clone(), local.clone()];
+        let config = Config::load_from_files(&files).expect("Failed to load config");
+        assert!(config.allow_unfree);
+        assert!(!config.impure);
+        assert_eq!(
+            config.inputs["nixpkgs"].url,
+            Some("github:cachix/devenv-nixpkgs/rolling".to_string())
+        );
+        assert_eq!(
+            config.inputs["other"].url,
+            Some("path:../other".to_string())
+        );
+
+        let origins = value_origins(&read_layers(&files).unwrap());
+        assert_eq!(origins["allowUnfree"], user);
+        assert_eq!(origins["impure"], project);
+        assert_eq!(origins["inputs.nixpkgs.url"], project);
+        assert_eq!(origins["inputs.other.url"], local);
+    }
+
+    #[test]
+    fn load_without_files() {
+        let dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let config = Config::load_from_files(&[dir.path().join(YAML_CONFIG)])
+            .expect("Failed to load config");
+        assert!(config.inputs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn write_preserves_comments_and_unknown_fields() {
+        let dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let before = indoc::indoc! {r#"
+            # yaml-language-server: $schema=https://devenv.sh/devenv.schema.json
+            inputs:
+              nixpkgs:
+                url: github:cachix/devenv-nixpkgs/rolling
+              other:
+                url: github:org/other
+
+            # If you're using non-OSS software, you can set allowUnfree to true.
+            allowUnfree: true
+            futureOption: 42
+        "#};
+        std::fs::write(dir.path().join(YAML_CONFIG), before).expect("Failed to write config");
+
+        let mut config = Config::load_from(dir.path()).expect("Failed to load config");
+        config
+            .remove_input("other")
+            .expect("Failed to remove input");
+        config
+            .add_input(
+                "rust-overlay",
+                "github:oxalica/rust-overlay",
+                &["nixpkgs".to_string()],
+            )
+            .expect("Failed to add input");
+        config
+            .write_to(dir.path())
+            .await
+            .expect("Failed to write config");
+
+        let after =
+            std::fs::read_to_string(dir.path().join(YAML_CONFIG)).expect("Failed to read config");
+        assert_eq!(
+            after,
+            indoc::indoc! {r#"
+                # yaml-language-server: $schema=https://devenv.sh/devenv.schema.json
+                inputs:
+                  nixpkgs:
+                    url: github:cachix/devenv-nixpkgs/rolling
+                  rust-overlay:
+                    url: github:oxalica/rust-overlay
+
+                # If you're using non-OSS software, you can set allowUnfree to true.
+                allowUnfree: true
+                futureOption: 42
+            "#}
+        );
+    }
+
+    #[tokio::test]
+    async fn write_creates_missing_file() {
+        let dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let mut config = Config::default();
+        config
+            .add_input("nixpkgs", "github:cachix/devenv-nixpkgs/rolling", &[])
+            .expect("Failed to add input");
+        config
+            .write_to(dir.path())
+            .await
+            .expect("Failed to write config");
+
+        let after =
+            std::fs::read_to_string(dir.path().join(YAML_CONFIG)).expect("Failed to read config");
+        assert_eq!(
+            after,
+            "inputs:\n  nixpkgs:\n    url: github:cachix/devenv-nixpkgs/rolling\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn write_only_the_changes_without_devenv_yaml() {
+        let dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        std::fs::write(
+            dir.path().join(YAML_LOCAL_CONFIG),
+            indoc::indoc! {"
+                allowUnfree: true
+                inputs:
+                  nixpkgs:
+                    url: github:NixOS/nixpkgs/nixpkgs-unstable
+            "},
+        )
+        .expect("Failed to write local config");
+
+        let mut config = Config::load_from(dir.path()).expect("Failed to load config");
+        config
+            .add_input("rust-overlay", "github:oxalica/rust-overlay", &[])
+            .expect("Failed to add input");
+        config
+            .write_to(dir.path())
+            .await
+            .expect("Failed to write config");
+
+        let after =
+            std::fs::read_to_string(dir.path().join(YAML_CONFIG)).expect("Failed to read config");
+        assert_eq!(
+            after,
+            indoc::indoc! {"
+                inputs:
+                  rust-overlay:
+                    url: github:oxalica/rust-overlay
+            "}
+        );
+    }
+
+    #[tokio::test]
+    async fn write_leaves_out_profile_imports() {
+        let dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let contents = indoc::indoc! {"
+            imports:
+              - ./backend
+            profiles:
+              ci:
+                imports:
+                  - ./ci
+        "};
+        std::fs::write(dir.path().join(YAML_CONFIG), contents).expect("Failed to write config");
+
+        let mut config = Config::load_from(dir.path()).expect("Failed to load config");
+        assert_eq!(
+            config.with_profile(Some("ci")).imports,
+            vec!["./backend", "./ci"]
+        );
+        config
+            .add_input("nixpkgs", "github:cachix/devenv-nixpkgs/rolling", &[])
+            .expect("Failed to add input");
+        config
+            .write_to(dir.path())
+            .await
+            .expect("Failed to write config");
+
+        let after =
+            std::fs::read_to_string(dir.path().join(YAML_CONFIG)).expect("Failed to read config");
+        assert_eq!(
+            after,
+            format!(
+                "{contents}inputs:\n  nixpkgs:\n    url: github:cachix/devenv-nixpkgs/rolling\n"
+            )
+        );
+    }
+
+    #[test]
+    fn set_and_unset_keys_in_file() {
+        let dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let file = dir.path().join(YAML_CONFIG);
+        std::fs::write(
+            &file,
+            indoc::indoc! {"
+                # Pinned inputs
+                inputs:
+                  nixpkgs:
+                    url: github:cachix/devenv-nixpkgs/rolling
+            "},
+        )
+        .unwrap();
+
+        set_in_file(&file, "allowUnfree", "true").expect("Failed to set allowUnfree");
+        set_in_file(
+            &file,
+            "inputs.nixpkgs.url",
+            "github:NixOS/nixpkgs/nixos-24.11",
+        )
+        .expect("Failed to set input url");
+        set_in_file(&file, "clean.keep", "[HOME, USER]").expect("Failed to set clean.keep");
+        assert_eq!(
+            std::fs::read_to_string(&file).unwrap(),
+            indoc::indoc! {"
+                # Pinned inputs
+                inputs:
+                  nixpkgs:
+                    url: github:NixOS/nixpkgs/nixos-24.11
+                allowUnfree: true
+                clean:
+                  keep:
+                  - HOME
+                  - USER
+            "}
+        );
+
+        let config = Config::load_from_files(&[file.clone()]).expect("Failed to load config");
+        assert_eq!(
+            config.get("allowUnfree").unwrap(),
+            Some(serde_yaml::Value::Bool(true))
+        );
+        assert_eq!(
+            config.get("impure").unwrap(),
+            Some(serde_yaml::Value::Bool(false))
+        );
+
+        assert!(unset_in_file(&file, "clean.keep").expect("Failed to unset clean.keep"));
+        assert!(!unset_in_file(&file, "impure").expect("Failed to unset impure"));
+        assert_eq!(
+            std::fs::read_to_string(&file).unwrap(),
+            indoc::indoc! {"
+                # Pinned inputs
+                inputs:
+                  nixpkgs:
+                    url: github:NixOS/nixpkgs/nixos-24.11
+                allowUnfree: true
+            "}
+        );
+    }
+
+    #[test]
+    fn set_rejects_unknown_keys_and_wrong_types() {
+        let dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let file = dir.path().join(YAML_CONFIG);
+
+        let err = set_in_file(&file, "allowUnfre", "true").unwrap_err();
+        assert!(err.to_string().contains("Did you mean `allowUnfree`?"));
+        let err = set_in_file(&file, "inputs.nixpkgs.urll", "github:NixOS/nixpkgs").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Did you mean `inputs.nixpkgs.url`?"));
+        let err = set_in_file(&file, "allowUnfree", "yes").unwrap_err();
+        assert!(err.to_string().contains("Expected a boolean"));
+        let err = set_in_file(&file, "clean.keep", "HOME").unwrap_err();
+        assert!(err.to_string().contains("Expected a list"));
+        assert!(unset_in_file(&file, "allowUnfre").is_err());
+        assert!(!file.exists());
+    }
+
+    #[test]
+    fn set_parses_values_for_the_schema() {
+        let schema = config_schema();
+        let parse = |key: &str, raw: &str| {
+            let path = key_path(key).unwrap();
+            parse_value(key, raw, find_key(&schema, &path).unwrap())
+        };
+        assert_eq!(
+            parse("inputs.nixpkgs.flake", "false").unwrap(),
+            serde_yaml::Value::Bool(false)
+        );
+        // Strings don't need quoting.
+        assert_eq!(
+            parse("inputs.nixpkgs.url", "true").unwrap(),
+            serde_yaml::Value::String("true".to_string())
+        );
+        assert_eq!(
+            parse(
+                "nixpkgs.per-platform.x86_64-linux.cudaCapabilities",
+                "[8.6, 9.0]"
+            )
+            .unwrap(),
+            serde_yaml::to_value(["8.6", "9.0"]).unwrap()
+        );
+        assert!(parse("backend", "nix").is_ok());
+        assert!(parse("backend", "lix").is_err());
+        assert!(parse("impure", "null").is_err());
+        assert!(key_path("inputs..url").is_err());
+    }
+
+    #[test]
+    fn get_effective_values() {
+        let config = Config {
+            inputs: BTreeMap::from_iter([(
+                "nixpkgs".to_string(),
+                Input {
+                    url: Some("github:cachix/devenv-nixpkgs/rolling".to_string()),
+                    flake: true,
+                    ..Default::default()
+                },
+            )]),
+            options: BTreeMap::from_iter([(
+                "languages.rust.enable:bool".to_string(),
+                "true".to_string(),
+            )]),
+            ..Default::default()
+        };
+
+        let get = |key: &str| config.get(key).unwrap();
+        assert_eq!(get("allowUnfree"), Some(serde_yaml::Value::Bool(false)));
+        assert_eq!(
+            get("inputs.nixpkgs.flake"),
+            Some(serde_yaml::Value::Bool(true))
+        );
+        assert_eq!(
+            get("nixpkgs.allowUnfree"),
+            Some(serde_yaml::Value::Bool(false))
+        );
+        assert_eq!(get("backend"), Some(serde_yaml::to_value("nix").unwrap()));
+        assert_eq!(get("inputs.nixpkgs.follows"), None);
+        assert_eq!(get("inputs.rust-overlay.flake"), None);
+        assert_eq!(
+            get("options.\"languages.rust.enable:bool\""),
+            Some(serde_yaml::to_value("true").unwrap())
+        );
+
+        assert_eq!(
+            key_path("options.\"languages.rust.enable:bool\"").unwrap(),
+            vec!["options", "languages.rust.enable:bool"]
+        );
+        assert!(key_path("options.\"languages.rust").is_err());
+        assert!(key_path("options.\"languages\"rust").is_err());
+    }
+
     #[test]
     fn default_config_serializes_to_empty_yaml() {
         let config = Config::default();
@@ -1,16 +1,21 @@
-use super::{cli, config, nix_backend, tasks, util};
+use super::{
+    builders, cli, cli_options, config, gc, lock, nix, nix_backend, nix_edit, oci, projects,
+    registry, search, tasks, util,
+};
 use ::nix::sys::signal;
 use ::nix::unistd::Pid;
 use clap::crate_version;
 use cli_table::Table;
-use cli_table::{print_stderr, WithTitle};
+use cli_table::{print_stderr, print_stdout, WithTitle};
 use include_dir::{include_dir, Dir};
+use indicatif::{HumanBytes, HumanDuration};
 use miette::{bail, miette, Context, IntoDiagnostic, Result};
+use nix_conf_parser::NixConf;
 use once_cell::sync::Lazy;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use sha2::Digest;
 use similar::{ChangeTag, TextDiff};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::io::Write;
 use std::os::unix::{fs::PermissionsExt, process::CommandExt};
 use std::path::{Path, PathBuf};
@@ -78,6 +83,8 @@ pub struct Devenv {
     devenv_dotfile: PathBuf,
     devenv_dot_gc: PathBuf,
     devenv_home_gc: PathBuf,
+    devenv_projects: PathBuf,
+    devenv_search_index: PathBuf,
     devenv_tmp: String,
     devenv_runtime: PathBuf,
 
@@ -100,6 +107,9 @@ impl Devenv {
         let devenv_home = xdg_dirs.get_data_home();
         let cachix_trusted_keys = devenv_home.join("cachix_trusted_keys.json");
         let devenv_home_gc = devenv_home.join("gc");
+        let devenv_projects = devenv_home.join(projects::REGISTRY_FILE);
+        // Shared between projects, so that nixpkgs is only indexed once per revision.
+        let devenv_search_index = devenv_home.join(search::INDEX_FILE);
 
         let devenv_root = options
             .devenv_root
@@ -109,7 +119,15 @@ impl Devenv {
             .devenv_dotfile
             .map(|p| p.to_path_buf())
             .unwrap_or(devenv_root.join(".devenv"));
-        let devenv_dot_gc = devenv_dotfile.join("gc");
+        let global_options = options.global_options.unwrap_or_default();
+
+        // Each profile gets its own GC roots and eval cache, so that switching between profiles
+        // doesn't invalidate the cached evaluations of the others.
+        let devenv_profile_dir = match &global_options.profile {
+            Some(profile) => devenv_dotfile.join("profiles").join(profile),
+            None => devenv_dotfile.clone(),
+        };
+        let devenv_dot_gc = devenv_profile_dir.join("gc");
 
         let devenv_tmp = std::env::var("XDG_RUNTIME_DIR")
             .unwrap_or_else(|_| std::env::var("TMPDIR").unwrap_or_else(|_| "/tmp".to_string()));
@@ -123,8 +141,6 @@ impl Devenv {
         let devenv_runtime =
             Path::new(&devenv_tmp).join(format!("devenv-{}", &devenv_state_hash[..7]));
 
-        let global_options = options.global_options.unwrap_or_default();
-
         xdg_dirs
             .create_data_directory(Path::new("devenv"))
             .expect("Failed to create DEVENV_HOME directory");
@@ -134,6 +150,10 @@ impl Devenv {
 
         // Determine backend type from config
         let backend_type = options.config.backend.clone();
+        // The backends evaluate with the profile's imports, which are never written back to devenv.yaml.
+        let eval_config = options
+            .config
+            .with_profile(global_options.profile.as_deref());
 
         // Create DevenvPaths struct
         let paths = nix_backend::DevenvPaths {
@@ -141,24 +161,21 @@ impl Devenv {
             dotfile: devenv_dotfile.clone(),
             dot_gc: devenv_dot_gc.clone(),
             home_gc: devenv_home_gc.clone(),
+            eval_cache: devenv_profile_dir.join("nix-eval-cache.db"),
             cachix_trusted_keys,
         };
 
         let nix: Box<dyn nix_backend::NixBackend> = match backend_type {
             config::NixBackendType::Nix => Box::new(
-                crate::nix::Nix::new(options.config.clone(), global_options.clone(), paths)
+                crate::nix::Nix::new(eval_config, global_options.clone(), paths)
                     .await
                     .expect("Failed to initialize Nix backend"),
             ),
             #[cfg(feature = "snix")]
             config::NixBackendType::Snix => Box::new(
-                crate::snix_backend::SnixBackend::new(
-                    options.config.clone(),
-                    global_options.clone(),
-                    paths,
-                )
-                .await
-                .expect("Failed to initialize Snix backend"),
+                crate::snix_backend::SnixBackend::new(eval_config, global_options.clone(), paths)
+                    .await
+                    .expect("Failed to initialize Snix backend"),
             ),
         };
 
@@ -169,6 +186,8 @@ impl Devenv {
             devenv_dotfile,
             devenv_dot_gc,
             devenv_home_gc,
+            devenv_projects,
+            devenv_search_index,
             devenv_tmp,
             devenv_runtime,
             nix: Arc::new(nix),
@@ -245,11 +264,236 @@ impl Devenv {
         {
             let mut config = self.config.write().await;
             config.add_input(name, url, follows)?;
-            config.write().await?;
+            config.write_to(&self.devenv_root).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn inputs_list(&self) -> Result<()> {
+        let config = self.config.read().await;
+        let lock = lock::Lock::load_from(&self.devenv_root)?;
+        let locked = lock
+            .as_ref()
+            .map(|lock| lock.root_inputs())
+            .unwrap_or_default();
+
+        let mut names = config.inputs.keys().map(String::as_str).collect::<Vec<_>>();
+        names.extend(
+            locked
+                .keys()
+                .filter(|name| !config.inputs.contains_key(**name)),
+        );
+        names.sort_unstable();
+
+        let results = names
+            .into_iter()
+            .map(|name| {
+                let input = config.inputs.get(name);
+                let url = match input {
+                    Some(config::Input { url: Some(url), .. }) => url.clone(),
+                    Some(config::Input {
+                        follows: Some(follows),
+                        ..
+                    }) => format!("follows {follows}"),
+                    _ => "(default)".to_string(),
+                };
+                let locked_ref = locked.get(name).and_then(|node| node.locked.as_ref());
+                DevenvInputResult {
+                    name: name.to_string(),
+                    url,
+                    rev: locked_ref
+                        .and_then(|locked| locked.short_rev())
+                        .unwrap_or("-")
+                        .to_string(),
+                    age: locked_ref
+                        .and_then(|locked| locked.age())
+                        .unwrap_or_else(|| "-".to_string()),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if results.is_empty() {
+            info!("No inputs defined in devenv.yaml.");
+            return Ok(());
+        }
+        print_stdout(results.with_title()).expect("Failed to print inputs");
+
+        match &lock {
+            Some(lock) => self.warn_about_lock(lock),
+            None => {
+                warn!("devenv.lock does not exist yet. Run `devenv update` to lock the inputs.")
+            }
+        }
+        Ok(())
+    }
+
+    /// Make inputs follow the top-level inputs they duplicate.
+    pub async fn inputs_dedupe(&self) -> Result<()> {
+        let Some(lock) = lock::Lock::load_from(&self.devenv_root)? else {
+            bail!("devenv.lock does not exist yet. Run `devenv update` to lock the inputs.");
+        };
+
+        let mut updated = 0;
+        {
+            let mut config = self.config.write().await;
+            for duplicate in lock.duplicate_inputs() {
+                if !config.inputs.contains_key(&duplicate.input) {
+                    warn!(
+                        "Skipping {duplicate}: {} is not defined in devenv.yaml",
+                        duplicate.input
+                    );
+                    continue;
+                }
+                if config.add_follows(&duplicate.input, &duplicate.nested, &duplicate.follows)? {
+                    info!(
+                        "{}/{} now follows {}",
+                        duplicate.input, duplicate.nested, duplicate.follows
+                    );
+                    updated += 1;
+                } else {
+                    info!("Skipping {duplicate}: it's already configured in devenv.yaml");
+                }
+            }
+            if updated > 0 {
+                config.write_to(&self.devenv_root).await?;
+            }
+        }
+
+        if updated == 0 {
+            info!("No duplicate inputs to deduplicate.");
+        } else {
+            info!("Updated {updated} input(s) in devenv.yaml. devenv.lock will be updated on the next run.");
+        }
+        Ok(())
+    }
+
+    /// Compare the locked inputs with the devenv.lock from a git revision.
+    pub async fn inputs_diff(&self, git_ref: &str) -> Result<()> {
+        let output = process::Command::new("git")
+            .args(["show", &format!("{git_ref}:./{}", lock::LOCK_FILE)])
+            .current_dir(&self.devenv_root)
+            .output()
+            .await
+            .into_diagnostic()
+            .wrap_err("Failed to run git")?;
+
+        let old_lock = if output.status.success() {
+            let contents = String::from_utf8_lossy(&output.stdout);
+            Some(
+                lock::Lock::parse(&contents)
+                    .wrap_err_with(|| format!("Failed to parse devenv.lock at {git_ref}"))?,
+            )
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            // The lock file didn't exist yet at that revision.
+            if stderr.contains("exists on disk, but not in") || stderr.contains("does not exist in")
+            {
+                None
+            } else {
+                bail!("Failed to read devenv.lock at {git_ref}: {}", stderr.trim());
+            }
+        };
+        let new_lock = lock::Lock::load_from(&self.devenv_root)?;
+
+        let changes = lock::diff(old_lock.as_ref(), new_lock.as_ref());
+        if changes.is_empty() {
+            info!("No inputs changed since {git_ref}.");
+        }
+        for change in changes {
+            println!("{change}");
+        }
+        Ok(())
+    }
+
+    pub async fn inputs_remove(&self, name: &str) -> Result<()> {
+        let removed = {
+            let mut config = self.config.write().await;
+            let removed = config.remove_input(name)?;
+            config.write_to(&self.devenv_root).await?;
+            removed
+        };
+        for path in removed {
+            info!("Removed {path}, which followed {name}");
+        }
+        info!("Removed input {name}. Run `devenv update` to update devenv.lock.");
+        Ok(())
+    }
+
+    /// Print the merged config, optionally with the file each value came from.
+    pub async fn config_show(&self, origin: bool) -> Result<()> {
+        let config = self.config.read().await;
+        if !origin {
+            print!(
+                "{}",
+                serde_yaml::to_string(&*config).expect("Failed to serialize config")
+            );
+            return Ok(());
+        }
+
+        let layers = config::read_layers(&config::config_paths(&self.devenv_root))?;
+        let origins = config::value_origins(&layers);
+        let layered = config::flatten_value(&config::merge_layers(&layers))
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        let effective = serde_yaml::to_value(&*config).expect("Failed to serialize config");
+
+        for (path, value) in config::flatten_value(&effective) {
+            let origin = match origins.get(&path) {
+                // Values that differ from the files were overridden on the command line.
+                Some(_) if layered.get(&path) != Some(&value) => "command line".to_string(),
+                Some(file) => file.display().to_string(),
+                None => "default".to_string(),
+            };
+            let value = serde_json::to_string(&value).expect("Failed to serialize value");
+            println!("{path}: {value}  # {origin}");
+        }
+        Ok(())
+    }
+
+    /// Print the value of a config key.
+    pub async fn config_get(&self, key: &str) -> Result<()> {
+        let config = self.config.read().await;
+        match config.get(key)? {
+            Some(serde_yaml::Value::String(value)) => println!("{value}"),
+            Some(value) => print!(
+                "{}",
+                serde_yaml::to_string(&value).expect("Failed to serialize value")
+            ),
+            None => bail!("{key} is not set."),
+        }
+        Ok(())
+    }
+
+    /// Set a config key in devenv.yaml, devenv.local.yaml or the user config.
+    pub async fn config_set(&self, key: &str, value: &str, local: bool, user: bool) -> Result<()> {
+        let file = self.config_file(local, user)?;
+        config::set_in_file(&file, key, value)?;
+        info!("Set {key} in {}", file.display());
+        Ok(())
+    }
+
+    /// Remove a config key from devenv.yaml, devenv.local.yaml or the user config.
+    pub async fn config_unset(&self, key: &str, local: bool, user: bool) -> Result<()> {
+        let file = self.config_file(local, user)?;
+        if config::unset_in_file(&file, key)? {
+            info!("Removed {key} from {}", file.display());
+        } else {
+            info!("{key} is not set in {}", file.display());
         }
         Ok(())
     }
 
+    fn config_file(&self, local: bool, user: bool) -> Result<PathBuf> {
+        if user {
+            config::user_config_path()
+                .ok_or_else(|| miette!("Failed to find the user config directory"))
+        } else if local {
+            Ok(self.devenv_root.join(config::YAML_LOCAL_CONFIG))
+        } else {
+            Ok(self.devenv_root.join(config::YAML_CONFIG))
+        }
+    }
+
     pub async fn print_dev_env(&self, json: bool) -> Result<()> {
         let env = self.get_dev_environment(json).await?;
         print!(
@@ -385,6 +629,7 @@ impl Devenv {
     pub async fn shell(self) -> Result<()> {
         let shell_cmd = self.prepare_shell(&None, &[]).await?;
         info!(devenv.is_user_message = true, "Entering shell");
+        crate::log::flush_before_exec();
         let err = shell_cmd.into_std().exec();
         bail!("Failed to execute shell: {}", err);
     }
@@ -416,22 +661,112 @@ impl Devenv {
             None => "Updating devenv.lock".to_string(),
         };
 
+        let old_lock = lock::Lock::load_from(&self.devenv_root)?;
+
         let span = info_span!("update", devenv.user_message = msg);
         self.nix.update(input_name).instrument(span).await?;
 
+        let new_lock = lock::Lock::load_from(&self.devenv_root)?;
+        let changes = lock::diff(old_lock.as_ref(), new_lock.as_ref());
+        if changes.is_empty() {
+            info!("All inputs are up to date.");
+        }
+        for change in changes {
+            info!("{change}");
+        }
+        if let Some(lock) = &new_lock {
+            self.warn_about_lock(lock);
+        }
+
         Ok(())
     }
 
     pub async fn container_build(&self, name: &str) -> Result<String> {
-        if cfg!(target_os = "macos") {
-            bail!(
-                "Containers are not supported on macOS yet: https://github.com/cachix/devenv/issues/430"
-            );
+        let spec = self.build_container_spec(name).await?;
+        println!("{spec}");
+        Ok(spec)
+    }
+
+    /// Build a container for each of `systems`, and write the images to an OCI layout
+    /// with an image index that lists them. Prints and returns the path of the layout.
+    pub async fn container_build_systems(
+        &mut self,
+        name: &str,
+        systems: &[String],
+    ) -> Result<PathBuf> {
+        let specs = self.build_container_specs(name, systems).await?;
+        let reference = self.container_reference(name).await?;
+        let tag = reference
+            .rsplit_once(':')
+            .map_or("latest", |(_, tag)| tag)
+            .to_string();
+
+        let layout = self
+            .devenv_dotfile
+            .join(format!("container-{}", sanitize_container_name(name)));
+        if layout.exists() {
+            fs::remove_dir_all(&layout)
+                .await
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to remove {}", layout.display()))?;
         }
+        let index = {
+            let layout = layout.clone();
+            tokio::task::spawn_blocking(move || {
+                oci::write_multi_platform_image(&specs, &layout, &tag)
+            })
+            .await
+            .into_diagnostic()??
+            .1
+        };
+        info!(
+            "Wrote the image index {} for {} to {}",
+            index.digest,
+            systems.join(", "),
+            layout.display()
+        );
+        println!("{}", layout.display());
+        Ok(layout)
+    }
 
+    /// Build the image specs of a container for each of `systems`, one after the other.
+    async fn build_container_specs(
+        &mut self,
+        name: &str,
+        systems: &[String],
+    ) -> Result<Vec<oci::ImageSpec>> {
+        let original_system = self.global_options.system.clone();
+        let specs = async {
+            let mut specs = Vec::new();
+            for system in systems {
+                self.global_options.system = system.clone();
+                // The system is written to flake.devenv.nix, so the project has to be assembled again.
+                self.assembled.store(false, Ordering::Release);
+                let spec = self.build_container_spec(name).await?;
+                specs.push(oci::ImageSpec::read(Path::new(&spec))?);
+            }
+            Ok::<_, miette::Report>(specs)
+        }
+        .await;
+
+        // Assemble for the original system again, even if a build failed,
+        // so that .devenv isn't left behind for the last of `systems`.
+        self.global_options.system = original_system;
+        self.assembled.store(false, Ordering::Release);
+        let assembled = self.assemble(false).await;
+        let specs = specs?;
+        assembled?;
+        Ok(specs)
+    }
+
+    /// Build the image spec of a container for the current system.
+    async fn build_container_spec(&self, name: &str) -> Result<String> {
+        self.check_container_system().await?;
+
+        let system = &self.global_options.system;
         let span = info_span!(
             "building_container",
-            devenv.user_message = format!("Building {name} container")
+            devenv.user_message = format!("Building {name} container for {system}")
         );
 
         async move {
@@ -440,7 +775,7 @@ impl Devenv {
             let sanitized_name = sanitize_container_name(name);
             let gc_root = self
                 .devenv_dot_gc
-                .join(format!("container-{sanitized_name}-derivation"));
+                .join(format!("container-{sanitized_name}-{system}-derivation"));
             let paths = self
                 .nix
                 .build(
@@ -449,21 +784,252 @@ impl Devenv {
                     Some(&gc_root),
                 )
                 .await?;
-            let container_store_path = &paths[0].to_string_lossy();
-            println!("{}", container_store_path);
-            Ok(container_store_path.to_string())
+            Ok(paths[0].to_string_lossy().to_string())
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Check that Nix can build for the container's system, locally or on a remote builder,
+    /// so that a missing builder is reported before evaluating the container.
+    async fn check_container_system(&self) -> Result<()> {
+        let system = &self.global_options.system;
+        if !system.ends_with("-linux") {
+            bail!("Containers can only be built for Linux systems, not {system}. Pass a Linux system with --system, like --system x86_64-linux.");
+        }
+        if *system == cli::default_system() {
+            return Ok(());
+        }
+
+        let options = nix_backend::Options {
+            logging: false,
+            ..Default::default()
+        };
+        let nix_config = match self.nix.run_nix("nix", &["config", "show"], &options).await {
+            Ok(output) => NixConf::parse_stdout(&output.stdout)?,
+            Err(e) => {
+                warn!("Failed to check which systems Nix can build for: {e}");
+                return Ok(());
+            }
+        };
+        builders::check_can_build(system, &nix_config)
+            .wrap_err_with(|| format!("Failed to build containers for {system}"))
+    }
+
+    /// Export a container to `output` without skopeo or Docker, and print its manifest digest.
+    pub async fn container_export(
+        &self,
+        name: &str,
+        format: oci::ExportFormat,
+        output: &Path,
+    ) -> Result<String> {
+        // Only the digest goes to stdout.
+        let spec = self.build_container_spec(name).await?;
+
+        let span = info_span!(
+            "exporting_container",
+            devenv.user_message = format!("Exporting {name} container")
+        );
+
+        async move {
+            let reference = self.container_reference(name).await?;
+            let spec = oci::ImageSpec::read(Path::new(&spec))?;
+            let output = output.to_path_buf();
+            let digest = {
+                let output = output.clone();
+                tokio::task::spawn_blocking(move || oci::export(&spec, &reference, format, &output))
+                    .await
+                    .into_diagnostic()??
+            };
+            info!("Exported {name} container to {}", output.display());
+            println!("{digest}");
+            Ok(digest)
         }
         .instrument(span)
         .await
     }
 
+    /// Print the name, entrypoint, startup command, registry and version of each container.
+    pub async fn container_list(&self) -> Result<()> {
+        self.assemble(false).await?;
+
+        let containers: BTreeMap<String, ContainerSummary> =
+            serde_json::from_str(&self.nix.eval(&["devenv.container.summary"]).await?)
+                .into_diagnostic()
+                .wrap_err("Failed to parse the containers")?;
+        let rows = containers
+            .into_iter()
+            .map(|(name, container)| DevenvContainerResult {
+                name,
+                image: container.name.unwrap_or_else(|| "-".to_string()),
+                version: container.version.unwrap_or_else(|| "-".to_string()),
+                registry: container.registry.unwrap_or_else(|| "-".to_string()),
+                startup_command: container.startup_command.unwrap_or_else(|| "-".to_string()),
+                entrypoint: container.entrypoint.join(" "),
+            })
+            .collect::<Vec<_>>();
+        print_stdout(rows.with_title()).expect("Failed to print containers");
+        Ok(())
+    }
+
+    /// Build a container and print its environment, layers and the store paths in each layer.
+    pub async fn container_inspect(&self, name: &str) -> Result<()> {
+        let spec = self.build_container_spec(name).await?;
+        let reference = self.container_reference(name).await?;
+        let spec = oci::ImageSpec::read(Path::new(&spec))?;
+
+        let store_paths = spec
+            .layers
+            .iter()
+            .flat_map(|layer| &layer.paths)
+            .map(|path| path.path.to_string_lossy().to_string())
+            .collect::<BTreeSet<_>>();
+        let sizes = if store_paths.is_empty() {
+            BTreeMap::new()
+        } else {
+            let options = nix_backend::Options {
+                logging: false,
+                ..Default::default()
+            };
+            let mut args = vec!["path-info", "--json"];
+            args.extend(store_paths.iter().map(String::as_str));
+            let output = self.nix.run_nix("nix", &args, &options).await?;
+            nix::nar_sizes(&output.stdout)?
+        };
+
+        let mut layers = Vec::new();
+        let mut paths = Vec::new();
+        for (i, layer) in spec.layers.iter().enumerate() {
+            let size = match &layer.layer_path {
+                // A prebuilt layer from a base image.
+                Some(layer_path) => fs::metadata(layer_path)
+                    .await
+                    .map(|metadata| metadata.len())
+                    .unwrap_or_default(),
+                None => layer
+                    .paths
+                    .iter()
+                    .map(|path| {
+                        sizes
+                            .get(&*path.path.to_string_lossy())
+                            .copied()
+                            .unwrap_or_default()
+                    })
+                    .sum(),
+            };
+            layers.push((i + 1, layer, size));
+            for path in &layer.paths {
+                let path = path.path.to_string_lossy().to_string();
+                let size = sizes.get(&path).copied().unwrap_or_default();
+                paths.push(DevenvContainerPathResult {
+                    path,
+                    layer: i + 1,
+                    size: HumanBytes(size).to_string(),
+                });
+            }
+        }
+        let total = layers.iter().map(|(_, _, size)| size).sum::<u64>();
+
+        println!("Image: {reference}");
+        println!("Architecture: {}", spec.arch);
+        println!("Size: {}", HumanBytes(total));
+        for field in ["Entrypoint", "Cmd", "WorkingDir", "User"] {
+            match spec.config.get(field) {
+                Some(serde_json::Value::Array(args)) if !args.is_empty() => {
+                    let args = args
+                        .iter()
+                        .map(|arg| arg.as_str().unwrap_or_default())
+                        .collect::<Vec<_>>();
+                    println!("{field}: {}", args.join(" "));
+                }
+                Some(serde_json::Value::String(value)) if !value.is_empty() => {
+                    println!("{field}: {value}");
+                }
+                _ => {}
+            }
+        }
+        if let Some(serde_json::Value::Array(env)) = spec.config.get("Env") {
+            println!("Env:");
+            for var in env.iter().filter_map(|var| var.as_str()) {
+                println!("  {var}");
+            }
+        }
+        println!();
+
+        let layers = layers
+            .into_iter()
+            .map(|(i, layer, size)| DevenvContainerLayerResult {
+                layer: i,
+                paths: match &layer.layer_path {
+                    Some(_) => "base image".to_string(),
+                    None => layer.paths.len().to_string(),
+                },
+                size: HumanBytes(size).to_string(),
+            })
+            .collect::<Vec<_>>();
+        print_stdout(layers.with_title()).expect("Failed to print layers");
+        println!();
+        print_stdout(paths.with_title()).expect("Failed to print store paths");
+        Ok(())
+    }
+
+    /// The `name:tag` of a container, from its `name` and `version` options.
+    async fn container_reference(&self, name: &str) -> Result<String> {
+        let image_name: String = self.container_option(name, "name").await?;
+        let version: Option<String> = self.container_option(name, "version").await?;
+        Ok(format!(
+            "{image_name}:{}",
+            version.as_deref().unwrap_or("latest")
+        ))
+    }
+
+    /// Evaluate `devenv.containers.<name>.<option>`.
+    async fn container_option<T: serde::de::DeserializeOwned>(
+        &self,
+        name: &str,
+        option: &str,
+    ) -> Result<T> {
+        serde_json::from_str(
+            &self
+                .nix
+                .eval(&[&format!("devenv.containers.{name}.{option}")])
+                .await?,
+        )
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to parse the container {option}"))
+    }
+
     pub async fn container_copy(
         &self,
         name: &str,
         copy_args: &[String],
         registry: Option<&str>,
+        tags: &[String],
     ) -> Result<()> {
-        let spec = self.container_build(name).await?;
+        let spec = self.build_container_spec(name).await?;
+
+        // Push to registries ourselves, unless skopeo is given arguments that only it understands.
+        // Pushing prints only the digest to stdout.
+        let destination = self.container_destination(name, registry).await?;
+        if let Some(destination) = destination
+            .as_deref()
+            .and_then(|destination| destination.strip_prefix("docker://"))
+        {
+            let default_copy_args: Vec<String> =
+                self.container_option(name, "defaultCopyArgs").await?;
+            if copy_args.is_empty() && default_copy_args.is_empty() {
+                let spec = oci::ImageSpec::read(Path::new(&spec))?;
+                self.container_push(name, vec![spec], destination, tags)
+                    .await?;
+                return Ok(());
+            }
+        }
+        if !tags.is_empty() {
+            bail!(
+                "--tag is only supported when pushing to a docker:// registry without --copy-args"
+            );
+        }
+        println!("{spec}");
 
         // TODO: No newline
         let span = info_span!(
@@ -513,6 +1079,122 @@ impl Devenv {
         .await
     }
 
+    /// Push a container built for each of `systems` to a registry, as an image index.
+    pub async fn container_copy_systems(
+        &mut self,
+        name: &str,
+        copy_args: &[String],
+        registry: Option<&str>,
+        systems: &[String],
+        tags: &[String],
+    ) -> Result<()> {
+        if !copy_args.is_empty() {
+            bail!(
+                "--copy-args can't be used with --systems, since skopeo can't push image indexes"
+            );
+        }
+        let default_copy_args: Vec<String> = self.container_option(name, "defaultCopyArgs").await?;
+        if !default_copy_args.is_empty() {
+            bail!(
+                "containers.{name}.defaultCopyArgs can't be used with --systems, since skopeo can't push image indexes"
+            );
+        }
+        let specs = self.build_container_specs(name, systems).await?;
+        let destination = self.container_destination(name, registry).await?;
+        let Some(destination) = destination
+            .as_deref()
+            .and_then(|destination| destination.strip_prefix("docker://"))
+        else {
+            bail!(
+                "Containers built for several systems can only be pushed to docker:// registries"
+            );
+        };
+        self.container_push(name, specs, destination, tags).await?;
+        Ok(())
+    }
+
+    /// The destination to copy a container to: the --registry flag, or the `registry` option.
+    async fn container_destination(
+        &self,
+        name: &str,
+        registry: Option<&str>,
+    ) -> Result<Option<String>> {
+        match registry {
+            Some(registry) => Ok(Some(registry.to_string())),
+            None => self.container_option(name, "registry").await,
+        }
+    }
+
+    /// Push a container to `destination` in a registry, and print the digest of its manifest.
+    ///
+    /// Several images are pushed as an image index, whose digest is printed instead.
+    async fn container_push(
+        &self,
+        name: &str,
+        specs: Vec<oci::ImageSpec>,
+        destination: &str,
+        tags: &[String],
+    ) -> Result<String> {
+        let span = info_span!(
+            "pushing_container",
+            devenv.user_message = format!("Pushing {name} container")
+        );
+
+        async move {
+            let image_name: String = self.container_option(name, "name").await?;
+            let reference = registry::Reference::parse(&format!("{destination}{image_name}"))?;
+            let tags = if tags.is_empty() {
+                let version: Option<String> = self.container_option(name, "version").await?;
+                vec![version.unwrap_or_else(|| "latest".to_string())]
+            } else {
+                tags.to_vec()
+            };
+
+            let staging = tempfile::Builder::new()
+                .prefix(".container-push.")
+                .tempdir_in(&self.devenv_dotfile)
+                .into_diagnostic()
+                .wrap_err("Failed to create a directory for the image")?;
+            let (images, index) = {
+                let dir = staging.path().to_path_buf();
+                let tag = tags[0].clone();
+                tokio::task::spawn_blocking(move || -> Result<_> {
+                    if let [spec] = specs.as_slice() {
+                        Ok((vec![oci::write_image(spec, &dir)?], None))
+                    } else {
+                        let (images, index) = oci::write_multi_platform_image(&specs, &dir, &tag)?;
+                        Ok((images, Some(index)))
+                    }
+                })
+                .await
+                .into_diagnostic()??
+            };
+
+            let client = registry::login(&reference).await?;
+            let digest = match index {
+                Some(index) => {
+                    client
+                        .push_index(staging.path(), &images, &index, &tags)
+                        .await?;
+                    index.digest
+                }
+                None => {
+                    let image = &images[0];
+                    client.push_image(staging.path(), image, &tags).await?;
+                    image.manifest.digest.clone()
+                }
+            };
+
+            for tag in &tags {
+                info!("Pushed {reference}:{tag}");
+            }
+            println!("{digest}");
+            Ok(digest)
+        }
+        .instrument(span)
+        .await
+    }
+
     pub async fn container_run(
         &self,
         name: &str,
@@ -522,7 +1204,7 @@ impl Devenv {
         if registry.is_some() {
             warn!("Ignoring --registry flag when running container");
         };
-        self.container_copy(name, copy_args, Some("docker-daemon:"))
+        self.container_copy(name, copy_args, Some("docker-daemon:"), &[])
             .await?;
 
         let span = info_span!(
@@ -555,92 +1237,743 @@ impl Devenv {
                 Ok(())
             }
         }
-        .instrument(span)
-        .await
+        .instrument(span)
+        .await
+    }
+
+    pub async fn repl(&self) -> Result<()> {
+        self.assemble(false).await?;
+        self.nix.repl().await
+    }
+
+    pub async fn gc(&self, options: &gc::GcOptions) -> Result<()> {
+        let start = std::time::Instant::now();
+        let gc_dir = if options.project {
+            &self.devenv_dot_gc
+        } else {
+            &self.devenv_home_gc
+        };
+
+        let (roots, dangling) = gc::read_roots(gc_dir)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read {}", gc_dir.display()))?;
+        // Within a project, only previous environments are deleted
+        let (candidates, current): (Vec<_>, Vec<_>) = roots
+            .into_iter()
+            .partition(|root| !options.project || root.is_history);
+        let selected = gc::select(
+            &candidates,
+            options.keep_generations,
+            options.older_than,
+            std::time::SystemTime::now(),
+        );
+        info!(
+            "Found {} environments in {}, {} of which can be deleted.",
+            candidates.len() + current.len(),
+            gc_dir.display(),
+            selected.len()
+        );
+
+        let selected_links: BTreeSet<&Path> =
+            selected.iter().map(|root| root.link.as_path()).collect();
+        let kept: Vec<&Path> = current
+            .iter()
+            .chain(candidates.iter())
+            .filter(|root| !selected_links.contains(root.link.as_path()))
+            .map(|root| root.target.as_path())
+            .collect();
+        let garbage = {
+            let span = info_span!(
+                "gc_closure",
+                devenv.user_message = "Computing the size of unused environments"
+            );
+            async {
+                let targets: Vec<&Path> =
+                    selected.iter().map(|root| root.target.as_path()).collect();
+                let mut garbage = self.closure_sizes(&targets).await?;
+                let kept = self.closure_sizes(&kept).await?;
+                garbage.retain(|path, _| !kept.contains_key(path));
+                Ok::<_, miette::Report>(garbage)
+            }
+            .instrument(span)
+            .await?
+        };
+
+        if options.dry_run {
+            for root in &selected {
+                println!("{} -> {}", root.link.display(), root.target.display());
+            }
+            info!(
+                "Would delete {} environments ({} store paths) and free up to {}.",
+                selected.len(),
+                garbage.len(),
+                HumanBytes(garbage.values().sum())
+            );
+            if !dangling.is_empty() {
+                info!(
+                    "Would remove {} symlinks to environments that no longer exist.",
+                    dangling.len()
+                );
+            }
+            return Ok(());
+        }
+
+        // Removing the links of a project is what makes their environments garbage
+        if options.project {
+            for root in &selected {
+                fs::remove_file(&root.link)
+                    .await
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to remove {}", root.link.display()))?;
+            }
+        }
+
+        {
+            let span = info_span!(
+                "nix_gc",
+                devenv.user_message =
+                    "Running garbage collection (this process will take some time)"
+            );
+            info!(
+                "If you'd like this to run faster, leave a thumbs up at https://github.com/NixOS/nix/issues/7239"
+            );
+            self.nix
+                .gc(garbage.keys().map(PathBuf::from).collect())
+                .instrument(span)
+                .await?;
+        }
+
+        let reclaimed: u64 = garbage
+            .iter()
+            .filter(|(path, _)| !Path::new(path).exists())
+            .map(|(_, size)| size)
+            .sum();
+        let (_, dangling) = gc::read_roots(gc_dir)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read {}", gc_dir.display()))?;
+        for link in &dangling {
+            fs::remove_file(link)
+                .await
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to remove {}", link.display()))?;
+        }
+        let removed = if options.project {
+            selected.len()
+        } else {
+            dangling.len()
+        };
+
+        info!(
+            "\nDone. Removed {} environments and freed {} in {:.2}s.",
+            removed,
+            HumanBytes(reclaimed),
+            start.elapsed().as_secs_f32()
+        );
+        Ok(())
+    }
+
+    /// The NAR size of every store path in the closures of `paths`.
+    async fn closure_sizes(&self, paths: &[&Path]) -> Result<BTreeMap<String, u64>> {
+        if paths.is_empty() {
+            return Ok(BTreeMap::new());
+        }
+        let options = nix_backend::Options {
+            logging: false,
+            ..Default::default()
+        };
+        let paths: Vec<String> = paths
+            .iter()
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+        let mut args = vec!["path-info", "--recursive", "--json"];
+        args.extend(paths.iter().map(String::as_str));
+        let output = self.nix.run_nix("nix", &args, &options).await?;
+        nix::nar_sizes(&output.stdout)
+    }
+
+    /// List the projects in the registry with their GC roots and the store paths they keep alive.
+    pub async fn projects_list(&self) -> Result<()> {
+        let registry = projects::Registry::load(&self.devenv_projects)?;
+        if registry.projects.is_empty() {
+            info!("No projects have been recorded yet.");
+            return Ok(());
+        }
+        let (home_roots, _) = gc::read_roots(&self.devenv_home_gc)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read {}", self.devenv_home_gc.display()))?;
+
+        let span = info_span!(
+            "projects_disk_usage",
+            devenv.user_message = "Computing the disk usage of projects"
+        );
+        let mut rows = Vec::new();
+        async {
+            for (root, project) in &registry.projects {
+                let roots = project.gc_roots(&home_roots)?;
+                let targets: Vec<&Path> = roots.iter().map(|root| root.target.as_path()).collect();
+                let size = match self.closure_sizes(&targets).await {
+                    Ok(sizes) => Some(sizes.values().sum::<u64>()),
+                    Err(e) => {
+                        warn!(
+                            "Failed to compute the disk usage of {}: {e}",
+                            root.display()
+                        );
+                        None
+                    }
+                };
+                rows.push((root, project, roots.len(), size));
+            }
+            Ok::<_, miette::Report>(())
+        }
+        .instrument(span)
+        .await?;
+
+        // The projects that keep the most alive come first
+        rows.sort_by_key(|(_, _, _, size)| std::cmp::Reverse(size.unwrap_or_default()));
+        let now = std::time::SystemTime::now();
+        let rows = rows
+            .into_iter()
+            .map(|(root, project, gc_roots, size)| DevenvProjectResult {
+                project: root.display().to_string(),
+                status: if root.exists() { "" } else { "missing" }.to_string(),
+                last_used: format!(
+                    "{} ago",
+                    HumanDuration(now.duration_since(project.last_used()).unwrap_or_default())
+                ),
+                gc_roots,
+                disk_usage: size
+                    .map(|size| HumanBytes(size).to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+            })
+            .collect::<Vec<_>>();
+        print_stdout(rows.with_title()).expect("Failed to print projects");
+        info!("Store paths shared between projects are counted for each of them.");
+        Ok(())
+    }
+
+    /// Remove a project from the registry, along with its GC roots in devenv's data home.
+    pub async fn projects_forget(&self, path: &Path) -> Result<()> {
+        // The checkout may have been deleted already
+        let root = path
+            .canonicalize()
+            .or_else(|_| std::path::absolute(path))
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to resolve {}", path.display()))?;
+        let Some(project) =
+            projects::Registry::update(&self.devenv_projects, |registry| registry.forget(&root))?
+        else {
+            bail!(
+                "{} is not a known project. See `devenv projects list`.",
+                root.display()
+            );
+        };
+
+        let (home_roots, _) = gc::read_roots(&self.devenv_home_gc)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read {}", self.devenv_home_gc.display()))?;
+        let mut removed = 0;
+        for home_root in home_roots
+            .iter()
+            .filter(|home_root| project.environments.contains(&home_root.target))
+        {
+            fs::remove_file(&home_root.link)
+                .await
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to remove {}", home_root.link.display()))?;
+            removed += 1;
+        }
+
+        info!(
+            "Forgot {} and removed {removed} of its GC roots. Run `devenv gc` to free the space they used.",
+            root.display()
+        );
+        if project.dotfile.exists() {
+            info!(
+                "Its environments are still kept alive by {}, delete it to free them as well.",
+                project.dotfile.display()
+            );
+        }
+        Ok(())
+    }
+
+    #[instrument(
+        skip(self),
+        fields(
+            devenv.user_message = "Searching options and packages",
+        )
+    )]
+    pub async fn search(&self, name: &str, options: &search::SearchOptions) -> Result<()> {
+        self.assemble(false).await?;
+
+        let index = search::SearchIndex::open(&self.devenv_search_index).await?;
+        let mut sources = Vec::new();
+        if !options.options_only {
+            sources.push(self.packages_source(&index).await?);
+        }
+        if !options.packages_only {
+            sources.push(self.options_source(&index).await?);
+        }
+        let mut results = Vec::new();
+        for source in sources {
+            results.extend(index.search(&[source], name, options.limit).await?);
+        }
+
+        let details = if options.details {
+            let attrs = results
+                .iter()
+                .filter(|entry| entry.kind == search::Kind::Package)
+                .map(|entry| entry.name.trim_start_matches("pkgs.").to_string())
+                .collect::<Vec<_>>();
+            let mut details = self.package_details(&attrs).await?;
+            self.check_cached(&mut details).await?;
+            details
+        } else {
+            Vec::new()
+        };
+
+        if options.json {
+            let mut details = details.into_iter();
+            let results = results
+                .iter()
+                .map(|entry| {
+                    let mut result =
+                        serde_json::to_value(entry).expect("Failed to serialize search results");
+                    if entry.kind == search::Kind::Package {
+                        if let Some(details) = details.next() {
+                            result["details"] = serde_json::to_value(details)
+                                .expect("Failed to serialize package details");
+                        }
+                    }
+                    result
+                })
+                .collect::<Vec<_>>();
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&results).expect("Failed to serialize search results")
+            );
+            return Ok(());
+        }
+
+        let (packages, options): (Vec<_>, Vec<_>) = results
+            .into_iter()
+            .partition(|entry| entry.kind == search::Kind::Package);
+        let package_results_count = packages.len();
+        let results_options_count = options.len();
+
+        if !details.is_empty() {
+            let system = &self.global_options.system;
+            for details in &details {
+                print_package_details(details, system);
+                println!();
+            }
+        } else if !packages.is_empty() {
+            let package_results = packages
+                .into_iter()
+                .map(|entry| DevenvPackageResult {
+                    name: entry.name,
+                    version: entry.version.unwrap_or_default(),
+                    description: entry.description.chars().take(80).collect::<String>(),
+                })
+                .collect::<Vec<_>>();
+            print_stderr(package_results.with_title()).expect("Failed to print package results");
+        }
+
+        if !options.is_empty() {
+            let options_results = options
+                .into_iter()
+                .map(|entry| DevenvOptionResult {
+                    name: entry.name,
+                    type_: entry.type_.unwrap_or_default(),
+                    default: entry.default.unwrap_or_default(),
+                    description: entry.description,
+                })
+                .collect::<Vec<_>>();
+            print_stderr(options_results.with_title()).expect("Failed to print options results");
+        }
+
+        info!(
+            "Found {package_results_count} packages and {results_options_count} options for '{name}'."
+        );
+        Ok(())
+    }
+
+    /// Print everything about a package: its metadata, where it's built for and whether it's
+    /// cached in the configured substituters.
+    pub async fn show_package(&self, attr: &str, json: bool) -> Result<()> {
+        self.assemble(false).await?;
+
+        let attr = attr.strip_prefix("pkgs.").unwrap_or(attr);
+        let mut details = self.package_details(&[attr.to_string()]).await?;
+        if !details.iter().any(|details| details.exists) {
+            bail!("Package pkgs.{attr} doesn't exist. Find packages with `devenv search {attr}`.");
+        }
+        self.check_cached(&mut details).await?;
+
+        if json {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&details[0])
+                    .expect("Failed to serialize package details")
+            );
+        } else {
+            print_package_details(&details[0], &self.global_options.system);
+        }
+        Ok(())
     }
 
-    pub async fn repl(&self) -> Result<()> {
+    /// Add a package to `packages` in devenv.nix.
+    ///
+    /// With `confirm`, the change is shown and written only after confirming it.
+    pub async fn add_package(&self, attr: &str, confirm: bool) -> Result<()> {
         self.assemble(false).await?;
-        self.nix.repl().await
+
+        let attr = attr.strip_prefix("pkgs.").unwrap_or(attr);
+        let details = self.package_details(&[attr.to_string()]).await?;
+        if !details.iter().any(|details| details.exists) {
+            bail!("Package pkgs.{attr} doesn't exist. Find packages with `devenv search {attr}`.");
+        }
+
+        let changed = self
+            .edit_devenv_nix(confirm, |document| document.add_package(attr))
+            .await?;
+        if !changed {
+            info!("pkgs.{attr} is already in packages.");
+        }
+        Ok(())
     }
 
-    pub async fn gc(&self) -> Result<()> {
-        let start = std::time::Instant::now();
+    /// Enable a language in devenv.nix, and set its version if one is given.
+    pub async fn add_language(
+        &self,
+        name: &str,
+        version: Option<&str>,
+        confirm: bool,
+    ) -> Result<()> {
+        let mut settings = vec![("enable", cli_options::NixValue::Bool(true))];
+        if let Some(version) = version {
+            settings.push((
+                "version",
+                cli_options::NixValue::String(version.to_string()),
+            ));
+        }
+        self.add_module("languages", "language", name, &settings, confirm)
+            .await
+    }
 
-        let (to_gc, removed_symlinks) = {
-            // TODO: No newline
-            let span = info_span!(
-                "cleanup_symlinks",
-                devenv.user_message = format!(
-                    "Removing non-existing symlinks in {}",
-                    &self.devenv_home_gc.display()
-                )
-            );
-            span.in_scope(|| cleanup_symlinks(&self.devenv_home_gc))
-        };
-        let to_gc_len = to_gc.len();
+    /// Enable a service in devenv.nix.
+    pub async fn add_service(&self, name: &str, confirm: bool) -> Result<()> {
+        let settings = [("enable", cli_options::NixValue::Bool(true))];
+        self.add_module("services", "service", name, &settings, confirm)
+            .await
+    }
 
-        info!("Found {} active environments.", to_gc_len);
-        info!(
-            "Deleted {} dangling environments (most likely due to previous GC).",
-            removed_symlinks.len()
-        );
+    /// Set options of a language or service, like `languages.python.version`, in devenv.nix,
+    /// after checking them against the options declared by the environment.
+    async fn add_module(
+        &self,
+        group: &str,
+        kind: &str,
+        name: &str,
+        settings: &[(&str, cli_options::NixValue)],
+        confirm: bool,
+    ) -> Result<()> {
+        self.assemble(false).await?;
 
-        {
-            let span = info_span!(
-                "nix_gc",
-                devenv.user_message =
-                    "Running garbage collection (this process will take some time)"
-            );
-            info!(
-                "If you'd like this to run faster, leave a thumbs up at https://github.com/NixOS/nix/issues/7239"
+        let options = self.options_json().await?;
+        if !options.0.contains_key(&format!("{group}.{name}.enable")) {
+            let prefix = format!("{group}.");
+            let available = options
+                .0
+                .keys()
+                .filter_map(|option| option.strip_prefix(&prefix)?.strip_suffix(".enable"))
+                .filter(|module| !module.contains('.'))
+                .collect::<Vec<_>>();
+            bail!(
+                "There is no {kind} called {name}. The {kind}s are: {}",
+                available.join(", ")
             );
-            self.nix.gc(to_gc).instrument(span).await?;
         }
-
-        let (after_gc, _) = cleanup_symlinks(&self.devenv_home_gc);
-        let end = std::time::Instant::now();
-
-        // TODO: newline before or after
-        info!(
-            "\nDone. Successfully removed {} symlinks in {}s.",
-            to_gc_len - after_gc.len(),
-            (end - start).as_secs_f32()
-        );
+        let overrides = settings
+            .iter()
+            .map(|(setting, value)| cli_options::OptionOverride {
+                path: vec![group.to_string(), name.to_string(), setting.to_string()],
+                type_name: "json".to_string(),
+                value: value.clone(),
+            })
+            .collect::<Vec<_>>();
+        let declarations = options
+            .0
+            .into_iter()
+            .map(|(option, declaration)| (option, declaration.type_))
+            .collect();
+        cli_options::validate(&overrides, &declarations)
+            .wrap_err_with(|| format!("Failed to set up {group}.{name}"))?;
+
+        let changed = self
+            .edit_devenv_nix(confirm, |document| {
+                let mut changed = false;
+                for option in &overrides {
+                    let path = option.path.iter().map(String::as_str).collect::<Vec<_>>();
+                    // Relative paths are resolved against devenv.nix, which is in the project root.
+                    changed |= document.set(&path, &option.value.to_nix("./."))?;
+                }
+                Ok(changed)
+            })
+            .await?;
+        if !changed {
+            info!("{group}.{name} is already set up like that.");
+        }
         Ok(())
     }
 
-    #[instrument(
-        skip(self),
-        fields(
-            devenv.user_message = "Searching options and packages",
-        )
-    )]
-    pub async fn search(&self, name: &str) -> Result<()> {
-        self.assemble(false).await?;
+    /// Edit devenv.nix, keeping its formatting, and write it back. With `confirm`, the change
+    /// is shown and only written after confirming it.
+    ///
+    /// `edit` returns whether it changed anything, which is returned in turn.
+    async fn edit_devenv_nix(
+        &self,
+        confirm: bool,
+        edit: impl FnOnce(&mut nix_edit::NixDocument) -> Result<bool>,
+    ) -> Result<bool> {
+        let devenv_nix = self.devenv_root.join("devenv.nix");
+        let contents = fs::read_to_string(&devenv_nix)
+            .await
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read {}", devenv_nix.display()))?;
+        let mut document = nix_edit::NixDocument::parse(&contents)
+            .wrap_err_with(|| format!("Failed to edit {}", devenv_nix.display()))?;
+        if !edit(&mut document)
+            .wrap_err_with(|| format!("Failed to edit {}", devenv_nix.display()))?
+        {
+            return Ok(false);
+        }
+        if confirm {
+            confirm_overwrite(&devenv_nix, document.to_string())?;
+        } else {
+            fs::write(&devenv_nix, document.to_string())
+                .await
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to write {}", devenv_nix.display()))?;
+        }
+        Ok(true)
+    }
 
-        // Run both searches concurrently
-        let (options_results, package_results) =
-            tokio::try_join!(self.search_options(name), self.search_packages(name))?;
+    /// Evaluate the metadata of packages in the package set of devenv.nix, which includes its
+    /// overlays, given their attribute paths without the `pkgs.` prefix.
+    async fn package_details(&self, attrs: &[String]) -> Result<Vec<PackageDetails>> {
+        if attrs.is_empty() {
+            return Ok(Vec::new());
+        }
+        if let Some(attr) = attrs.iter().find(|attr| !is_attr_path(attr)) {
+            bail!("{attr} is not an attribute path, like python3Packages.pytest");
+        }
+        let attrs = attrs
+            .iter()
+            .map(|attr| format!("\"{attr}\""))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let apply = indoc::formatdoc! {r#"
+            pkgs:
+            let
+              lib = pkgs.lib;
+              toList = value: if builtins.isList value then value else [ value ];
+              licenseName = license:
+                if builtins.isString license then license
+                else license.spdxId or license.shortName or license.fullName or "unknown";
+              details = attr:
+                let
+                  found = builtins.tryEval (lib.attrByPath (lib.splitString "." attr) null pkgs);
+                  package = found.value;
+                  meta = package.meta or {{ }};
+                  # Unfree and broken packages refuse to evaluate
+                  outPath = builtins.tryEval package.outPath;
+                in
+                if !found.success || !lib.isDerivation package then {{
+                  inherit attr;
+                  exists = false;
+                }} else {{
+                  inherit attr;
+                  exists = true;
+                  name = package.name or null;
+                  version = package.version or null;
+                  description = meta.description or null;
+                  homepage = builtins.head (toList (meta.homepage or [ null ]) ++ [ null ]);
+                  licenses = map licenseName (toList (meta.license or [ ]));
+                  platforms = builtins.filter builtins.isString (meta.platforms or [ ]);
+                  mainProgram = meta.mainProgram or null;
+                  outputs = package.outputs or [ "out" ];
+                  broken = meta.broken or false;
+                  insecure = meta.insecure or false;
+                  unfree = meta.unfree or false;
+                  storePath = if outPath.success then outPath.value else null;
+                }};
+            in
+            map details [ {attrs} ]
+        "#};
 
-        let results_options_count = options_results.len();
-        let package_results_count = package_results.len();
+        let options = nix_backend::Options {
+            logging: false,
+            cache_output: true,
+            ..Default::default()
+        };
+        let output = self
+            .nix
+            .run_nix(
+                "nix",
+                &["eval", "--json", ".#pkgs", "--apply", apply.as_str()],
+                &options,
+            )
+            .await?;
+        serde_json::from_slice(&output.stdout)
+            .into_diagnostic()
+            .wrap_err("Failed to parse the package details")
+    }
 
-        if !package_results.is_empty() {
-            print_stderr(package_results.with_title()).expect("Failed to print package results");
+    /// Find out which of the configured substituters have the store paths of `packages`.
+    async fn check_cached(&self, packages: &mut [PackageDetails]) -> Result<()> {
+        let store_paths = packages
+            .iter()
+            .filter_map(|package| package.store_path.as_deref())
+            .collect::<BTreeSet<_>>();
+        if store_paths.is_empty() {
+            return Ok(());
         }
 
-        if !options_results.is_empty() {
-            print_stderr(options_results.with_title()).expect("Failed to print options results");
+        let substituters = self.nix.substituters().await?;
+        let options = nix_backend::Options {
+            logging: false,
+            // Substituters fail for paths they don't have
+            bail_on_error: false,
+            ..Default::default()
+        };
+        let lookups = substituters.iter().map(|substituter| {
+            let mut args = vec!["path-info", "--json", "--store", substituter.as_str()];
+            args.extend(store_paths.iter().copied());
+            let options = &options;
+            async move { (substituter, self.nix.run_nix("nix", &args, options).await) }
+        });
+        for (substituter, output) in futures::future::join_all(lookups).await {
+            let cached = match output.and_then(|output| nix::nar_sizes(&output.stdout)) {
+                Ok(cached) => cached,
+                Err(e) => {
+                    warn!("Failed to query {substituter}: {e}");
+                    continue;
+                }
+            };
+            for package in packages.iter_mut() {
+                if package
+                    .store_path
+                    .as_ref()
+                    .is_some_and(|path| cached.contains_key(path))
+                {
+                    package.cached_in.push(substituter.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The packages of nixpkgs in the search index, which are indexed again when the locked
+    /// revision of nixpkgs changes. Without a lock, they're indexed again once a day,
+    /// or with --refresh-eval-cache.
+    async fn packages_source(&self, index: &search::SearchIndex) -> Result<i64> {
+        let lock = lock::Lock::load_from(&self.devenv_root)?;
+        let locked = lock.as_ref().and_then(|lock| {
+            lock.root_inputs()
+                .get("nixpkgs")
+                .and_then(|node| node.locked.clone())
+        });
+        let key = match &locked {
+            Some(locked) => match locked.rev.as_ref().or(locked.nar_hash.as_ref()) {
+                Some(rev) => format!(
+                    "packages:{}:{}@{rev}",
+                    self.global_options.system,
+                    locked.source_id()
+                ),
+                None => format!("packages:{}:{locked}", self.global_options.system),
+            },
+            // Without a lock, there's no way to tell whether nixpkgs changed
+            None => format!("packages:{}:unlocked", self.global_options.system),
+        };
+        let max_age = locked.is_none().then_some(search::MAX_UNLOCKED_AGE_SECS);
+        if locked.is_some() || !self.global_options.refresh_eval_cache {
+            if let Some(source) = index.source(&key, max_age).await? {
+                return Ok(source);
+            }
         }
 
-        info!(
-            "Found {package_results_count} packages and {results_options_count} options for '{name}'."
+        let description = locked
+            .map(|locked| locked.describe())
+            .unwrap_or_else(|| "nixpkgs".to_string());
+        let span = info_span!(
+            "index_packages",
+            devenv.user_message = format!(
+                "Indexing the packages of nixpkgs {description} (this only happens when nixpkgs changes)"
+            )
         );
-        Ok(())
+        async {
+            let search_options = nix_backend::Options {
+                logging: false,
+                ..Default::default()
+            };
+            let search = self.nix.search("^", Some(search_options)).await?;
+            let search_json: PackageResults = serde_json::from_slice(&search.stdout)
+                .into_diagnostic()
+                .wrap_err("Failed to parse search results")?;
+            let entries = search_json
+                .0
+                .into_iter()
+                .map(|(key, value)| search::Entry {
+                    kind: search::Kind::Package,
+                    name: format!(
+                        "pkgs.{}",
+                        key.split('.').skip(2).collect::<Vec<_>>().join(".")
+                    ),
+                    description: value.description,
+                    version: Some(value.version),
+                    type_: None,
+                    default: None,
+                })
+                .collect::<Vec<_>>();
+            index.index(&key, &entries).await
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// The options declared by the environment in the search index, keyed by the store path of
+    /// their documentation, which changes whenever they do.
+    async fn options_source(&self, index: &search::SearchIndex) -> Result<i64> {
+        let options_path = self.options_json_path().await?;
+        let key = format!("options:{}", options_path.display());
+        if let Some(source) = index.source(&key, None).await? {
+            return Ok(source);
+        }
+
+        let options_json = read_options_json(&options_path).await?;
+        let entries = options_json
+            .0
+            .into_iter()
+            .map(|(name, option)| search::Entry {
+                kind: search::Kind::Option,
+                name,
+                description: option.description,
+                version: None,
+                type_: Some(option.type_),
+                default: Some(option.default.unwrap_or_default()),
+            })
+            .collect::<Vec<_>>();
+        index.index(&key, &entries).await
+    }
+
+    /// Build the documentation of the options declared by the environment.
+    async fn options_json(&self) -> Result<OptionResults> {
+        read_options_json(&self.options_json_path().await?).await
     }
 
-    async fn search_options(&self, name: &str) -> Result<Vec<DevenvOptionResult>> {
+    /// Build the documentation of the options declared by the environment and return the path
+    /// to its options.json.
+    async fn options_json_path(&self) -> Result<PathBuf> {
         let build_options = nix_backend::Options {
             logging: false,
             cache_output: true,
@@ -650,55 +1983,32 @@ impl Devenv {
             .nix
             .build(&["optionsJSON"], Some(build_options), None)
             .await?;
-        let options_path = options[0]
+        Ok(options[0]
             .join("share")
             .join("doc")
             .join("nixos")
-            .join("options.json");
-        let options_contents = fs::read(options_path)
-            .await
-            .expect("Failed to read options.json");
-        let options_json: OptionResults =
-            serde_json::from_slice(&options_contents).expect("Failed to parse options.json");
-
-        let options_results = options_json
-            .0
-            .into_iter()
-            .filter(|(key, _)| key.contains(name))
-            .map(|(key, value)| DevenvOptionResult {
-                name: key,
-                type_: value.type_,
-                default: value.default.unwrap_or_default(),
-                description: value.description,
-            })
-            .collect::<Vec<_>>();
-
-        Ok(options_results)
+            .join("options.json"))
     }
 
-    async fn search_packages(&self, name: &str) -> Result<Vec<DevenvPackageResult>> {
-        let search_options = nix_backend::Options {
-            logging: false,
-            cache_output: true,
-            ..Default::default()
+    /// Check the `--option` overrides against the options declared by the environment.
+    async fn validate_option_overrides(
+        &self,
+        overrides: &[cli_options::OptionOverride],
+    ) -> Result<()> {
+        // Evaluation errors are reported by the command itself, with more context.
+        let options_json = match self.options_json().await {
+            Ok(options_json) => options_json,
+            Err(e) => {
+                debug!("Skipping validation of --option overrides: {e}");
+                return Ok(());
+            }
         };
-        let search = self.nix.search(name, Some(search_options)).await?;
-        let search_json: PackageResults =
-            serde_json::from_slice(&search.stdout).expect("Failed to parse search results");
-        let search_results = search_json
+        let declarations = options_json
             .0
             .into_iter()
-            .map(|(key, value)| DevenvPackageResult {
-                name: format!(
-                    "pkgs.{}",
-                    key.split('.').skip(2).collect::<Vec<_>>().join(".")
-                ),
-                version: value.version,
-                description: value.description.chars().take(80).collect::<String>(),
-            })
-            .collect::<Vec<_>>();
-
-        Ok(search_results)
+            .map(|(name, option)| (name, option.type_))
+            .collect();
+        cli_options::validate(overrides, &declarations).wrap_err("Invalid --option override")
     }
 
     pub async fn has_processes(&self) -> Result<bool> {
@@ -917,12 +2227,24 @@ impl Devenv {
     }
 
     pub async fn info(&self) -> Result<()> {
+        // The inputs come straight from the lock file, so they don't need an evaluation.
+        if let Some(lock) = lock::Lock::load_from(&self.devenv_root)? {
+            println!("{}", lock.render_tree());
+            self.warn_about_lock(&lock);
+        }
+
         self.assemble(false).await?;
         let output = self.nix.metadata().await?;
         println!("{}", output);
         Ok(())
     }
 
+    fn warn_about_lock(&self, lock: &lock::Lock) {
+        for warning in lock.warnings(lock::now()) {
+            warn!("{warning}");
+        }
+    }
+
     pub async fn build(&self, attributes: &[String]) -> Result<()> {
         let span = info_span!("build", devenv.user_message = "Building");
         async move {
@@ -1064,6 +2386,7 @@ impl Devenv {
                 }
                 info!("Stop:      $ devenv processes stop");
             } else {
+                crate::log::flush_before_exec();
                 let err = cmd.into_std().exec();
                 bail!(err);
             }
@@ -1102,6 +2425,46 @@ impl Devenv {
         Ok(())
     }
 
+    /// Make sure that the imports from outside of the project can be loaded.
+    ///
+    /// Nix only sees the files in the git repository that the project is part of,
+    /// so `../` imports have to exist and stay within that repository.
+    fn check_parent_imports(&self, config: &config::Config) -> Result<()> {
+        let imports = config.parent_imports(&self.devenv_root);
+        let Some((first, _)) = imports.first() else {
+            return Ok(());
+        };
+
+        let root = self
+            .devenv_root
+            .canonicalize()
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to resolve {}", self.devenv_root.display()))?;
+        let Some(repository) = root.ancestors().find(|dir| dir.join(".git").exists()) else {
+            bail!(
+                "Import {first} is outside of the project, which requires {} to be in a git repository.",
+                root.display()
+            );
+        };
+
+        for (import, module) in &imports {
+            let Ok(resolved) = module.canonicalize() else {
+                bail!(
+                    "Import {import} not found: {} does not exist.",
+                    module.display()
+                );
+            };
+            if !resolved.starts_with(repository) {
+                bail!(
+                    "Import {import} resolves to {}, which is outside of the git repository at {}.",
+                    resolved.display(),
+                    repository.display()
+                );
+            }
+        }
+        Ok(())
+    }
+
     pub async fn assemble(&self, is_testing: bool) -> Result<()> {
         if self.assembled.load(Ordering::Acquire) {
             return Ok(());
@@ -1125,8 +2488,38 @@ impl Devenv {
         // Initialise any Nix state
         self.nix.assemble().await?;
 
+        let config = self
+            .config
+            .read()
+            .await
+            .with_profile(self.global_options.profile.as_deref());
+        self.check_parent_imports(&config)?;
+
+        // Warn about inputs that bring in their own copy of a top-level input, like nixpkgs.
+        match lock::Lock::load_from(&self.devenv_root) {
+            Ok(Some(lock)) => {
+                // Only mention the inputs that can be fixed in devenv.yaml.
+                let duplicates = lock
+                    .duplicate_inputs()
+                    .into_iter()
+                    .filter(|d| config.inputs.contains_key(&d.input))
+                    .collect::<Vec<_>>();
+                if !duplicates.is_empty() {
+                    warn!(
+                        "Some inputs lock their own copy of another input, which slows down evaluation: {}. Run `devenv inputs dedupe` to make them follow the top-level inputs.",
+                        duplicates
+                            .iter()
+                            .map(|d| format!("{}/{}", d.input, d.nested))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    );
+                }
+            }
+            Ok(None) => {}
+            Err(e) => debug!("Failed to read devenv.lock: {e}"),
+        }
+
         let mut flake_inputs = BTreeMap::new();
-        let config = self.config.read().await;
         for (input, attrs) in config.inputs.iter() {
             match config::FlakeInput::try_from(attrs) {
                 Ok(flake_input) => {
@@ -1144,7 +2537,7 @@ impl Devenv {
         )?;
         util::write_file_with_lock(
             self.devenv_dotfile.join("devenv.json"),
-            serde_json::to_string(&*config).unwrap(),
+            serde_json::to_string(&config).unwrap(),
         )?;
         // TODO: superceded by eval caching.
         // Remove once direnvrc migration is implemented.
@@ -1160,67 +2553,19 @@ impl Devenv {
             })?;
 
         // Create cli-options.nix if there are CLI options
-        if !self.global_options.option.is_empty() {
-            let mut cli_options = String::from("{ pkgs, lib, config, ... }: {\n");
-
-            const SUPPORTED_TYPES: &[&str] =
-                &["string", "int", "float", "bool", "path", "pkg", "pkgs"];
-
-            for chunk in self.global_options.option.chunks_exact(2) {
-                // Parse the path and type from the first value
-                let key_parts: Vec<&str> = chunk[0].split(':').collect();
-                if key_parts.len() < 2 {
-                    miette::bail!("Invalid option format: '{}'. Must include type, e.g. 'languages.rust.version:string'. Supported types: {}",
-                           chunk[0], SUPPORTED_TYPES.join(", "));
-                }
-
-                let path = key_parts[0];
-                let type_name = key_parts[1];
-
-                // Format value based on type
-                let value = match type_name {
-                    "string" => format!("\"{}\"", &chunk[1]),
-                    "int" => chunk[1].clone(),
-                    "float" => chunk[1].clone(),
-                    "bool" => chunk[1].clone(), // true/false will work directly in Nix
-                    "path" => format!("./{}", &chunk[1]), // relative path
-                    "pkg" => format!("pkgs.{}", &chunk[1]),
-                    "pkgs" => {
-                        // Split by whitespace and format as a Nix list of package references
-                        let items = chunk[1]
-                            .split_whitespace()
-                            .map(|item| format!("pkgs.{}", item))
-                            .collect::<Vec<_>>()
-                            .join(" ");
-                        format!("[ {} ]", items)
-                    }
-                    _ => miette::bail!(
-                        "Unsupported type: '{}'. Supported types: {}",
-                        type_name,
-                        SUPPORTED_TYPES.join(", ")
-                    ),
-                };
-
-                // Use lib.mkForce for all types except pkgs
-                let final_value = if type_name == "pkgs" {
-                    value
-                } else {
-                    format!("lib.mkForce {}", value)
-                };
-                cli_options.push_str(&format!("  {} = {};\n", path, final_value));
-            }
-
-            cli_options.push_str("}\n");
-
-            util::write_file_with_lock(self.devenv_dotfile.join("cli-options.nix"), &cli_options)?;
-        } else {
+        let option_overrides = cli_options::parse_overrides(&self.global_options.option)?;
+        let cli_options_path = self.devenv_dotfile.join("cli-options.nix");
+        if !option_overrides.is_empty() {
+            let root = cli_options::root_expr(&self.devenv_root, &self.devenv_dotfile);
+            util::write_file_with_lock(
+                &cli_options_path,
+                cli_options::render_module(&option_overrides, &root),
+            )?;
+        } else if cli_options_path.exists() {
             // Remove the file if it exists but there are no CLI options
-            let cli_options_path = self.devenv_dotfile.join("cli-options.nix");
-            if cli_options_path.exists() {
-                fs::remove_file(&cli_options_path)
-                    .await
-                    .expect("Failed to remove cli-options.nix");
-            }
+            fs::remove_file(&cli_options_path)
+                .await
+                .expect("Failed to remove cli-options.nix");
         }
 
         // create flake.devenv.nix
@@ -1254,10 +2599,42 @@ impl Devenv {
         let flake_path = self.devenv_root.join(DEVENV_FLAKE);
         util::write_file_with_lock(&flake_path, &flake)?;
 
+        // Only check the overrides from the command line. Checking evaluates every option
+        // declaration, which is too slow to repeat on each run for the ones in the config files.
+        let mut config_options = config.option_args();
+        if let Some(profile) = &self.global_options.profile {
+            config_options.extend(config.profile_options(profile)?);
+        }
+        let command_line_options = self
+            .global_options
+            .option
+            .strip_prefix(config_options.as_slice())
+            .unwrap_or(&self.global_options.option);
+        if !command_line_options.is_empty() {
+            self.validate_option_overrides(&cli_options::parse_overrides(command_line_options)?)
+                .await?;
+        }
+
         self.assembled.store(true, Ordering::Release);
+        self.record_project(None);
         Ok(())
     }
 
+    /// Remember this project in the registry, so that `devenv projects` can find its GC roots.
+    fn record_project(&self, environment: Option<&Path>) {
+        let result = projects::Registry::update(&self.devenv_projects, |registry| {
+            registry.record(
+                &self.devenv_root,
+                &self.devenv_dotfile,
+                environment,
+                std::time::SystemTime::now(),
+            )
+        });
+        if let Err(e) = result {
+            warn!("Failed to update the project registry: {e}");
+        }
+    }
+
     #[instrument(skip_all,fields(devenv.user_message = "Building shell"))]
     pub async fn get_dev_environment(&self, json: bool) -> Result<DevEnv> {
         self.assemble(false).await?;
@@ -1265,6 +2642,9 @@ impl Devenv {
         let gc_root = self.devenv_dot_gc.join("shell");
         let span = tracing::debug_span!("evaluating_dev_env");
         let env = self.nix.dev_env(json, &gc_root).instrument(span).await?;
+        if let Ok(environment) = std::fs::canonicalize(&gc_root) {
+            self.record_project(Some(&environment));
+        }
 
         use devenv_eval_cache::command::{FileInputDesc, Input};
         util::write_file_with_lock(
@@ -1292,6 +2672,79 @@ impl Devenv {
     }
 }
 
+async fn read_options_json(path: &Path) -> Result<OptionResults> {
+    let options_contents = fs::read(path)
+        .await
+        .into_diagnostic()
+        .wrap_err("Failed to read options.json")?;
+    serde_json::from_slice(&options_contents)
+        .into_diagnostic()
+        .wrap_err("Failed to parse options.json")
+}
+
+/// Whether `attr` is a plain attribute path, like `python3Packages.pytest`, that can be put in
+/// a Nix string as is.
+fn is_attr_path(attr: &str) -> bool {
+    !attr.is_empty()
+        && attr.split('.').all(|name| {
+            !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '\'' | '+'))
+        })
+}
+
+fn print_package_details(details: &PackageDetails, system: &str) {
+    let version = details.version.as_deref().unwrap_or_default();
+    println!("Package: pkgs.{} {version}", details.attr);
+    if let Some(description) = &details.description {
+        println!("Description: {description}");
+    }
+    if let Some(homepage) = &details.homepage {
+        println!("Homepage: {homepage}");
+    }
+    if !details.licenses.is_empty() {
+        println!("License: {}", details.licenses.join(", "));
+    }
+    if let Some(main_program) = &details.main_program {
+        println!("Main program: {main_program}");
+    }
+    println!("Outputs: {}", details.outputs.join(", "));
+    if !details.platforms.is_empty() {
+        let supported = if details.platforms.iter().any(|platform| platform == system) {
+            "including"
+        } else {
+            "but not"
+        };
+        println!(
+            "Platforms: {} systems, {supported} {system}",
+            details.platforms.len()
+        );
+    }
+    let status = [
+        (details.broken, "broken"),
+        (details.insecure, "insecure"),
+        (details.unfree, "unfree"),
+    ]
+    .into_iter()
+    .filter_map(|(flag, status)| flag.then_some(status))
+    .collect::<Vec<_>>();
+    if !status.is_empty() {
+        println!("Status: {}", status.join(", "));
+    }
+    match &details.store_path {
+        Some(store_path) => {
+            println!("Store path: {store_path}");
+            if details.cached_in.is_empty() {
+                println!("Cached: no, it will be built locally");
+            } else {
+                println!("Cached: {}", details.cached_in.join(", "));
+            }
+        }
+        None => println!("Store path: none, the package doesn't evaluate for {system}"),
+    }
+}
+
 fn confirm_overwrite(file: &Path, contents: String) -> Result<()> {
     if std::fs::metadata(file).is_ok() {
         // first output the old version and propose new changes
@@ -1362,6 +2815,18 @@ struct DevenvOptionResult {
     description: String,
 }
 
+#[derive(Table)]
+struct DevenvInputResult {
+    #[table(title = "Input")]
+    name: String,
+    #[table(title = "URL")]
+    url: String,
+    #[table(title = "Revision")]
+    rev: String,
+    #[table(title = "Last modified")]
+    age: String,
+}
+
 #[derive(Table)]
 struct DevenvPackageResult {
     #[table(title = "Package")]
@@ -1372,32 +2837,105 @@ struct DevenvPackageResult {
     description: String,
 }
 
-fn sanitize_container_name(name: &str) -> String {
-    name.chars()
-        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
-        .collect::<String>()
+/// The metadata of a package, as evaluated by `Devenv::package_details`.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PackageDetails {
+    attr: String,
+    exists: bool,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    version: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    homepage: Option<String>,
+    #[serde(default)]
+    licenses: Vec<String>,
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    main_program: Option<String>,
+    #[serde(default)]
+    outputs: Vec<String>,
+    #[serde(default)]
+    broken: bool,
+    #[serde(default)]
+    insecure: bool,
+    #[serde(default)]
+    unfree: bool,
+    #[serde(default)]
+    store_path: Option<String>,
+    /// The substituters that have the store path, which isn't part of the evaluation.
+    #[serde(default)]
+    cached_in: Vec<String>,
 }
 
-fn cleanup_symlinks(root: &Path) -> (Vec<PathBuf>, Vec<PathBuf>) {
-    let mut to_gc = Vec::new();
-    let mut removed_symlinks = Vec::new();
+/// A container, as summarized by `devenv.container.summary`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ContainerSummary {
+    name: Option<String>,
+    #[serde(default)]
+    entrypoint: Vec<String>,
+    startup_command: Option<String>,
+    registry: Option<String>,
+    version: Option<String>,
+}
 
-    if !root.exists() {
-        std::fs::create_dir_all(root).expect("Failed to create gc directory");
-    }
+#[derive(Table)]
+struct DevenvProjectResult {
+    #[table(title = "Project")]
+    project: String,
+    #[table(title = "Status")]
+    status: String,
+    #[table(title = "Last used")]
+    last_used: String,
+    #[table(title = "GC roots")]
+    gc_roots: usize,
+    #[table(title = "Disk usage")]
+    disk_usage: String,
+}
 
-    for entry in std::fs::read_dir(root).expect("Failed to read directory") {
-        let entry = entry.expect("Failed to read entry");
-        let path = entry.path();
-        if path.is_symlink() {
-            if !path.exists() {
-                removed_symlinks.push(path.clone());
-            } else {
-                let target = std::fs::canonicalize(&path).expect("Failed to read link");
-                to_gc.push(target);
-            }
-        }
-    }
+#[derive(Table)]
+struct DevenvContainerResult {
+    #[table(title = "Container")]
+    name: String,
+    #[table(title = "Image")]
+    image: String,
+    #[table(title = "Version")]
+    version: String,
+    #[table(title = "Registry")]
+    registry: String,
+    #[table(title = "Startup command")]
+    startup_command: String,
+    #[table(title = "Entrypoint")]
+    entrypoint: String,
+}
+
+#[derive(Table)]
+struct DevenvContainerLayerResult {
+    #[table(title = "Layer")]
+    layer: usize,
+    #[table(title = "Store paths")]
+    paths: String,
+    #[table(title = "Size")]
+    size: String,
+}
+
+#[derive(Table)]
+struct DevenvContainerPathResult {
+    #[table(title = "Store path")]
+    path: String,
+    #[table(title = "Layer")]
+    layer: usize,
+    #[table(title = "Size")]
+    size: String,
+}
 
-    (to_gc, removed_symlinks)
+fn sanitize_container_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric() || *c == '-' || *c == '_')
+        .collect::<String>()
 }
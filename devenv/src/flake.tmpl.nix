@@ -46,7 +46,9 @@
             then ./. + (builtins.substring 1 255 path)
             else ./. + (builtins.substring 1 255 path) + "/devenv.nix"
             else if lib.hasPrefix "../" path
-            then throw "devenv: ../ is not supported for imports"
+            then if lib.hasSuffix ".nix" path
+            then ./. + "/${path}"
+            else ./. + "/${path}/devenv.nix"
             else
               let
                 paths = lib.splitString "/" path;
@@ -144,6 +146,8 @@
             ci = config.ciDerivation;
           });
           devenv = config;
+          # The package set of devenv.nix, including overlays, for `devenv show-package`.
+          pkgs = config._module.args.pkgs;
           build = build project.options project.config;
         };
       }
@@ -0,0 +1,242 @@
+//! Choose which environments `devenv gc` deletes.
+//!
+//! Every environment that devenv builds is kept alive by a symlink, either in the GC directory
+//! of devenv's data home or in `.devenv/gc` of a project. Symlinks with a timestamp in their name
+//! record the history of a project, while the others point at its current environment.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug, Default)]
+pub struct GcOptions {
+    /// Only show what would be deleted.
+    pub dry_run: bool,
+    /// Keep the newest environments of each kind.
+    pub keep_generations: Option<usize>,
+    /// Only delete environments that are older than this.
+    pub older_than: Option<Duration>,
+    /// Only delete the old environments of the current project.
+    pub project: bool,
+}
+
+/// A symlink that keeps an environment alive.
+#[derive(Clone, Debug)]
+pub struct GcRoot {
+    pub link: PathBuf,
+    /// The store path that the link points at.
+    pub target: PathBuf,
+    /// The kind of environment, like `shell`, that generations are counted in.
+    pub group: String,
+    pub created: SystemTime,
+    /// Whether the link records a previous environment, rather than the current one.
+    pub is_history: bool,
+}
+
+/// Parse an age like `30d`, `12h`, `45m`, `2w` or `90s`.
+pub fn parse_age(age: &str) -> Result<Duration, String> {
+    let age = age.trim();
+    let split = age
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("{age} is missing a unit, like 30d"))?;
+    let (value, unit) = age.split_at(split);
+    let value: u64 = value
+        .parse()
+        .map_err(|_| format!("{age} doesn't start with a number"))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("Unknown unit {unit} in {age}, use s, m, h, d or w")),
+    };
+    value
+        .checked_mul(seconds)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("{age} is too long"))
+}
+
+/// Read the links in `dir`. Links that no longer point at anything are returned separately.
+pub fn read_roots(dir: &Path) -> io::Result<(Vec<GcRoot>, Vec<PathBuf>)> {
+    let mut roots = Vec::new();
+    let mut dangling = Vec::new();
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((roots, dangling)),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let link = entry.path();
+        if !entry.file_type()?.is_symlink() {
+            continue;
+        }
+        let Ok(target) = std::fs::canonicalize(&link) else {
+            dangling.push(link);
+            continue;
+        };
+        let name = entry.file_name().to_string_lossy().to_string();
+        let (group, timestamp) = parse_root_name(&name);
+        let created = match timestamp {
+            Some(created) => created,
+            None => std::fs::symlink_metadata(&link)?.modified()?,
+        };
+        roots.push(GcRoot {
+            link,
+            target,
+            group,
+            created,
+            is_history: timestamp.is_some(),
+        });
+    }
+    roots.sort_by(|a, b| a.link.cmp(&b.link));
+    Ok((roots, dangling))
+}
+
+/// Split the name of a link into its group and the time in it.
+///
+/// Names are `<timestamp>-<group>` or `<group>-<timestamp>`, with timestamps like `1712345678.123456789`.
+fn parse_root_name(name: &str) -> (String, Option<SystemTime>) {
+    if let Some((timestamp, group)) = name.split_once('-') {
+        if let Some(time) = parse_timestamp(timestamp) {
+            return (group.to_string(), Some(time));
+        }
+    }
+    if let Some((group, timestamp)) = name.rsplit_once('-') {
+        if let Some(time) = parse_timestamp(timestamp) {
+            return (group.to_string(), Some(time));
+        }
+    }
+    (name.to_string(), None)
+}
+
+fn parse_timestamp(timestamp: &str) -> Option<SystemTime> {
+    let (secs, nanos) = timestamp.split_once('.')?;
+    let secs = secs.parse().ok()?;
+    let nanos = nanos.parse().ok()?;
+    Some(UNIX_EPOCH + Duration::new(secs, nanos))
+}
+
+/// The roots to delete: all but the newest `keep_generations` of each group,
+/// that are older than `older_than`.
+pub fn select(
+    roots: &[GcRoot],
+    keep_generations: Option<usize>,
+    older_than: Option<Duration>,
+    now: SystemTime,
+) -> Vec<&GcRoot> {
+    let mut groups: BTreeMap<&str, Vec<&GcRoot>> = BTreeMap::new();
+    for root in roots {
+        groups.entry(&root.group).or_default().push(root);
+    }
+
+    let mut selected = Vec::new();
+    for mut group in groups.into_values() {
+        group.sort_by_key(|root| std::cmp::Reverse(root.created));
+        for root in group.into_iter().skip(keep_generations.unwrap_or(0)) {
+            let age = now.duration_since(root.created).unwrap_or_default();
+            if older_than.is_none_or(|older_than| age > older_than) {
+                selected.push(root);
+            }
+        }
+    }
+    selected.sort_by(|a, b| a.link.cmp(&b.link));
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root(name: &str, age: Duration, now: SystemTime) -> GcRoot {
+        GcRoot {
+            link: PathBuf::from(name),
+            target: PathBuf::from(format!("/nix/store/{name}")),
+            group: parse_root_name(name).0,
+            created: now - age,
+            is_history: true,
+        }
+    }
+
+    #[test]
+    fn parses_ages() {
+        assert_eq!(parse_age("30d"), Ok(Duration::from_secs(30 * 24 * 60 * 60)));
+        assert_eq!(parse_age("12h"), Ok(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(parse_age("2w"), Ok(Duration::from_secs(14 * 24 * 60 * 60)));
+        assert!(parse_age("30").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("30y").is_err());
+        assert!(parse_age("18446744073709551615w").is_err());
+    }
+
+    #[test]
+    fn parses_root_names() {
+        let (group, time) = parse_root_name("1712345678.5-shell");
+        assert_eq!(group, "shell");
+        assert_eq!(time, Some(UNIX_EPOCH + Duration::new(1712345678, 5)));
+
+        let (group, time) = parse_root_name("container-shell-1712345678.5");
+        assert_eq!(group, "container-shell");
+        assert!(time.is_some());
+
+        assert_eq!(parse_root_name("shell"), ("shell".to_string(), None));
+        assert_eq!(
+            parse_root_name("container-shell-x86_64-linux-derivation"),
+            ("container-shell-x86_64-linux-derivation".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn keeps_generations_and_recent_roots() {
+        let now = SystemTime::now();
+        let day = Duration::from_secs(24 * 60 * 60);
+        let roots = vec![
+            root("1.0-shell", day, now),
+            root("2.0-shell", 10 * day, now),
+            root("3.0-shell", 40 * day, now),
+            root("build-1.0", 50 * day, now),
+        ];
+        let links = |selected: Vec<&GcRoot>| {
+            selected
+                .into_iter()
+                .map(|root| root.link.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(links(select(&roots, None, None, now)).len(), 4);
+        assert_eq!(
+            links(select(&roots, Some(1), None, now)),
+            vec!["2.0-shell", "3.0-shell"]
+        );
+        assert_eq!(
+            links(select(&roots, None, Some(30 * day), now)),
+            vec!["3.0-shell", "build-1.0"]
+        );
+        assert_eq!(
+            links(select(&roots, Some(2), Some(5 * day), now)),
+            vec!["3.0-shell"]
+        );
+    }
+
+    #[test]
+    fn reads_roots_and_dangling_links() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("target");
+        std::fs::create_dir(&target).unwrap();
+        let gc = dir.path().join("gc");
+        std::fs::create_dir(&gc).unwrap();
+        std::os::unix::fs::symlink(&target, gc.join("shell")).unwrap();
+        std::os::unix::fs::symlink(&target, gc.join("shell-1712345678.5")).unwrap();
+        std::os::unix::fs::symlink(dir.path().join("gone"), gc.join("1.0-shell")).unwrap();
+
+        let (roots, dangling) = read_roots(&gc).unwrap();
+        assert_eq!(dangling, vec![gc.join("1.0-shell")]);
+        assert_eq!(roots.len(), 2);
+        assert!(!roots[0].is_history);
+        assert!(roots[1].is_history);
+        assert_eq!(roots[1].group, "shell");
+        assert_eq!(roots[1].target, target.canonicalize().unwrap());
+    }
+}
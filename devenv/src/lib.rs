@@ -1,13 +1,27 @@
+mod builders;
 pub mod cli;
+mod cli_options;
 pub mod config;
 mod devenv;
+pub mod gc;
+pub mod lock;
 pub mod log;
 pub mod mcp;
 pub(crate) mod nix;
 pub mod nix_backend;
+mod nix_edit;
+mod nix_progress;
+pub mod oci;
+#[cfg(feature = "otel")]
+mod otel;
+pub mod projects;
+pub mod registry;
+pub mod search;
 #[cfg(feature = "snix")]
 pub(crate) mod snix_backend;
 mod util;
+pub mod workspace;
+mod yaml_edit;
 
 pub use cli::{default_system, GlobalOptions};
 pub use devenv::{Devenv, DevenvOptions, ProcessOptions, DIRENVRC, DIRENVRC_VERSION};
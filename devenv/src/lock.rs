@@ -0,0 +1,903 @@
+//! A typed model of devenv.lock.
+//!
+//! devenv.lock is a Nix flake lock file: a graph of nodes keyed by name, starting at `root`.
+//! Each node maps its input names either to another node or to a `follows` path through the
+//! inputs of the root node.
+//!
+//! Reading the lock file directly lets us describe the inputs without evaluating anything.
+
+use miette::{IntoDiagnostic, Result, WrapErr};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const LOCK_FILE: &str = "devenv.lock";
+
+/// Inputs that haven't been updated for this long are reported as stale.
+pub const STALE_AFTER_SECS: u64 = 180 * 24 * 60 * 60;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Lock {
+    pub nodes: BTreeMap<String, Node>,
+    pub root: String,
+    pub version: u32,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Node {
+    #[serde(default)]
+    pub inputs: BTreeMap<String, InputRef>,
+    pub locked: Option<FlakeRef>,
+    pub original: Option<FlakeRef>,
+    #[serde(default = "true_default")]
+    pub flake: bool,
+}
+
+/// Where a node's input points to.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum InputRef {
+    /// The name of another node.
+    Node(String),
+    /// A path of input names starting at the root node.
+    Follows(Vec<String>),
+}
+
+/// A locked or original flake reference, in its attribute set form.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FlakeRef {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub owner: Option<String>,
+    pub repo: Option<String>,
+    pub host: Option<String>,
+    pub url: Option<String>,
+    pub path: Option<String>,
+    pub id: Option<String>,
+    pub dir: Option<String>,
+    #[serde(rename = "ref")]
+    pub ref_: Option<String>,
+    pub rev: Option<String>,
+    pub nar_hash: Option<String>,
+    pub last_modified: Option<u64>,
+}
+
+/// A problem with the locked inputs worth telling the user about.
+#[derive(Debug, PartialEq, Eq)]
+pub enum LockWarning {
+    /// A top-level input hasn't been updated in a long time.
+    Stale { input: String, last_modified: u64 },
+    /// Several revisions of nixpkgs are locked, keyed by the short revision.
+    /// Each revision is instantiated separately, which costs evaluation time and disk space.
+    DuplicateNixpkgs {
+        revisions: BTreeMap<String, Vec<String>>,
+    },
+}
+
+/// A nested input that locks its own copy of one of the top-level inputs.
+///
+/// Making it follow the top-level input avoids evaluating and fetching the same source twice.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DuplicateInput {
+    /// The top-level input that brings in the duplicate.
+    pub input: String,
+    /// The name of the duplicate within that input.
+    pub nested: String,
+    /// The top-level input it should follow.
+    pub follows: String,
+}
+
+fn true_default() -> bool {
+    true
+}
+
+impl Lock {
+    /// Read the lock file from the given project directory.
+    /// Returns `None` if the project hasn't been locked yet.
+    pub fn load_from<P>(path: P) -> Result<Option<Self>>
+    where
+        P: AsRef<Path>,
+    {
+        let file = path.as_ref().join(LOCK_FILE);
+        let contents = match std::fs::read_to_string(&file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to read {}", file.display()))
+            }
+        };
+        Self::parse(&contents)
+            .wrap_err_with(|| format!("Failed to parse {}", file.display()))
+            .map(Some)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        serde_json::from_str(contents).into_diagnostic()
+    }
+
+    /// The locked revision of each top-level input, keyed by input name.
+    pub fn root_inputs(&self) -> BTreeMap<&str, &Node> {
+        let Some(root) = self.nodes.get(&self.root) else {
+            return BTreeMap::new();
+        };
+        root.inputs
+            .iter()
+            .filter_map(|(name, input)| Some((name.as_str(), self.resolve(input)?)))
+            .collect()
+    }
+
+    /// Find the node an input points to, following `follows` paths from the root.
+    ///
+    /// Returns `None` if the input doesn't exist, or if it ends up following itself
+    /// in a malformed lock file.
+    pub fn resolve(&self, input: &InputRef) -> Option<&Node> {
+        self.resolve_following(input, &mut Vec::new())
+    }
+
+    /// Resolve an input, given the `follows` paths that are already being resolved.
+    fn resolve_following(
+        &self,
+        input: &InputRef,
+        following: &mut Vec<Vec<String>>,
+    ) -> Option<&Node> {
+        match input {
+            InputRef::Node(name) => self.nodes.get(name),
+            InputRef::Follows(path) => {
+                if following.contains(path) {
+                    return None;
+                }
+                following.push(path.clone());
+                let mut node = self.nodes.get(&self.root)?;
+                for name in path {
+                    node = self.resolve_following(node.inputs.get(name)?, following)?;
+                }
+                following.pop();
+                Some(node)
+            }
+        }
+    }
+
+    /// Every node reachable from the root without going through a `follows`,
+    /// as `(input path, node name)` pairs, e.g. `("git-hooks/nixpkgs", "nixpkgs_2")`.
+    pub fn input_paths(&self) -> Vec<(String, &str)> {
+        fn walk<'a>(
+            lock: &'a Lock,
+            node: &'a Node,
+            prefix: &str,
+            depth: usize,
+            paths: &mut Vec<(String, &'a str)>,
+        ) {
+            if depth > lock.nodes.len() {
+                return;
+            }
+            for (name, input) in &node.inputs {
+                let InputRef::Node(node_name) = input else {
+                    continue;
+                };
+                let Some(child) = lock.nodes.get(node_name) else {
+                    continue;
+                };
+                let path = if prefix.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{prefix}/{name}")
+                };
+                paths.push((path.clone(), node_name.as_str()));
+                walk(lock, child, &path, depth + 1, paths);
+            }
+        }
+
+        let mut paths = Vec::new();
+        if let Some(root) = self.nodes.get(&self.root) {
+            walk(self, root, "", 0, &mut paths);
+        }
+        paths
+    }
+
+    /// Render the inputs as a tree, in the style of `nix flake metadata`.
+    pub fn render_tree(&self) -> String {
+        fn render(lock: &Lock, node: &Node, prefix: &str, depth: usize, out: &mut String) {
+            if depth > lock.nodes.len() {
+                return;
+            }
+            let count = node.inputs.len();
+            for (i, (name, input)) in node.inputs.iter().enumerate() {
+                let last = i + 1 == count;
+                let branch = if last { "└───" } else { "├───" };
+                match input {
+                    InputRef::Follows(path) => {
+                        let _ = writeln!(
+                            out,
+                            "{prefix}{branch}{name} follows input '{}'",
+                            path.join("/")
+                        );
+                    }
+                    InputRef::Node(node_name) => {
+                        let Some(child) = lock.nodes.get(node_name) else {
+                            continue;
+                        };
+                        let url = child
+                            .locked
+                            .as_ref()
+                            .map(|locked| locked.to_string())
+                            .unwrap_or_default();
+                        let date = child
+                            .locked
+                            .as_ref()
+                            .and_then(FlakeRef::date)
+                            .map(|date| format!(" ({date})"))
+                            .unwrap_or_default();
+                        let _ = writeln!(out, "{prefix}{branch}{name}: {url}{date}");
+                        let prefix = format!("{prefix}{}", if last { "    " } else { "│   " });
+                        render(lock, child, &prefix, depth + 1, out);
+                    }
+                }
+            }
+        }
+
+        let mut out = String::from("Inputs:\n");
+        if let Some(root) = self.nodes.get(&self.root) {
+            render(self, root, "", 0, &mut out);
+        }
+        out
+    }
+
+    /// Check the locked inputs for stale top-level inputs and duplicated nixpkgs revisions.
+    pub fn warnings(&self, now: u64) -> Vec<LockWarning> {
+        let mut warnings = Vec::new();
+
+        for (name, node) in self.root_inputs() {
+            if let Some(last_modified) = node.locked.as_ref().and_then(|l| l.last_modified) {
+                if now.saturating_sub(last_modified) > STALE_AFTER_SECS {
+                    warnings.push(LockWarning::Stale {
+                        input: name.to_string(),
+                        last_modified,
+                    });
+                }
+            }
+        }
+
+        let revisions = self.nixpkgs_revisions();
+        if revisions.len() > 1 {
+            warnings.push(LockWarning::DuplicateNixpkgs { revisions });
+        }
+
+        warnings
+    }
+
+    /// Find the direct inputs of top-level inputs that duplicate another top-level input.
+    ///
+    /// Any unpinned nixpkgs counts as a duplicate of the top-level nixpkgs, even if it's a fork.
+    pub fn duplicate_inputs(&self) -> Vec<DuplicateInput> {
+        let Some(root) = self.nodes.get(&self.root) else {
+            return Vec::new();
+        };
+        let top_level = root
+            .inputs
+            .iter()
+            .filter_map(|(name, input)| match input {
+                InputRef::Node(node_name) => Some((
+                    name.as_str(),
+                    node_name.as_str(),
+                    self.nodes.get(node_name)?,
+                )),
+                InputRef::Follows(_) => None,
+            })
+            .collect::<Vec<_>>();
+
+        let mut duplicates = Vec::new();
+        for (input_name, _, node) in &top_level {
+            for (nested_name, nested) in &node.inputs {
+                let InputRef::Node(nested_node_name) = nested else {
+                    continue;
+                };
+                let Some(nested_node) = self.nodes.get(nested_node_name) else {
+                    continue;
+                };
+                let mut candidates = top_level
+                    .iter()
+                    .filter(|(name, node_name, top_level_node)| {
+                        name != input_name
+                            && node_name != nested_node_name
+                            && nested_node.same_source(top_level_node)
+                    })
+                    .map(|(name, ..)| *name)
+                    .collect::<Vec<_>>();
+                // Prefer the top-level input with the same name, e.g. `nixpkgs` for `nixpkgs`.
+                candidates.sort_by_key(|name| *name != nested_name);
+                if let Some(follows) = candidates.first() {
+                    duplicates.push(DuplicateInput {
+                        input: input_name.to_string(),
+                        nested: nested_name.clone(),
+                        follows: follows.to_string(),
+                    });
+                }
+            }
+        }
+        duplicates
+    }
+
+    /// The input paths that lock nixpkgs, keyed by the short revision.
+    ///
+    /// Inputs that pin nixpkgs to a specific revision on purpose are left out.
+    pub fn nixpkgs_revisions(&self) -> BTreeMap<String, Vec<String>> {
+        let mut revisions: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (path, node_name) in self.input_paths() {
+            let Some(node) = self.nodes.get(node_name) else {
+                continue;
+            };
+            let (Some(locked), Some(original)) = (&node.locked, &node.original) else {
+                continue;
+            };
+            if !locked.is_nixpkgs() || original.rev.is_some() {
+                continue;
+            }
+            if let Some(rev) = locked.short_rev() {
+                revisions.entry(rev.to_string()).or_default().push(path);
+            }
+        }
+        revisions
+    }
+}
+
+impl Node {
+    /// Whether both nodes lock the same source, possibly at different revisions.
+    /// Nodes that pin a revision on purpose never match.
+    fn same_source(&self, other: &Node) -> bool {
+        let (Some(locked), Some(other_locked)) = (&self.locked, &other.locked) else {
+            return false;
+        };
+        let pinned = |node: &Node| node.original.as_ref().is_some_and(|o| o.rev.is_some());
+        if pinned(self) || pinned(other) {
+            return false;
+        }
+        if locked.is_nixpkgs() && other_locked.is_nixpkgs() {
+            return true;
+        }
+        locked.source_id() == other_locked.source_id()
+    }
+}
+
+impl FlakeRef {
+    /// Identify the source regardless of its revision, e.g. `github:nixos/nixpkgs`.
+    pub fn source_id(&self) -> String {
+        match (&self.owner, &self.repo, &self.url, &self.path) {
+            (Some(owner), Some(repo), ..) => {
+                format!("{}:{owner}/{repo}", self.type_).to_lowercase()
+            }
+            (_, _, Some(url), _) => {
+                let url = url.split(['?', '#']).next().unwrap_or(url);
+                format!("{}:{url}", self.type_)
+            }
+            (_, _, _, Some(path)) => format!("{}:{path}", self.type_),
+            _ => self.to_string(),
+        }
+    }
+
+    pub fn short_rev(&self) -> Option<&str> {
+        self.rev.as_deref().map(|rev| &rev[..rev.len().min(7)])
+    }
+
+    /// The date of the last modification as `YYYY-MM-DD`.
+    pub fn date(&self) -> Option<String> {
+        self.last_modified.map(format_date)
+    }
+
+    /// A human readable age of the last modification, e.g. `3 days ago`.
+    pub fn age(&self) -> Option<String> {
+        self.last_modified
+            .map(|last_modified| format_age(now().saturating_sub(last_modified)))
+    }
+
+    /// A short description of the locked revision, e.g. `9100a0f (2025-05-12)`.
+    pub fn describe(&self) -> String {
+        match (self.short_rev(), self.date()) {
+            (Some(rev), Some(date)) => format!("{rev} ({date})"),
+            (Some(rev), None) => rev.to_string(),
+            (None, Some(date)) => format!("{} ({date})", self.type_),
+            (None, None) => self.type_.clone(),
+        }
+    }
+
+    /// Whether this reference points to nixpkgs or a fork of it, like `cachix/devenv-nixpkgs`.
+    pub fn is_nixpkgs(&self) -> bool {
+        match (&self.repo, &self.url) {
+            (Some(repo), _) => repo.ends_with("nixpkgs"),
+            (None, Some(url)) => url.contains("nixpkgs"),
+            (None, None) => false,
+        }
+    }
+}
+
+impl fmt::Display for FlakeRef {
+    /// Format the reference as a flake URL, e.g. `github:NixOS/nixpkgs/<rev>`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut params = Vec::new();
+        match self.type_.as_str() {
+            "github" | "gitlab" | "sourcehut" => {
+                write!(
+                    f,
+                    "{}:{}/{}",
+                    self.type_,
+                    self.owner.as_deref().unwrap_or_default(),
+                    self.repo.as_deref().unwrap_or_default()
+                )?;
+                if let Some(rev_or_ref) = self.rev.as_ref().or(self.ref_.as_ref()) {
+                    write!(f, "/{rev_or_ref}")?;
+                }
+                if let Some(host) = &self.host {
+                    params.push(format!("host={host}"));
+                }
+            }
+            "git" | "hg" => {
+                let url = self.url.as_deref().unwrap_or_default();
+                write!(f, "{}+{url}", self.type_)?;
+                if let Some(ref_) = &self.ref_ {
+                    params.push(format!("ref={ref_}"));
+                }
+                if let Some(rev) = &self.rev {
+                    params.push(format!("rev={rev}"));
+                }
+            }
+            "path" => write!(f, "path:{}", self.path.as_deref().unwrap_or_default())?,
+            "indirect" => {
+                write!(f, "flake:{}", self.id.as_deref().unwrap_or_default())?;
+                if let Some(rev_or_ref) = self.rev.as_ref().or(self.ref_.as_ref()) {
+                    write!(f, "/{rev_or_ref}")?;
+                }
+            }
+            _ => match &self.url {
+                Some(url) => write!(f, "{url}")?,
+                None => write!(f, "{}", self.type_)?,
+            },
+        }
+        if let Some(dir) = &self.dir {
+            params.push(format!("dir={dir}"));
+        }
+        if !params.is_empty() {
+            write!(f, "?{}", params.join("&"))?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for DuplicateInput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} could follow {}",
+            self.input, self.nested, self.follows
+        )
+    }
+}
+
+impl fmt::Display for LockWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockWarning::Stale {
+                input,
+                last_modified,
+            } => write!(
+                f,
+                "Input {input} was last updated {}. Run `devenv update {input}` to update it.",
+                format_age(now().saturating_sub(*last_modified))
+            ),
+            LockWarning::DuplicateNixpkgs { revisions } => {
+                let revisions = revisions
+                    .iter()
+                    .map(|(rev, paths)| format!("{rev} ({})", paths.join(", ")))
+                    .collect::<Vec<_>>();
+                write!(
+                    f,
+                    "{} different revisions of nixpkgs are locked: {}",
+                    revisions.len(),
+                    revisions.join(", ")
+                )
+            }
+        }
+    }
+}
+
+/// A change to a top-level input between two versions of the lock file.
+///
+/// `old` is `None` for added inputs, and `new` is `None` for removed ones.
+#[derive(Debug, PartialEq, Eq)]
+pub struct InputChange {
+    pub name: String,
+    pub old: Option<FlakeRef>,
+    pub new: Option<FlakeRef>,
+}
+
+impl fmt::Display for InputChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = &self.name;
+        match (&self.old, &self.new) {
+            (Some(old), Some(new)) => write!(f, "{name}: {} → {}", old.describe(), new.describe()),
+            (None, Some(new)) => write!(f, "{name}: added {}", new.describe()),
+            (Some(old), None) => write!(f, "{name}: removed {}", old.describe()),
+            (None, None) => write!(f, "{name}: unchanged"),
+        }
+    }
+}
+
+/// Compare the locked top-level inputs of two lock files.
+pub fn diff(old: Option<&Lock>, new: Option<&Lock>) -> Vec<InputChange> {
+    fn locked_inputs(lock: Option<&Lock>) -> BTreeMap<String, FlakeRef> {
+        lock.map(|lock| {
+            lock.root_inputs()
+                .into_iter()
+                .filter_map(|(name, node)| Some((name.to_string(), node.locked.clone()?)))
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    let old = locked_inputs(old);
+    let mut new = locked_inputs(new);
+
+    let mut changes = Vec::new();
+    for (name, old) in old {
+        let new = new.remove(&name);
+        if new.as_ref() != Some(&old) {
+            changes.push(InputChange {
+                name,
+                old: Some(old),
+                new,
+            });
+        }
+    }
+    for (name, new) in new {
+        changes.push(InputChange {
+            name,
+            old: None,
+            new: Some(new),
+        });
+    }
+    changes.sort_by(|a, b| a.name.cmp(&b.name));
+    changes
+}
+
+/// The current time as a unix timestamp.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn format_age(secs: u64) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 60 * MINUTE;
+    const DAY: u64 = 24 * HOUR;
+
+    let (value, unit) = match secs {
+        s if s < MINUTE => return "just now".to_string(),
+        s if s < HOUR => (s / MINUTE, "minute"),
+        s if s < DAY => (s / HOUR, "hour"),
+        s if s < 60 * DAY => (s / DAY, "day"),
+        s if s < 730 * DAY => (s / (30 * DAY), "month"),
+        s => (s / (365 * DAY), "year"),
+    };
+    let plural = if value == 1 { "" } else { "s" };
+    format!("{value} {unit}{plural} ago")
+}
+
+/// Format a unix timestamp as a UTC `YYYY-MM-DD` date.
+fn format_date(timestamp: u64) -> String {
+    // Convert days since the epoch to a civil date.
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (timestamp / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCK: &str = r#"{
+      "nodes": {
+        "devenv": {
+          "locked": { "dir": "src/modules", "path": ".", "type": "path" },
+          "original": { "dir": "src/modules", "path": ".", "type": "path" }
+        },
+        "git-hooks": {
+          "inputs": { "nixpkgs": ["nixpkgs"] },
+          "locked": {
+            "lastModified": 1750779888,
+            "owner": "cachix",
+            "repo": "git-hooks.nix",
+            "rev": "16ec914f6fb6f599ce988427d9d94efddf25fe6d",
+            "type": "github"
+          },
+          "original": { "owner": "cachix", "repo": "git-hooks.nix", "type": "github" }
+        },
+        "nix": {
+          "inputs": { "nixpkgs": "nixpkgs_2", "nixpkgs-regression": "nixpkgs-regression" },
+          "locked": {
+            "lastModified": 1750955511,
+            "owner": "cachix",
+            "repo": "nix",
+            "rev": "afa41b08df4f67b8d77a8034b037ac28c71c77df",
+            "type": "github"
+          },
+          "original": { "owner": "cachix", "ref": "devenv-2.30", "repo": "nix", "type": "github" }
+        },
+        "nixpkgs": {
+          "locked": {
+            "lastModified": 1747046372,
+            "owner": "cachix",
+            "repo": "devenv-nixpkgs",
+            "rev": "9100a0f413b0c601e0533d1d94ffd501ce2e7885",
+            "type": "github"
+          },
+          "original": { "owner": "cachix", "ref": "rolling", "repo": "devenv-nixpkgs", "type": "github" }
+        },
+        "nixpkgs-regression": {
+          "locked": {
+            "lastModified": 1643052045,
+            "owner": "NixOS",
+            "repo": "nixpkgs",
+            "rev": "215d4d0fd80ca5163643b03a33fde804a29cc1e2",
+            "type": "github"
+          },
+          "original": {
+            "owner": "NixOS",
+            "repo": "nixpkgs",
+            "rev": "215d4d0fd80ca5163643b03a33fde804a29cc1e2",
+            "type": "github"
+          }
+        },
+        "nixpkgs_2": {
+          "locked": {
+            "lastModified": 1750776420,
+            "owner": "NixOS",
+            "repo": "nixpkgs",
+            "rev": "30a61f056ac492e3b7cdcb69c1e6abdcf00e39cf",
+            "type": "github"
+          },
+          "original": { "owner": "NixOS", "ref": "nixos-unstable", "repo": "nixpkgs", "type": "github" }
+        },
+        "root": {
+          "inputs": {
+            "devenv": "devenv",
+            "git-hooks": "git-hooks",
+            "nix": "nix",
+            "nixpkgs": "nixpkgs",
+            "pre-commit-hooks": ["git-hooks"]
+          }
+        }
+      },
+      "root": "root",
+      "version": 7
+    }"#;
+
+    #[test]
+    fn resolves_root_inputs() {
+        let lock = Lock::parse(LOCK).unwrap();
+        let inputs = lock.root_inputs();
+        assert_eq!(inputs.len(), 5);
+        assert_eq!(
+            inputs["pre-commit-hooks"].locked,
+            inputs["git-hooks"].locked
+        );
+        assert_eq!(
+            inputs["nixpkgs"].locked.as_ref().unwrap().short_rev(),
+            Some("9100a0f")
+        );
+    }
+
+    #[test]
+    fn resolves_nested_follows() {
+        let lock = Lock::parse(LOCK).unwrap();
+        let nixpkgs = lock
+            .resolve(&InputRef::Follows(vec![
+                "git-hooks".to_string(),
+                "nixpkgs".to_string(),
+            ]))
+            .unwrap();
+        assert_eq!(
+            nixpkgs.locked.as_ref().unwrap().rev.as_deref(),
+            Some("9100a0f413b0c601e0533d1d94ffd501ce2e7885")
+        );
+    }
+
+    #[test]
+    fn ignores_inputs_that_follow_themselves() {
+        let lock = Lock::parse(
+            r#"{
+              "nodes": {
+                "nixpkgs": {
+                  "locked": { "type": "github", "owner": "NixOS", "repo": "nixpkgs", "rev": "abc" }
+                },
+                "root": {
+                  "inputs": {
+                    "a": ["a"],
+                    "b": ["c"],
+                    "c": ["b", "nixpkgs"],
+                    "nixpkgs": "nixpkgs"
+                  }
+                }
+              },
+              "root": "root",
+              "version": 7
+            }"#,
+        )
+        .unwrap();
+        let inputs = lock.root_inputs();
+        assert_eq!(inputs.keys().copied().collect::<Vec<_>>(), vec!["nixpkgs"]);
+    }
+
+    #[test]
+    fn formats_flake_urls() {
+        let lock = Lock::parse(LOCK).unwrap();
+        let inputs = lock.root_inputs();
+        let url = |name: &str| inputs[name].locked.as_ref().unwrap().to_string();
+        assert_eq!(url("devenv"), "path:.?dir=src/modules");
+        assert_eq!(
+            url("nixpkgs"),
+            "github:cachix/devenv-nixpkgs/9100a0f413b0c601e0533d1d94ffd501ce2e7885"
+        );
+        assert_eq!(
+            inputs["nix"].original.as_ref().unwrap().to_string(),
+            "github:cachix/nix/devenv-2.30"
+        );
+
+        let git = FlakeRef {
+            type_: "git".to_string(),
+            url: Some("https://example.com/repo.git".to_string()),
+            ref_: Some("main".to_string()),
+            rev: Some("abc".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            git.to_string(),
+            "git+https://example.com/repo.git?ref=main&rev=abc"
+        );
+    }
+
+    #[test]
+    fn renders_tree() {
+        let lock = Lock::parse(LOCK).unwrap();
+        let tree = lock.render_tree();
+        assert!(tree.starts_with("Inputs:\n├───devenv: path:.?dir=src/modules\n"));
+        assert!(tree.contains("│   └───nixpkgs follows input 'nixpkgs'\n"));
+        assert!(tree.contains(
+            "│   ├───nixpkgs: github:NixOS/nixpkgs/30a61f056ac492e3b7cdcb69c1e6abdcf00e39cf (2025-06-24)\n"
+        ));
+        assert!(tree.ends_with("└───pre-commit-hooks follows input 'git-hooks'\n"));
+    }
+
+    #[test]
+    fn warns_about_duplicate_nixpkgs() {
+        let lock = Lock::parse(LOCK).unwrap();
+        let revisions = lock.nixpkgs_revisions();
+        assert_eq!(
+            revisions,
+            BTreeMap::from([
+                ("30a61f0".to_string(), vec!["nix/nixpkgs".to_string()]),
+                ("9100a0f".to_string(), vec!["nixpkgs".to_string()]),
+            ])
+        );
+
+        let warnings = lock.warnings(1750955511);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].to_string(),
+            "2 different revisions of nixpkgs are locked: 30a61f0 (nix/nixpkgs), 9100a0f (nixpkgs)"
+        );
+    }
+
+    #[test]
+    fn finds_duplicate_inputs() {
+        let lock = Lock::parse(LOCK).unwrap();
+        assert_eq!(
+            lock.duplicate_inputs(),
+            vec![DuplicateInput {
+                input: "nix".to_string(),
+                nested: "nixpkgs".to_string(),
+                follows: "nixpkgs".to_string(),
+            }]
+        );
+        assert_eq!(
+            lock.duplicate_inputs()[0].to_string(),
+            "nix/nixpkgs could follow nixpkgs"
+        );
+    }
+
+    #[test]
+    fn source_ids_ignore_revisions() {
+        let github = |owner: &str, rev: &str| FlakeRef {
+            type_: "github".to_string(),
+            owner: Some(owner.to_string()),
+            repo: Some("nixpkgs".to_string()),
+            rev: Some(rev.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            github("NixOS", "a").source_id(),
+            github("nixos", "b").source_id()
+        );
+        assert_eq!(github("NixOS", "a").source_id(), "github:nixos/nixpkgs");
+
+        let tarball = FlakeRef {
+            type_: "tarball".to_string(),
+            url: Some("https://example.com/source.tar.gz?rev=abc".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            tarball.source_id(),
+            "tarball:https://example.com/source.tar.gz"
+        );
+    }
+
+    #[test]
+    fn warns_about_stale_inputs() {
+        let lock = Lock::parse(LOCK).unwrap();
+        let now = 1747046372 + STALE_AFTER_SECS + 1;
+        let stale = lock
+            .warnings(now)
+            .into_iter()
+            .filter_map(|warning| match warning {
+                LockWarning::Stale { input, .. } => Some(input),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(stale, vec!["nixpkgs".to_string()]);
+    }
+
+    #[test]
+    fn diff_lock_files() {
+        let old = Lock::parse(LOCK).unwrap();
+        let mut new = old.clone();
+        new.nodes.get_mut("nixpkgs").unwrap().locked = Some(FlakeRef {
+            type_: "github".to_string(),
+            owner: Some("cachix".to_string()),
+            repo: Some("devenv-nixpkgs".to_string()),
+            rev: Some("e28ba067a9368286a8bc88b68dc2ca92181a09f0".to_string()),
+            last_modified: Some(1751596734),
+            ..Default::default()
+        });
+        new.nodes
+            .get_mut("root")
+            .unwrap()
+            .inputs
+            .remove("pre-commit-hooks");
+
+        let changes = diff(Some(&old), Some(&new));
+        assert_eq!(changes.len(), 2);
+        assert_eq!(
+            changes[0].to_string(),
+            "nixpkgs: 9100a0f (2025-05-12) → e28ba06 (2025-07-04)"
+        );
+        assert_eq!(changes[1].name, "pre-commit-hooks");
+        assert_eq!(changes[1].new, None);
+
+        assert!(diff(Some(&old), Some(&old)).is_empty());
+        assert_eq!(diff(None, Some(&old)).len(), 5);
+    }
+
+    #[test]
+    fn formats_dates_and_ages() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(951782400), "2000-02-29");
+        assert_eq!(format_date(1747046372), "2025-05-12");
+        assert_eq!(format_age(30), "just now");
+        assert_eq!(format_age(3600), "1 hour ago");
+        assert_eq!(format_age(3 * 86400), "3 days ago");
+        assert_eq!(format_age(90 * 86400), "3 months ago");
+        assert_eq!(format_age(800 * 86400), "2 years ago");
+    }
+}
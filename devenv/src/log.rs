@@ -1,4 +1,5 @@
 use console::style;
+use std::any::TypeId;
 use std::collections::HashSet;
 use std::fmt;
 use std::io::{self, IsTerminal};
@@ -51,6 +52,8 @@ pub enum LogFormat {
     TracingFull,
     /// A pretty human-readable log format used for debugging.
     TracingPretty,
+    /// One JSON object per line for every event and span, for consumption by other tools.
+    Json,
 }
 
 pub fn init_tracing_default() {
@@ -58,6 +61,65 @@ pub fn init_tracing_default() {
 }
 
 pub fn init_tracing(level: Level, log_format: LogFormat) {
+    init_tracing_with_otlp(level, log_format, None);
+}
+
+/// Keeps the trace exporter alive. Dropping it flushes any pending spans.
+#[derive(Default)]
+pub struct TracingGuard {
+    #[cfg(feature = "otel")]
+    otel: Option<crate::otel::OtelGuard>,
+}
+
+impl TracingGuard {
+    /// Flush pending spans and stop exporting them.
+    pub fn shutdown(&self) {
+        #[cfg(feature = "otel")]
+        if let Some(otel) = &self.otel {
+            otel.shutdown();
+        }
+    }
+}
+
+/// Flush pending spans before replacing the process with `exec`, which skips destructors,
+/// including the one of the [TracingGuard].
+pub fn flush_before_exec() {
+    #[cfg(feature = "otel")]
+    crate::otel::shutdown();
+}
+
+/// Initialize tracing and optionally export spans to an OpenTelemetry collector.
+pub fn init_tracing_with_otlp(
+    level: Level,
+    log_format: LogFormat,
+    otlp_endpoint: Option<&str>,
+) -> TracingGuard {
+    #[cfg(feature = "otel")]
+    let (otel_layer, guard) = match otlp_endpoint.map(crate::otel::layer) {
+        Some(Ok((layer, otel_guard))) => (
+            Some(layer),
+            TracingGuard {
+                otel: Some(otel_guard),
+            },
+        ),
+        Some(Err(err)) => {
+            eprintln!("Failed to set up the OpenTelemetry exporter: {err}");
+            (None, TracingGuard::default())
+        }
+        None => (None, TracingGuard::default()),
+    };
+
+    #[cfg(not(feature = "otel"))]
+    let (otel_layer, guard) = {
+        if otlp_endpoint.is_some() {
+            eprintln!("devenv was built without OpenTelemetry support. Ignoring --otlp-endpoint.");
+        }
+        (
+            None::<tracing_subscriber::layer::Identity>,
+            TracingGuard::default(),
+        )
+    };
+
     let devenv_layer = DevenvLayer::new();
 
     let filter = EnvFilter::builder()
@@ -74,6 +136,7 @@ pub fn init_tracing(level: Level, log_format: LogFormat) {
                 .with_ansi(ansi)
                 .boxed();
             tracing_subscriber::registry()
+                .with(otel_layer)
                 .with(filter)
                 .with(stderr_layer)
                 .with(devenv_layer)
@@ -86,11 +149,19 @@ pub fn init_tracing(level: Level, log_format: LogFormat) {
                 .pretty()
                 .boxed();
             tracing_subscriber::registry()
+                .with(otel_layer)
                 .with(filter)
                 .with(stderr_layer)
                 .with(devenv_layer)
                 .init();
         }
+        LogFormat::Json => {
+            tracing_subscriber::registry()
+                .with(otel_layer)
+                .with(filter)
+                .with(JsonLayer::new(stderr))
+                .init();
+        }
         LogFormat::Cli => {
             use indicatif::ProgressStyle;
             // For CLI mode, use IndicatifLayer to coordinate ALL output with progress bars
@@ -113,6 +184,7 @@ pub fn init_tracing(level: Level, log_format: LogFormat) {
                 .boxed();
 
             tracing_subscriber::registry()
+                .with(otel_layer)
                 .with(filter)
                 .with(stderr_layer)
                 .with(devenv_layer)
@@ -120,6 +192,8 @@ pub fn init_tracing(level: Level, log_format: LogFormat) {
                 .init();
         }
     }
+
+    guard
 }
 
 /// A structure to capture span timings, similar to what is available internally in tracing_subscriber.
@@ -151,6 +225,11 @@ impl SpanTimings {
         self.last = now;
     }
 
+    /// Count the time since the span was last exited as idle, when the span closes.
+    fn close(&mut self) {
+        self.enter();
+    }
+
     /// Returns the total duration of the span, combining the idle and busy times.
     fn total_duration(&self) -> HumanReadableDuration {
         HumanReadableDuration(self.idle + self.busy)
@@ -352,6 +431,17 @@ where
         // Forward all events to IndicatifLayer so they appear above progress bars without interruption
         self.inner.on_event(event, ctx);
     }
+
+    // Expose the inner IndicatifLayer, so that `IndicatifSpanExt` can drive the progress bars.
+    //
+    // SAFETY: the returned pointers are valid for the lifetime of `&self`, see `IndicatifLayer::downcast_raw`.
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        if id == TypeId::of::<Self>() {
+            Some(self as *const _ as *const ())
+        } else {
+            self.inner.downcast_raw(id)
+        }
+    }
 }
 
 #[derive(Default)]
@@ -423,7 +513,7 @@ where
         let mut extensions = span.extensions_mut();
 
         if let Some(span_ctx) = extensions.get_mut::<SpanContext>() {
-            span_ctx.timings.enter();
+            span_ctx.timings.close();
 
             let has_error = self.has_error.load(Ordering::SeqCst);
             if has_error {
@@ -459,6 +549,220 @@ where
     }
 }
 
+/// Fields recorded on a span, along with its timings, for the JSON log format.
+struct JsonSpanContext {
+    fields: serde_json::Map<String, serde_json::Value>,
+    has_error: bool,
+    timings: SpanTimings,
+}
+
+/// Collect all fields into a JSON object.
+#[derive(Default)]
+struct JsonVisitor(serde_json::Map<String, serde_json::Value>);
+
+impl Visit for JsonVisitor {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+}
+
+/// A layer that writes every event, span start, span update and span end as a JSON line.
+///
+/// The objects carry a `type` of `event`, `span_start`, `span_update` or `span_end`.
+/// Spans are identified by their `id` and `parent`, and keep the `devenv.user_message` and
+/// other fields under `fields`. Span ends include the timings in milliseconds.
+pub struct JsonLayer<W> {
+    make_writer: W,
+}
+
+impl<W> JsonLayer<W> {
+    pub fn new(make_writer: W) -> Self {
+        Self { make_writer }
+    }
+}
+
+impl<W> JsonLayer<W>
+where
+    W: for<'a> tracing_subscriber::fmt::MakeWriter<'a> + 'static,
+{
+    fn write(&self, mut object: serde_json::Map<String, serde_json::Value>) {
+        use std::io::Write;
+        use tracing_subscriber::fmt::time::FormatTime;
+
+        let mut timestamp = String::new();
+        if tracing_subscriber::fmt::time::SystemTime
+            .format_time(&mut Writer::new(&mut timestamp))
+            .is_ok()
+        {
+            object.insert("timestamp".to_string(), timestamp.into());
+        }
+
+        if let Ok(mut line) = serde_json::to_vec(&object) {
+            line.push(b'\n');
+            let _ = self.make_writer.make_writer().write_all(&line);
+        }
+    }
+}
+
+impl<S, W> Layer<S> for JsonLayer<W>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'a> tracing_subscriber::fmt::MakeWriter<'a> + 'static,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: layer::Context<'_, S>) {
+        let span = ctx.span(id).expect("Span not found in context");
+
+        let mut visitor = JsonVisitor::default();
+        attrs.record(&mut visitor);
+
+        let mut object = serde_json::Map::new();
+        object.insert("type".to_string(), "span_start".into());
+        object.insert("id".to_string(), id.into_u64().into());
+        if let Some(parent) = span.parent() {
+            object.insert("parent".to_string(), parent.id().into_u64().into());
+        }
+        object.insert("name".to_string(), span.name().into());
+        object.insert("target".to_string(), span.metadata().target().into());
+        object.insert("fields".to_string(), visitor.0.clone().into());
+
+        span.extensions_mut().insert(JsonSpanContext {
+            fields: visitor.0,
+            has_error: false,
+            timings: SpanTimings::new(),
+        });
+
+        self.write(object);
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: layer::Context<'_, S>) {
+        let span = ctx.span(id).expect("Span not found in context");
+
+        let mut visitor = JsonVisitor::default();
+        values.record(&mut visitor);
+
+        let mut extensions = span.extensions_mut();
+        if let Some(span_ctx) = extensions.get_mut::<JsonSpanContext>() {
+            span_ctx.fields.extend(visitor.0.clone());
+        }
+        drop(extensions);
+
+        let mut object = serde_json::Map::new();
+        object.insert("type".to_string(), "span_update".into());
+        object.insert("id".to_string(), id.into_u64().into());
+        object.insert("name".to_string(), span.name().into());
+        object.insert("fields".to_string(), visitor.0.into());
+        self.write(object);
+    }
+
+    fn on_enter(&self, id: &span::Id, ctx: layer::Context<'_, S>) {
+        let span = ctx.span(id).expect("Span not found in context");
+        let mut extensions = span.extensions_mut();
+        if let Some(span_ctx) = extensions.get_mut::<JsonSpanContext>() {
+            span_ctx.timings.enter();
+        }
+    }
+
+    fn on_exit(&self, id: &span::Id, ctx: layer::Context<'_, S>) {
+        let span = ctx.span(id).expect("Span not found in context");
+        let mut extensions = span.extensions_mut();
+        if let Some(span_ctx) = extensions.get_mut::<JsonSpanContext>() {
+            span_ctx.timings.exit();
+        }
+    }
+
+    fn on_close(&self, id: span::Id, ctx: layer::Context<'_, S>) {
+        let span = ctx.span(&id).expect("Span not found in context");
+        let mut extensions = span.extensions_mut();
+        let Some(span_ctx) = extensions.get_mut::<JsonSpanContext>() else {
+            return;
+        };
+        span_ctx.timings.close();
+
+        let mut object = serde_json::Map::new();
+        object.insert("type".to_string(), "span_end".into());
+        object.insert("id".to_string(), id.into_u64().into());
+        if let Some(parent) = span.parent() {
+            object.insert("parent".to_string(), parent.id().into_u64().into());
+        }
+        object.insert("name".to_string(), span.name().into());
+        object.insert("target".to_string(), span.metadata().target().into());
+        object.insert("has_error".to_string(), span_ctx.has_error.into());
+        object.insert(
+            "busy_ms".to_string(),
+            (span_ctx.timings.busy.as_millis() as u64).into(),
+        );
+        object.insert(
+            "idle_ms".to_string(),
+            (span_ctx.timings.idle.as_millis() as u64).into(),
+        );
+        object.insert(
+            "duration_ms".to_string(),
+            ((span_ctx.timings.busy + span_ctx.timings.idle).as_millis() as u64).into(),
+        );
+        object.insert(
+            "fields".to_string(),
+            std::mem::take(&mut span_ctx.fields).into(),
+        );
+
+        drop(extensions);
+        self.write(object);
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: layer::Context<'_, S>) {
+        let meta = event.metadata();
+
+        let mut visitor = JsonVisitor::default();
+        event.record(&mut visitor);
+
+        let mut object = serde_json::Map::new();
+        object.insert("type".to_string(), "event".into());
+        object.insert("level".to_string(), meta.level().as_str().into());
+        object.insert("target".to_string(), meta.target().into());
+        if let Some(span) = ctx.event_span(event) {
+            object.insert("span".to_string(), span.id().into_u64().into());
+        }
+        if let Some(message) = visitor.0.remove("message") {
+            object.insert("message".to_string(), message);
+        }
+        object.insert("fields".to_string(), visitor.0.into());
+
+        // Mark the enclosing spans as failed.
+        if *meta.level() == tracing::Level::ERROR {
+            if let Some(scope) = ctx.event_scope(event) {
+                for span in scope {
+                    let mut extensions = span.extensions_mut();
+                    if let Some(span_ctx) = extensions.get_mut::<JsonSpanContext>() {
+                        span_ctx.has_error = true;
+                    }
+                }
+            }
+        }
+
+        self.write(object);
+    }
+}
+
 #[derive(Default)]
 pub struct DevenvFormat {
     pub verbose: bool,
@@ -571,3 +875,131 @@ where
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// Collects everything that a layer writes.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for Output {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    impl Output {
+        /// The JSON objects that were written, without their timestamps.
+        fn objects(&self) -> Vec<serde_json::Value> {
+            let output = self.0.lock().unwrap();
+            String::from_utf8_lossy(&output)
+                .lines()
+                .map(|line| {
+                    let mut object: serde_json::Value = serde_json::from_str(line).unwrap();
+                    let timestamp = object.as_object_mut().unwrap().remove("timestamp");
+                    assert!(timestamp.is_some_and(|t| t.is_string()));
+                    object
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn writes_spans_and_events_as_json_lines() {
+        let output = Output::default();
+        let subscriber = tracing_subscriber::registry().with(JsonLayer::new(output.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            let outer = tracing::info_span!(
+                target: "devenv::test",
+                "building_shell",
+                devenv.user_message = "Building shell",
+                cache_status = tracing::field::Empty
+            );
+            outer.in_scope(|| {
+                tracing::info_span!(target: "devenv::test", "evaluating").in_scope(|| {
+                    std::thread::sleep(Duration::from_millis(20));
+                    tracing::error!(target: "devenv::test", attr = "shell", "Failed to evaluate");
+                });
+            });
+            outer.record("cache_status", "hit");
+        });
+
+        let mut objects = output.objects();
+        let outer_end = objects.remove(5);
+        let inner_end = objects.remove(3);
+        assert_eq!(
+            objects,
+            vec![
+                serde_json::json!({
+                    "type": "span_start",
+                    "id": 1,
+                    "name": "building_shell",
+                    "target": "devenv::test",
+                    "fields": { "devenv.user_message": "Building shell" },
+                }),
+                serde_json::json!({
+                    "type": "span_start",
+                    "id": 2,
+                    "parent": 1,
+                    "name": "evaluating",
+                    "target": "devenv::test",
+                    "fields": {},
+                }),
+                serde_json::json!({
+                    "type": "event",
+                    "level": "ERROR",
+                    "target": "devenv::test",
+                    "span": 2,
+                    "message": "Failed to evaluate",
+                    "fields": { "attr": "shell" },
+                }),
+                serde_json::json!({
+                    "type": "span_update",
+                    "id": 1,
+                    "name": "building_shell",
+                    "fields": { "cache_status": "hit" },
+                }),
+            ]
+        );
+
+        let timing = |object: &serde_json::Value, key: &str| object[key].as_u64().unwrap();
+        assert_eq!(inner_end["type"], "span_end");
+        assert_eq!(inner_end["id"], 2);
+        assert_eq!(inner_end["parent"], 1);
+        assert_eq!(inner_end["has_error"], true);
+        assert!(timing(&inner_end, "busy_ms") >= 20);
+        assert!(
+            timing(&inner_end, "duration_ms")
+                >= timing(&inner_end, "busy_ms") + timing(&inner_end, "idle_ms")
+        );
+
+        assert_eq!(outer_end["type"], "span_end");
+        assert_eq!(outer_end["id"], 1);
+        assert_eq!(outer_end.get("parent"), None);
+        assert_eq!(outer_end["has_error"], true);
+        assert!(timing(&outer_end, "duration_ms") >= 20);
+        assert_eq!(
+            outer_end["fields"],
+            serde_json::json!({
+                "devenv.user_message": "Building shell",
+                "cache_status": "hit",
+            })
+        );
+    }
+}
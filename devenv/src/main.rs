@@ -1,7 +1,10 @@
 use clap::crate_version;
 use devenv::{
-    cli::{Cli, Commands, ContainerCommand, InputsCommand, ProcessesCommand, TasksCommand},
-    config, log, Devenv,
+    cli::{
+        self, AddCommand, Cli, Commands, ConfigCommand, ContainerCommand, InputsCommand,
+        ProcessesCommand, ProjectsCommand, TasksCommand,
+    },
+    config, log, workspace, Devenv,
 };
 use miette::{IntoDiagnostic, Result, WrapErr};
 use std::env;
@@ -48,9 +51,27 @@ async fn main() -> Result<()> {
         log::Level::default()
     };
 
-    log::init_tracing(level, cli.global_options.log_format);
+    let tracing_guard = log::init_tracing_with_otlp(
+        level,
+        cli.global_options.log_format,
+        cli.global_options.otlp_endpoint.as_deref(),
+    );
 
-    let mut config = config::Config::load()?;
+    if cli.global_options.all {
+        return run_in_all_projects(&command, &args, cli.global_options.max_parallel).await;
+    }
+
+    let cwd = env::current_dir()
+        .into_diagnostic()
+        .wrap_err("Failed to get current directory")?;
+    // Commands run in a subdirectory operate on the closest project above it,
+    // except for `devenv init`, which creates a new project.
+    let devenv_root = match command {
+        Commands::Init { .. } => cwd.clone(),
+        _ => workspace::find_root(&cwd).unwrap_or_else(|| cwd.clone()),
+    };
+
+    let mut config = config::Config::load_from(&devenv_root)?;
     for input in cli.global_options.override_input.chunks_exact(2) {
         config
             .override_input_url(&input[0].clone(), &input[1].clone())
@@ -62,9 +83,41 @@ async fn main() -> Result<()> {
             })?;
     }
 
+    let mut global_options = cli.global_options;
+    if global_options.persist_options {
+        config::persist_options(
+            &devenv_root.join(config::YAML_LOCAL_CONFIG),
+            &global_options.option,
+        )?;
+        info!(
+            "Saved the option overrides to {}",
+            config::YAML_LOCAL_CONFIG
+        );
+    }
+
+    // Options from the command line take precedence over the profile and the config files.
+    let mut option_args = config.option_args();
+    if let Some(profile) = &global_options.profile {
+        let profile_options = config
+            .profile_options(profile)
+            .wrap_err_with(|| format!("Failed to use profile {profile}"))?;
+        option_args.extend(profile_options);
+    }
+    option_args.extend(global_options.option);
+    global_options.option = option_args;
+
+    if matches!(command, Commands::Container { .. }) {
+        let system = cli::container_system(&global_options.system);
+        if system != global_options.system {
+            info!("Building containers for {system}");
+            global_options.system = system;
+        }
+    }
+
     let mut options = devenv::DevenvOptions {
-        global_options: Some(cli.global_options),
+        global_options: Some(global_options),
         config,
+        devenv_root: Some(devenv_root.clone()),
         ..Default::default()
     };
 
@@ -73,10 +126,7 @@ async fn main() -> Result<()> {
         dont_override_dotfile,
     } = command
     {
-        let pwd = std::env::current_dir()
-            .into_diagnostic()
-            .wrap_err("Failed to get current directory")?;
-        let tmpdir = TempDir::with_prefix_in(".devenv.", pwd)
+        let tmpdir = TempDir::with_prefix_in(".devenv.", &devenv_root)
             .into_diagnostic()
             .wrap_err("Failed to create temporary directory")?;
         if !dont_override_dotfile {
@@ -123,15 +173,40 @@ async fn main() -> Result<()> {
                 None => {
                     if let Some(c) = command {
                         match c {
-                            ContainerCommand::Build { name } => {
+                            ContainerCommand::Build { name, systems } => {
                                 devenv.container_name = Some(name.clone());
-                                let _ = devenv.container_build(&name).await?;
+                                if systems.is_empty() {
+                                    let _ = devenv.container_build(&name).await?;
+                                } else {
+                                    devenv.container_build_systems(&name, &systems).await?;
+                                }
                             }
-                            ContainerCommand::Copy { name } => {
+                            ContainerCommand::Copy {
+                                name,
+                                tags,
+                                systems,
+                            } => {
                                 devenv.container_name = Some(name.clone());
-                                devenv
-                                    .container_copy(&name, &copy_args, registry.as_deref())
-                                    .await?;
+                                if systems.is_empty() {
+                                    devenv
+                                        .container_copy(
+                                            &name,
+                                            &copy_args,
+                                            registry.as_deref(),
+                                            &tags,
+                                        )
+                                        .await?;
+                                } else {
+                                    devenv
+                                        .container_copy_systems(
+                                            &name,
+                                            &copy_args,
+                                            registry.as_deref(),
+                                            &systems,
+                                            &tags,
+                                        )
+                                        .await?;
+                                }
                             }
                             ContainerCommand::Run { name } => {
                                 devenv.container_name = Some(name.clone());
@@ -139,6 +214,21 @@ async fn main() -> Result<()> {
                                     .container_run(&name, &copy_args, registry.as_deref())
                                     .await?;
                             }
+                            ContainerCommand::List => {
+                                devenv.container_list().await?;
+                            }
+                            ContainerCommand::Inspect { name } => {
+                                devenv.container_name = Some(name.clone());
+                                devenv.container_inspect(&name).await?;
+                            }
+                            ContainerCommand::Export {
+                                name,
+                                format,
+                                output,
+                            } => {
+                                devenv.container_name = Some(name.clone());
+                                devenv.container_export(&name, format, &output).await?;
+                            }
                         }
                     }
                 }
@@ -147,7 +237,7 @@ async fn main() -> Result<()> {
                         (true, false) => {
                             warn!("--copy flag is deprecated, use `devenv container copy` instead",);
                             devenv
-                                .container_copy(&name, &copy_args, registry.as_deref())
+                                .container_copy(&name, &copy_args, registry.as_deref(), &[])
                                 .await?;
                         }
                         (_, true) => {
@@ -172,6 +262,7 @@ async fn main() -> Result<()> {
         Commands::Init { target } => devenv.init(&target),
         Commands::Generate { .. } => match which::which("devenv-generate") {
             Ok(devenv_generate) => {
+                tracing_guard.shutdown();
                 let error = Command::new(devenv_generate)
                     .args(std::env::args().skip(1).filter(|arg| arg != "generate"))
                     .exec();
@@ -187,8 +278,50 @@ async fn main() -> Result<()> {
                 "})
             }
         },
-        Commands::Search { name } => devenv.search(&name).await,
-        Commands::Gc {} => devenv.gc().await,
+        Commands::Search {
+            name,
+            json,
+            options_only,
+            packages_only,
+            limit,
+            details,
+        } => {
+            let options = devenv::search::SearchOptions {
+                json,
+                options_only,
+                packages_only,
+                limit,
+                details,
+            };
+            devenv.search(&name, &options).await
+        }
+        Commands::ShowPackage { attr, json } => devenv.show_package(&attr, json).await,
+        Commands::Add { command } => match command {
+            AddCommand::Package { attr } => devenv.add_package(&attr, true).await,
+            AddCommand::Language { name, version } => {
+                devenv.add_language(&name, version.as_deref(), true).await
+            }
+            AddCommand::Service { name } => devenv.add_service(&name, true).await,
+        },
+        Commands::AddPackage { attr } => devenv.add_package(&attr, true).await,
+        Commands::Gc {
+            dry_run,
+            keep_generations,
+            older_than,
+            project,
+        } => {
+            let options = devenv::gc::GcOptions {
+                dry_run,
+                keep_generations,
+                older_than,
+                project,
+            };
+            devenv.gc(&options).await
+        }
+        Commands::Projects { command } => match command {
+            ProjectsCommand::List => devenv.projects_list().await,
+            ProjectsCommand::Forget { path } => devenv.projects_forget(&path).await,
+        },
         Commands::Info {} => devenv.info().await,
         Commands::Repl {} => devenv.repl().await,
         Commands::Build { attributes } => devenv.build(&attributes).await,
@@ -214,6 +347,23 @@ async fn main() -> Result<()> {
             InputsCommand::Add { name, url, follows } => {
                 devenv.inputs_add(&name, &url, &follows).await
             }
+            InputsCommand::List {} => devenv.inputs_list().await,
+            InputsCommand::Remove { name } => devenv.inputs_remove(&name).await,
+            InputsCommand::Diff { git_ref } => devenv.inputs_diff(&git_ref).await,
+            InputsCommand::Dedupe {} => devenv.inputs_dedupe().await,
+        },
+        Commands::Config { command } => match command {
+            ConfigCommand::Show { origin } => devenv.config_show(origin).await,
+            ConfigCommand::Get { key } => devenv.config_get(&key).await,
+            ConfigCommand::Set {
+                key,
+                value,
+                local,
+                user,
+            } => devenv.config_set(&key, &value, local, user).await,
+            ConfigCommand::Unset { key, local, user } => {
+                devenv.config_unset(&key, local, user).await
+            }
         },
 
         // hidden
@@ -233,3 +383,71 @@ async fn main() -> Result<()> {
         Commands::Version => unreachable!(),
     }
 }
+
+/// Run the command in every project below the current directory.
+async fn run_in_all_projects(
+    command: &Commands,
+    args: &[String],
+    max_parallel: usize,
+) -> Result<()> {
+    let interactive = matches!(
+        command,
+        Commands::Init { .. }
+            | Commands::Generate { .. }
+            | Commands::Shell { cmd: None, .. }
+            | Commands::Search { .. }
+            | Commands::ShowPackage { .. }
+            | Commands::Add { .. }
+            | Commands::AddPackage { .. }
+            | Commands::Up { detach: false, .. }
+            | Commands::Repl { .. }
+            | Commands::Gc { .. }
+            | Commands::Projects { .. }
+            | Commands::Mcp { .. }
+            | Commands::GenerateJSONSchema
+    );
+    if interactive {
+        miette::bail!("This command can't be run in all projects at once.");
+    }
+
+    let cwd = env::current_dir()
+        .and_then(|cwd| cwd.canonicalize())
+        .into_diagnostic()
+        .wrap_err("Failed to get current directory")?;
+    let projects = workspace::find_projects(&cwd)?;
+    if projects.is_empty() {
+        miette::bail!(
+            "No projects found in {}. A project is a directory with a {}.",
+            cwd.display(),
+            workspace::PROJECT_FILE
+        );
+    }
+    info!("Running in {} projects", projects.len());
+
+    // Pass the rest of the arguments on to each project, up to a `--` separator.
+    let mut project_args = Vec::new();
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--" => {
+                project_args.push(arg.clone());
+                project_args.extend(rest.by_ref().cloned());
+            }
+            "--all" => {}
+            "--max-parallel" => {
+                rest.next();
+            }
+            arg if arg.starts_with("--max-parallel=") => {}
+            arg => project_args.push(arg.to_string()),
+        }
+    }
+
+    let results = workspace::run_in_projects(&cwd, &projects, &project_args, max_parallel).await?;
+    workspace::print_summary(&cwd, &results);
+
+    let failed = results.iter().filter(|result| !result.success).count();
+    if failed > 0 {
+        miette::bail!("{failed} of {} projects failed", results.len());
+    }
+    Ok(())
+}
@@ -191,6 +191,42 @@ impl DevenvMcpServer {
 
         Ok(options)
     }
+
+    /// Edit devenv.nix with `edit`, and describe the outcome for the client.
+    ///
+    /// MCP clients ask their users to approve tool calls, so the edit is written without
+    /// the confirmation that `devenv add` asks for.
+    async fn edit_devenv_nix<F>(&self, done: String, edit: F) -> String
+    where
+        F: AsyncFnOnce(&Devenv) -> Result<()> + Send + 'static,
+    {
+        let devenv_options = DevenvOptions {
+            config: self.config.clone(),
+            devenv_root: self.devenv_root.clone(),
+            ..Default::default()
+        };
+
+        // The Nix backend's futures aren't `Send`, which tools have to be,
+        // so the edit runs on a thread of its own.
+        let runtime = tokio::runtime::Handle::current();
+        let result = tokio::task::spawn_blocking(move || {
+            runtime.block_on(async move {
+                let devenv = Devenv::new(devenv_options).await;
+                edit(&devenv).await
+            })
+        })
+        .await
+        .unwrap_or_else(|e| Err(miette::miette!("Failed to edit devenv.nix: {e}")));
+
+        match result {
+            Ok(()) => done,
+            Err(e) => e
+                .chain()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(": "),
+        }
+    }
 }
 
 fn parse_type_to_value(type_str: &str) -> Value {
@@ -265,6 +301,28 @@ struct ListOptionsRequest {
     prefix: Option<String>,
 }
 
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct AddPackageRequest {
+    #[schemars(description = "The attribute path of the package, like python3Packages.pytest")]
+    attr: String,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct AddLanguageRequest {
+    #[schemars(description = "The name of the language, like python")]
+    name: String,
+    #[schemars(
+        description = "Optional version of the language, for languages that have a version option"
+    )]
+    version: Option<String>,
+}
+
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+struct AddServiceRequest {
+    #[schemars(description = "The name of the service, like postgres")]
+    name: String,
+}
+
 impl ServerHandler for DevenvMcpServer {
     fn get_info(&self) -> ServerInfo {
         ServerInfo {
@@ -335,6 +393,39 @@ impl DevenvMcpServer {
 
         serde_json::to_string(&filtered_options).unwrap_or_default()
     }
+
+    #[tool(description = "Add a package to packages in devenv.nix")]
+    async fn add_package(&self, params: Parameters<AddPackageRequest>) -> String {
+        let attr = params.0.attr;
+        let done = format!(
+            "devenv.nix has pkgs.{} in packages",
+            attr.trim_start_matches("pkgs.")
+        );
+        self.edit_devenv_nix(done, async move |devenv| {
+            devenv.add_package(&attr, false).await
+        })
+        .await
+    }
+
+    #[tool(description = "Enable a language in devenv.nix, optionally with a version")]
+    async fn add_language(&self, params: Parameters<AddLanguageRequest>) -> String {
+        let AddLanguageRequest { name, version } = params.0;
+        let done = format!("devenv.nix enables languages.{name}");
+        self.edit_devenv_nix(done, async move |devenv| {
+            devenv.add_language(&name, version.as_deref(), false).await
+        })
+        .await
+    }
+
+    #[tool(description = "Enable a service in devenv.nix")]
+    async fn add_service(&self, params: Parameters<AddServiceRequest>) -> String {
+        let name = params.0.name;
+        let done = format!("devenv.nix enables services.{name}");
+        self.edit_devenv_nix(done, async move |devenv| {
+            devenv.add_service(&name, false).await
+        })
+        .await
+    }
 }
 
 pub async fn run_mcp_server(config: Config) -> Result<()> {
@@ -1,4 +1,5 @@
 use crate::nix_backend::{self, NixBackend};
+use crate::nix_progress::NixProgress;
 use crate::{cli, config};
 use async_trait::async_trait;
 use futures::future;
@@ -36,12 +37,12 @@ impl Nix {
         paths: nix_backend::DevenvPaths,
     ) -> Result<Self> {
         let cachix_caches = Arc::new(OnceCell::new());
-        let options = nix_backend::Options::default();
+        let options = nix_backend::Options {
+            cache_ttl: global_options.eval_cache_ttl,
+            ..Default::default()
+        };
 
-        let database_url = format!(
-            "sqlite:{}/nix-eval-cache.db",
-            paths.dotfile.to_string_lossy()
-        );
+        let database_url = format!("sqlite:{}", paths.eval_cache.to_string_lossy());
 
         Ok(Self {
             options,
@@ -147,6 +148,7 @@ impl Nix {
 
     pub async fn repl(&self) -> Result<()> {
         let mut cmd = self.prepare_command("nix", &["repl", "."], &self.options)?;
+        crate::log::flush_before_exec();
         let _ = cmd.exec();
         Ok(())
     }
@@ -229,26 +231,10 @@ impl Nix {
             ..self.options
         };
 
-        // TODO: use --json
-        let metadata = self
-            .run_nix("nix", &["flake", "metadata"], &options)
-            .await?;
-
-        let re = regex::Regex::new(r"(Inputs:.+)$").unwrap();
-        let metadata_str = String::from_utf8_lossy(&metadata.stdout);
-        let inputs = match re.captures(&metadata_str) {
-            Some(captures) => captures.get(1).unwrap().as_str(),
-            None => "",
-        };
-
         let info_ = self
             .run_nix("nix", &["eval", "--raw", ".#info"], &options)
             .await?;
-        Ok(format!(
-            "{}\n{}",
-            inputs,
-            &String::from_utf8_lossy(&info_.stdout)
-        ))
+        Ok(String::from_utf8_lossy(&info_.stdout).to_string())
     }
 
     pub async fn search(
@@ -277,19 +263,58 @@ impl Nix {
     }
 
     pub async fn gc(&self, paths: Vec<PathBuf>) -> Result<()> {
-        let paths: std::collections::HashSet<&str> = paths
+        let paths: std::collections::BTreeSet<&str> = paths
             .iter()
             .filter_map(|path_buf| path_buf.to_str())
             .collect();
-        for path in paths {
-            info!("Deleting {}...", path);
-            let args: Vec<&str> = ["store", "delete", path].to_vec();
-            // we ignore if this command fails, because root might be in use
-            let _ = self.run_nix("nix", &args, &self.options).await;
+        if paths.is_empty() {
+            return Ok(());
+        }
+        info!("Deleting {} store paths...", paths.len());
+        let mut args = vec!["store", "delete", "--skip-live"];
+        args.extend(paths);
+        let options = nix_backend::Options {
+            // paths that are still in use are skipped, but anything else shouldn't stop the GC
+            bail_on_error: false,
+            ..self.options.clone()
+        };
+        let output = self.run_nix("nix", &args, &options).await?;
+        if !output.status.success() {
+            warn!("Failed to delete some store paths, see the output above.");
         }
         Ok(())
     }
 
+    /// The substituters that Nix is configured with, followed by the cachix caches that the
+    /// project pulls from.
+    pub async fn substituters(&self) -> Result<Vec<String>> {
+        let nix_conf = self.get_nix_config().await?;
+        let mut substituters: Vec<String> = ["substituters", "extra-substituters"]
+            .iter()
+            .filter_map(|key| nix_conf.get(key))
+            .flat_map(|value| value.split_whitespace())
+            .map(String::from)
+            .collect();
+
+        if !self.global_options.offline {
+            match self.get_cachix_caches().await {
+                Ok(cachix_caches) => {
+                    for cache in &cachix_caches.caches.pull {
+                        let url = format!("https://{}.cachix.org", cache);
+                        if !substituters.contains(&url) {
+                            substituters.push(url);
+                        }
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to get cachix caches due to evaluation error");
+                    debug!("{}", e);
+                }
+            }
+        }
+        Ok(substituters)
+    }
+
     // Run Nix with debugger capability and return the output
     pub async fn run_nix(
         &self,
@@ -331,6 +356,7 @@ impl Nix {
 
             debug!("Running command: {}", display_command(&cmd));
 
+            crate::log::flush_before_exec();
             let error = cmd.exec();
             error!(
                 "Failed to replace shell with `{}`: {error}",
@@ -356,10 +382,25 @@ impl Nix {
             let mut cached_cmd = CachedCommand::new(pool);
 
             cached_cmd.watch_path(self.paths.root.join("devenv.yaml"));
+            cached_cmd.watch_path(self.paths.root.join("devenv.local.yaml"));
+            if let Some(user_config) = config::user_config_path() {
+                cached_cmd.watch_path(user_config);
+            }
             cached_cmd.watch_path(self.paths.root.join("devenv.lock"));
             cached_cmd.watch_path(self.paths.dotfile.join("flake.json"));
             cached_cmd.watch_path(self.paths.dotfile.join("cli-options.nix"));
 
+            // Modules imported with `../` aren't part of the project's source, so watch them explicitly.
+            for (import, module) in self.config.parent_imports(&self.paths.root) {
+                if import.ends_with(".nix") {
+                    cached_cmd.watch_path(module);
+                } else if let Some(dir) = module.parent() {
+                    for path in nix_files(dir) {
+                        cached_cmd.watch_path(path);
+                    }
+                }
+            }
+
             // Ignore anything in .devenv except for the specifically watched files above.
             cached_cmd.unwatch_path(&self.paths.dotfile);
 
@@ -367,7 +408,25 @@ impl Nix {
                 cached_cmd.force_refresh();
             }
 
+            if let Some(ttl) = options.cache_ttl {
+                cached_cmd.ttl(ttl);
+            }
+
+            let pretty_cmd = display_command(&cmd);
+            let span = debug_span!(
+                "Running command",
+                command = pretty_cmd.as_str(),
+                devenv.user_message = format!("Running command: {}", pretty_cmd)
+            );
+
             if options.logging && !self.global_options.quiet {
+                // Nest the progress bars under the closest enabled span.
+                let progress = NixProgress::new(if span.is_disabled() {
+                    tracing::Span::current()
+                } else {
+                    span.clone()
+                });
+
                 // Show eval and build logs only in verbose mode
                 let target_log_level = if self.global_options.verbose {
                     Verbosity::Talkative
@@ -376,6 +435,8 @@ impl Nix {
                 };
 
                 cached_cmd.on_stderr(move |log| {
+                    progress.handle(log);
+
                     if let Some(log) = log.filter_by_level(target_log_level) {
                         if let Some(msg) = log.get_msg() {
                             use devenv_eval_cache::internal_log::InternalLog;
@@ -393,12 +454,6 @@ impl Nix {
                 });
             }
 
-            let pretty_cmd = display_command(&cmd);
-            let span = debug_span!(
-                "Running command",
-                command = pretty_cmd.as_str(),
-                devenv.user_message = format!("Running command: {}", pretty_cmd)
-            );
             let output = cached_cmd
                 .output(&mut cmd)
                 .instrument(span)
@@ -406,13 +461,15 @@ impl Nix {
                 .into_diagnostic()
                 .wrap_err_with(|| format!("Failed to run command `{}`", display_command(&cmd)))?;
 
-            if output.cache_hit {
-                tracing::Span::current().record(
-                    "cache_status",
-                    if output.cache_hit { "hit" } else { "miss" },
-                );
+            if let Some(reason) = &output.uncacheable {
+                debug!("Not caching the evaluation: {reason}");
             }
 
+            tracing::Span::current().record(
+                "cache_status",
+                if output.cache_hit { "hit" } else { "miss" },
+            );
+
             output
         } else {
             let pretty_cmd = display_command(&cmd);
@@ -433,6 +490,7 @@ impl Nix {
                 stderr: output.stderr,
                 inputs: vec![],
                 cache_hit: false,
+                uncacheable: None,
             }
         };
 
@@ -456,6 +514,7 @@ impl Nix {
                 && cmd.get_program().to_string_lossy().ends_with("bin/nix")
             {
                 info!("Starting Nix debugger ...");
+                crate::log::flush_before_exec();
                 let _ = cmd.arg("--debugger").exec();
             }
 
@@ -953,6 +1012,10 @@ impl NixBackend for Nix {
         self.gc(paths).await
     }
 
+    async fn substituters(&self) -> Result<Vec<String>> {
+        self.substituters().await
+    }
+
     fn name(&self) -> &'static str {
         self.name()
     }
@@ -1011,6 +1074,56 @@ fn get_now_with_nanoseconds() -> String {
     format!("{}.{}", secs, nanos)
 }
 
+// The Nix files in a directory and its subdirectories, skipping hidden directories.
+fn nix_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_dir() {
+                if !entry.file_name().to_string_lossy().starts_with('.') {
+                    pending.push(path);
+                }
+            } else if path.extension().is_some_and(|ext| ext == "nix") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+/// The NAR size of each store path in the output of `nix path-info --json`.
+///
+/// Nix 2.19 changed the output from a list of objects with a `path` to an object keyed by path.
+pub(crate) fn nar_sizes(output: &[u8]) -> Result<BTreeMap<String, u64>> {
+    let info: serde_json::Value = serde_json::from_slice(output)
+        .into_diagnostic()
+        .wrap_err("Failed to parse the output of nix path-info")?;
+    let paths = match &info {
+        serde_json::Value::Object(paths) => paths
+            .iter()
+            .map(|(path, info)| (path.as_str(), info))
+            .collect::<Vec<_>>(),
+        serde_json::Value::Array(infos) => infos
+            .iter()
+            .filter_map(|info| Some((info.get("path")?.as_str()?, info)))
+            .collect(),
+        _ => bail!("Unexpected output from nix path-info: {info}"),
+    };
+    Ok(paths
+        .into_iter()
+        .filter_map(|(path, info)| Some((path.to_string(), info.get("narSize")?.as_u64()?)))
+        .collect())
+}
+
 // Display a command as a pretty string.
 fn display_command(cmd: &std::process::Command) -> String {
     let command = cmd.get_program().to_string_lossy();
@@ -1101,6 +1214,32 @@ fn detect_missing_caches(caches: &CachixCaches, nix_conf: NixConf) -> (Vec<Strin
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_nix_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("modules")).unwrap();
+        std::fs::create_dir_all(root.join(".devenv")).unwrap();
+        std::fs::write(root.join("devenv.nix"), "{ }").unwrap();
+        std::fs::write(root.join("README.md"), "").unwrap();
+        std::fs::write(root.join("modules/db.nix"), "{ }").unwrap();
+        std::fs::write(root.join(".devenv/cli-options.nix"), "{ }").unwrap();
+
+        assert_eq!(
+            nix_files(root),
+            vec![root.join("devenv.nix"), root.join("modules/db.nix")]
+        );
+    }
+
+    #[test]
+    fn test_nar_sizes() {
+        let expected = BTreeMap::from([("/nix/store/abc-hello".to_string(), 1024)]);
+        let current = br#"{"/nix/store/abc-hello":{"narSize":1024},"/nix/store/def-missing":null}"#;
+        assert_eq!(nar_sizes(current).unwrap(), expected);
+        let legacy = br#"[{"path":"/nix/store/abc-hello","narSize":1024,"valid":true}]"#;
+        assert_eq!(nar_sizes(legacy).unwrap(), expected);
+    }
+
     #[test]
     fn test_trusted() {
         let store_ping = r#"{"trusted":1,"url":"daemon","version":"2.18.1"}"#;
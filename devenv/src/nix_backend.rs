@@ -7,6 +7,7 @@ use async_trait::async_trait;
 use devenv_eval_cache::Output;
 use miette::Result;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 /// Common paths used by devenv backends
 #[derive(Debug, Clone)]
@@ -15,6 +16,7 @@ pub struct DevenvPaths {
     pub dotfile: PathBuf,
     pub dot_gc: PathBuf,
     pub home_gc: PathBuf,
+    pub eval_cache: PathBuf,
     pub cachix_trusted_keys: PathBuf,
 }
 
@@ -29,6 +31,8 @@ pub struct Options {
     pub cache_output: bool,
     /// Force a refresh of the cached output.
     pub refresh_cached_output: bool,
+    /// Expire the cached output after this duration, even if its inputs haven't changed.
+    pub cache_ttl: Option<Duration>,
     /// Enable logging.
     pub logging: bool,
     /// Log the stdout of the command.
@@ -44,6 +48,7 @@ impl Default for Options {
             bail_on_error: true,
             cache_output: false,
             refresh_cached_output: false,
+            cache_ttl: None,
             logging: true,
             logging_stdout: false,
             nix_flags: &[
@@ -101,7 +106,7 @@ pub trait NixBackend: Send + Sync {
     /// Update flake inputs
     async fn update(&self, input_name: &Option<String>) -> Result<()>;
 
-    /// Get flake metadata
+    /// Get the evaluated project info: environment variables, packages, scripts, etc.
     async fn metadata(&self) -> Result<String>;
 
     /// Search for packages
@@ -110,6 +115,9 @@ pub trait NixBackend: Send + Sync {
     /// Garbage collect the specified paths
     async fn gc(&self, paths: Vec<PathBuf>) -> Result<()>;
 
+    /// Get the substituters to look for store paths in
+    async fn substituters(&self) -> Result<Vec<String>>;
+
     /// Get the backend name (for debugging/logging)
     fn name(&self) -> &'static str;
 
@@ -0,0 +1,489 @@
+//! Edit devenv.nix in place, keeping comments and formatting.
+//!
+//! The file is parsed with rnix to find where things are, and the edits are made to the text
+//! around them, so that everything else stays as it was written.
+
+use crate::cli_options;
+use miette::{bail, Result};
+use rnix::ast::{self, HasEntry};
+use rowan::ast::AstNode;
+use std::fmt;
+use std::ops::Range;
+
+/// The text of a Nix file.
+#[derive(Clone, Debug)]
+pub struct NixDocument {
+    text: String,
+}
+
+impl NixDocument {
+    pub fn parse(text: &str) -> Result<Self> {
+        let parse = rnix::Root::parse(text);
+        if let Some(error) = parse.errors().first() {
+            bail!("Failed to parse the Nix file: {error}");
+        }
+        Ok(Self {
+            text: text.to_string(),
+        })
+    }
+
+    /// Add `pkgs.<attr>` to `packages`, unless it's there already.
+    ///
+    /// Returns whether the document changed.
+    pub fn add_package(&mut self, attr: &str) -> Result<bool> {
+        let root = self.root()?;
+        let Some(value) = find_value(&root, &["packages"]) else {
+            self.insert_entry(&root, &format!("packages = [ pkgs.{attr} ];"));
+            return Ok(true);
+        };
+
+        // `with pkgs; [ git ]` refers to packages without the `pkgs.` prefix
+        let (value, item) = match value {
+            ast::Expr::With(with)
+                if with
+                    .namespace()
+                    .is_some_and(|namespace| namespace.syntax().to_string() == "pkgs") =>
+            {
+                match with.body() {
+                    Some(body) => (body, attr.to_string()),
+                    None => bail!("Failed to find the list of packages"),
+                }
+            }
+            value => (value, format!("pkgs.{attr}")),
+        };
+
+        let list = match value {
+            ast::Expr::List(list) => list,
+            // Something like `lib.optionals stdenv.isLinux [ ... ]`, which gets `++ [ ... ]` added,
+            // or a concatenation that already ends with a list, which the package is added to.
+            value => {
+                let operands = concat_operands(value.clone());
+                let listed = operands
+                    .iter()
+                    .filter_map(|operand| match operand {
+                        ast::Expr::List(list) => Some(list.items()),
+                        _ => None,
+                    })
+                    .flatten()
+                    .any(|existing| existing.syntax().to_string() == item);
+                if listed {
+                    return Ok(false);
+                }
+                match operands.last() {
+                    Some(ast::Expr::List(list)) if operands.len() > 1 => list.clone(),
+                    _ => {
+                        let range = range(value.syntax());
+                        let expr = &self.text[range.clone()];
+                        let expr = if binds_tighter_than_concat(&value) {
+                            expr.to_string()
+                        } else {
+                            format!("({expr})")
+                        };
+                        self.text
+                            .replace_range(range, &format!("{expr} ++ [ {item} ]"));
+                        return Ok(true);
+                    }
+                }
+            }
+        };
+        let items = list.items().collect::<Vec<_>>();
+        if items
+            .iter()
+            .any(|existing| existing.syntax().to_string() == item)
+        {
+            return Ok(false);
+        }
+        match items.last() {
+            Some(last) => {
+                let last = range(last.syntax());
+                let separator = if list.syntax().to_string().contains('\n') {
+                    format!("\n{}", self.indent_at(last.start))
+                } else {
+                    " ".to_string()
+                };
+                self.text
+                    .insert_str(last.end, &format!("{separator}{item}"));
+            }
+            None => {
+                self.text
+                    .replace_range(range(list.syntax()), &format!("[ {item} ]"));
+            }
+        }
+        Ok(true)
+    }
+
+    /// Set the attribute at `path` to `value`, which is Nix code, like `true` or `"3.12"`.
+    ///
+    /// If a parent of the attribute is already an attribute set, like `languages.python = { ... };`,
+    /// the attribute is set in there. Returns whether the document changed.
+    pub fn set(&mut self, path: &[&str], value: &str) -> Result<bool> {
+        let mut set = self.root()?;
+        let mut rest = path;
+        loop {
+            let mut parent = None;
+            for entry in set.attrpath_values() {
+                let Some(names) = entry.attrpath().map(|attrpath| attr_names(&attrpath)) else {
+                    continue;
+                };
+                if names == rest {
+                    let Some(existing) = entry.value() else {
+                        bail!("Failed to find the value of {}", path.join("."));
+                    };
+                    let existing = range(existing.syntax());
+                    if self.text[existing.clone()] == *value {
+                        return Ok(false);
+                    }
+                    self.text.replace_range(existing, value);
+                    return Ok(true);
+                }
+                let is_parent =
+                    names.len() < rest.len() && names.iter().zip(rest).all(|(a, b)| a == b);
+                if is_parent && parent.as_ref().is_none_or(|(len, _)| names.len() > *len) {
+                    parent = Some((names.len(), entry.value()));
+                }
+            }
+
+            match parent {
+                Some((len, Some(ast::Expr::AttrSet(inner)))) => {
+                    set = inner;
+                    rest = &rest[len..];
+                }
+                Some((len, existing)) => {
+                    let prefix = path[..path.len() - rest.len() + len].join(".");
+                    let existing = existing
+                        .map(|existing| existing.syntax().to_string())
+                        .unwrap_or_default();
+                    bail!(
+                        "{prefix} is set to `{existing}`, which can't be edited. Set {} there instead.",
+                        path.join(".")
+                    );
+                }
+                None => {
+                    let attrpath = rest
+                        .iter()
+                        .map(|name| cli_options::attr_name(name))
+                        .collect::<Vec<_>>()
+                        .join(".");
+                    let entry = format!("{attrpath} = {value};");
+                    self.insert_entry_near(&set, rest, &entry);
+                    return Ok(true);
+                }
+            }
+        }
+    }
+
+    /// The attribute set that devenv.nix evaluates to, after its arguments and any `let` bindings.
+    fn root(&self) -> Result<ast::AttrSet> {
+        let mut expr = rnix::Root::parse(&self.text).tree().expr();
+        loop {
+            expr = match expr {
+                Some(ast::Expr::AttrSet(set)) => return Ok(set),
+                Some(ast::Expr::Lambda(lambda)) => lambda.body(),
+                Some(ast::Expr::LetIn(let_in)) => let_in.body(),
+                Some(ast::Expr::With(with)) => with.body(),
+                Some(ast::Expr::Paren(paren)) => paren.expr(),
+                _ => bail!(
+                    "Expected devenv.nix to define an attribute set, like `{{ pkgs, ... }}: {{ packages = [ ]; }}`"
+                ),
+            };
+        }
+    }
+
+    /// Add an entry to an attribute set, before its first entry.
+    fn insert_entry(&mut self, set: &ast::AttrSet, entry: &str) {
+        match set.entries().next() {
+            Some(first) => {
+                let start = range(first.syntax()).start;
+                let separator = self.separator_before(start);
+                self.text.insert_str(start, &format!("{entry}{separator}"));
+            }
+            None => self.replace_empty_set(set, entry),
+        }
+    }
+
+    /// Add an entry for `path` to an attribute set, after the last entry that shares the most
+    /// of its path, so that `languages.python.version` ends up next to `languages.python.enable`.
+    /// Entries that don't share anything go at the end.
+    fn insert_entry_near(&mut self, set: &ast::AttrSet, path: &[&str], entry: &str) {
+        let shared = |names: &[String]| names.iter().zip(path).take_while(|(a, b)| a == b).count();
+        let mut anchor = None;
+        let mut most_shared = 0;
+        for existing in set.attrpath_values() {
+            let names = existing
+                .attrpath()
+                .map(|attrpath| attr_names(&attrpath))
+                .unwrap_or_default();
+            if shared(&names) >= most_shared.max(1) {
+                most_shared = shared(&names);
+                anchor = Some(range(existing.syntax()));
+            }
+        }
+        let anchor = anchor.or_else(|| set.entries().last().map(|last| range(last.syntax())));
+
+        match anchor {
+            Some(anchor) => {
+                let separator = self.separator_before(anchor.start);
+                self.text
+                    .insert_str(anchor.end, &format!("{separator}{entry}"));
+            }
+            None => self.replace_empty_set(set, entry),
+        }
+    }
+
+    /// Replace an empty attribute set with one that has `entry` on its own line.
+    fn replace_empty_set(&mut self, set: &ast::AttrSet, entry: &str) {
+        let range = range(set.syntax());
+        let indent = self.indent_at(range.start);
+        self.text
+            .replace_range(range, &format!("{{\n{indent}  {entry}\n{indent}}}"));
+    }
+
+    /// What separates an entry inserted next to the one at `offset`: a new line with the same
+    /// indentation if that one starts its line, or a space if it shares the line with something else.
+    fn separator_before(&self, offset: usize) -> String {
+        let line_start = self.text[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let prefix = &self.text[line_start..offset];
+        if prefix.trim().is_empty() {
+            format!("\n{prefix}")
+        } else {
+            " ".to_string()
+        }
+    }
+
+    /// The indentation of the line that `offset` is on.
+    fn indent_at(&self, offset: usize) -> String {
+        let line_start = self.text[..offset].rfind('\n').map_or(0, |i| i + 1);
+        self.text[line_start..]
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .collect()
+    }
+}
+
+impl fmt::Display for NixDocument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// The value of the entry in `set` whose attribute path is exactly `path`.
+fn find_value(set: &ast::AttrSet, path: &[&str]) -> Option<ast::Expr> {
+    set.attrpath_values()
+        .find(|entry| {
+            entry
+                .attrpath()
+                .is_some_and(|attrpath| attr_names(&attrpath) == path)
+        })
+        .and_then(|entry| entry.value())
+}
+
+/// The operands of `a ++ b ++ c`, or just the expression if it isn't a concatenation.
+fn concat_operands(expr: ast::Expr) -> Vec<ast::Expr> {
+    match expr {
+        ast::Expr::BinOp(op) if op.operator() == Some(ast::BinOpKind::Concat) => op
+            .lhs()
+            .into_iter()
+            .chain(op.rhs())
+            .flat_map(concat_operands)
+            .collect(),
+        expr => vec![expr],
+    }
+}
+
+/// Whether `expr ++ [ ... ]` concatenates all of `expr`, without parentheses around it.
+fn binds_tighter_than_concat(expr: &ast::Expr) -> bool {
+    match expr {
+        ast::Expr::BinOp(op) => op.operator() == Some(ast::BinOpKind::Concat),
+        ast::Expr::Apply(_)
+        | ast::Expr::Select(_)
+        | ast::Expr::Ident(_)
+        | ast::Expr::Paren(_)
+        | ast::Expr::List(_) => true,
+        _ => false,
+    }
+}
+
+/// The names in an attribute path, with quotes around string attributes removed.
+fn attr_names(attrpath: &ast::Attrpath) -> Vec<String> {
+    attrpath
+        .attrs()
+        .map(|attr| attr.syntax().to_string().trim_matches('"').to_string())
+        .collect()
+}
+
+fn range(node: &rnix::SyntaxNode) -> Range<usize> {
+    let range = node.text_range();
+    usize::from(range.start())..usize::from(range.end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add_package(text: &str, attr: &str) -> String {
+        let mut document = NixDocument::parse(text).unwrap();
+        document.add_package(attr).unwrap();
+        document.to_string()
+    }
+
+    #[test]
+    fn adds_packages_to_lists() {
+        assert_eq!(
+            add_package(
+                "{ pkgs, ... }:\n\n{\n  # Tools\n  packages = [\n    pkgs.git\n  ];\n}\n",
+                "jq"
+            ),
+            "{ pkgs, ... }:\n\n{\n  # Tools\n  packages = [\n    pkgs.git\n    pkgs.jq\n  ];\n}\n"
+        );
+        assert_eq!(
+            add_package("{ pkgs, ... }: { packages = [ pkgs.git ]; }", "jq"),
+            "{ pkgs, ... }: { packages = [ pkgs.git pkgs.jq ]; }"
+        );
+        assert_eq!(
+            add_package("{ pkgs, ... }: { packages = [ ]; }", "jq"),
+            "{ pkgs, ... }: { packages = [ pkgs.jq ]; }"
+        );
+        assert_eq!(
+            add_package("{ pkgs, ... }: { packages = with pkgs; [ git ]; }", "jq"),
+            "{ pkgs, ... }: { packages = with pkgs; [ git jq ]; }"
+        );
+        assert_eq!(
+            add_package(
+                "{ pkgs, lib, ... }: { packages = lib.optionals true [ pkgs.git ]; }",
+                "jq"
+            ),
+            "{ pkgs, lib, ... }: { packages = lib.optionals true [ pkgs.git ] ++ [ pkgs.jq ]; }"
+        );
+        assert_eq!(
+            add_package(
+                "{ pkgs, lib, ... }: { packages = lib.optionals true [ pkgs.git ] ++ [ pkgs.jq ]; }",
+                "yq"
+            ),
+            "{ pkgs, lib, ... }: { packages = lib.optionals true [ pkgs.git ] ++ [ pkgs.jq pkgs.yq ]; }"
+        );
+        assert_eq!(
+            add_package(
+                "{ pkgs, ... }: { packages = if pkgs.stdenv.isLinux then [ pkgs.git ] else [ ]; }",
+                "jq"
+            ),
+            "{ pkgs, ... }: { packages = (if pkgs.stdenv.isLinux then [ pkgs.git ] else [ ]) ++ [ pkgs.jq ]; }"
+        );
+    }
+
+    #[test]
+    fn adds_the_packages_attribute() {
+        assert_eq!(
+            add_package(
+                "{ pkgs, ... }:\n\nlet\n  name = \"app\";\nin\n{\n  env.NAME = name;\n}\n",
+                "jq"
+            ),
+            "{ pkgs, ... }:\n\nlet\n  name = \"app\";\nin\n{\n  packages = [ pkgs.jq ];\n  env.NAME = name;\n}\n"
+        );
+        assert_eq!(
+            add_package("{ pkgs, ... }: { }", "jq"),
+            "{ pkgs, ... }: {\n  packages = [ pkgs.jq ];\n}"
+        );
+    }
+
+    #[test]
+    fn skips_packages_that_are_already_there() {
+        for text in [
+            "{ pkgs, ... }: { packages = [ pkgs.jq ]; }",
+            "{ pkgs, lib, ... }: { packages = lib.optionals true [ pkgs.git ] ++ [ pkgs.jq ]; }",
+        ] {
+            let mut document = NixDocument::parse(text).unwrap();
+            assert!(!document.add_package("jq").unwrap());
+            assert_eq!(document.to_string(), text);
+        }
+    }
+
+    fn set(text: &str, path: &[&str], value: &str) -> String {
+        let mut document = NixDocument::parse(text).unwrap();
+        document.set(path, value).unwrap();
+        document.to_string()
+    }
+
+    #[test]
+    fn sets_attributes() {
+        let text = "{ pkgs, ... }:\n\n{\n  packages = [ pkgs.git ];\n\n  # Languages\n  languages.python.enable = true;\n  env.NAME = \"app\";\n}\n";
+        assert_eq!(
+            set(text, &["languages", "python", "version"], "\"3.12\""),
+            "{ pkgs, ... }:\n\n{\n  packages = [ pkgs.git ];\n\n  # Languages\n  languages.python.enable = true;\n  languages.python.version = \"3.12\";\n  env.NAME = \"app\";\n}\n"
+        );
+        assert_eq!(
+            set(text, &["services", "postgres", "enable"], "true"),
+            "{ pkgs, ... }:\n\n{\n  packages = [ pkgs.git ];\n\n  # Languages\n  languages.python.enable = true;\n  env.NAME = \"app\";\n  services.postgres.enable = true;\n}\n"
+        );
+        assert_eq!(
+            set(
+                "{ pkgs, ... }: { languages.rust.enable = false; }",
+                &["languages", "rust", "enable"],
+                "true"
+            ),
+            "{ pkgs, ... }: { languages.rust.enable = true; }"
+        );
+        assert_eq!(
+            set(
+                "{ pkgs, ... }: { }",
+                &["services", "redis", "enable"],
+                "true"
+            ),
+            "{ pkgs, ... }: {\n  services.redis.enable = true;\n}"
+        );
+    }
+
+    #[test]
+    fn merges_into_attribute_sets() {
+        assert_eq!(
+            set(
+                "{\n  languages.python = {\n    enable = true;\n  };\n}\n",
+                &["languages", "python", "version"],
+                "\"3.12\""
+            ),
+            "{\n  languages.python = {\n    enable = true;\n    version = \"3.12\";\n  };\n}\n"
+        );
+        assert_eq!(
+            set(
+                "{ languages = { python.enable = true; }; }",
+                &["languages", "go", "enable"],
+                "true"
+            ),
+            "{ languages = { python.enable = true; go.enable = true; }; }"
+        );
+
+        let mut document =
+            NixDocument::parse("{ lib, ... }: { languages = lib.mkForce { }; }").unwrap();
+        assert!(document
+            .set(&["languages", "go", "enable"], "true")
+            .is_err());
+    }
+
+    #[test]
+    fn skips_attributes_that_are_already_set() {
+        let text = "{ languages.python.enable = true; }";
+        let mut document = NixDocument::parse(text).unwrap();
+        assert!(!document
+            .set(&["languages", "python", "enable"], "true")
+            .unwrap());
+        assert_eq!(document.to_string(), text);
+    }
+
+    #[test]
+    fn quotes_attribute_names() {
+        assert_eq!(
+            set("{ }", &["processes", "my app", "exec"], "\"serve\""),
+            "{\n  processes.\"my app\".exec = \"serve\";\n}"
+        );
+        assert_eq!(
+            set("{ env.FOO = \"1\"; }", &["env", "in"], "\"2\""),
+            "{ env.FOO = \"1\"; env.\"in\" = \"2\"; }"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_files() {
+        assert!(NixDocument::parse("{ pkgs, ... }: {").is_err());
+        let mut document = NixDocument::parse("[ ]").unwrap();
+        assert!(document.add_package("jq").is_err());
+    }
+}
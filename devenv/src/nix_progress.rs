@@ -0,0 +1,266 @@
+//! Render Nix build progress from the activities in its internal-json log.
+//!
+//! Nix reports its work as a tree of activities: a `Builds` activity counts the derivations to
+//! build, a `CopyPaths` activity counts the paths to fetch, and each derivation gets its own
+//! `Build` activity that streams log lines.
+//! We mirror these activities as tracing spans with a `devenv.user_message`, so that
+//! [crate::log::DevenvIndicatifFilter] renders them as nested progress bars.
+//! The progress is also recorded on the spans' fields for the JSON log format.
+
+use devenv_eval_cache::internal_log::{ActivityType, Field, InternalLog, ResultType};
+use indicatif::{HumanBytes, ProgressStyle};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tracing::{field, info_span, Span};
+use tracing_indicatif::span_ext::IndicatifSpanExt;
+
+pub struct NixProgress {
+    /// The span to nest the progress bars under.
+    parent: Span,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Spans for the activities that have a progress bar, keyed by their Nix activity id.
+    spans: HashMap<u64, Span>,
+    /// The id of the `CopyPaths` activity, which also reports the downloaded bytes.
+    copy_paths: Option<u64>,
+    /// The id of the `Builds` activity, which parents the individual builds.
+    builds: Option<u64>,
+    /// Bytes downloaded per file transfer activity.
+    downloaded: HashMap<u64, u64>,
+}
+
+impl State {
+    fn downloaded_bytes(&self) -> u64 {
+        self.downloaded.values().sum()
+    }
+}
+
+impl NixProgress {
+    pub fn new(parent: Span) -> Self {
+        Self {
+            parent,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Update the progress bars from a single log entry.
+    pub fn handle(&self, log: &InternalLog) {
+        let Ok(mut guard) = self.state.lock() else {
+            return;
+        };
+        let state = &mut *guard;
+
+        match log {
+            InternalLog::Start {
+                id, typ, fields, ..
+            } => match typ {
+                ActivityType::Builds => {
+                    let span = info_span!(
+                        parent: &self.parent,
+                        "nix_builds",
+                        devenv.user_message = "Building derivations",
+                        done = field::Empty,
+                        expected = field::Empty,
+                    );
+                    span.pb_start();
+                    state.builds = Some(*id);
+                    state.spans.insert(*id, span);
+                }
+                ActivityType::CopyPaths => {
+                    let span = info_span!(
+                        parent: &self.parent,
+                        "nix_copy_paths",
+                        devenv.user_message = "Fetching paths",
+                        done = field::Empty,
+                        expected = field::Empty,
+                        downloaded_bytes = field::Empty,
+                    );
+                    span.pb_start();
+                    state.copy_paths = Some(*id);
+                    state.spans.insert(*id, span);
+                }
+                ActivityType::Build => {
+                    let Some(Field::String(drv)) = fields.first() else {
+                        return;
+                    };
+                    let name = drv_name(drv);
+                    let parent = state
+                        .builds
+                        .and_then(|id| state.spans.get(&id))
+                        .unwrap_or(&self.parent);
+                    let span = info_span!(
+                        parent: parent,
+                        "nix_build",
+                        devenv.user_message = format!("Building {name}"),
+                        drv = drv.as_str(),
+                        phase = field::Empty,
+                    );
+                    span.pb_set_style(&build_style());
+                    span.pb_start();
+                    state.spans.insert(*id, span);
+                }
+                ActivityType::FileTransfer => {
+                    state.downloaded.insert(*id, 0);
+                }
+                _ => {}
+            },
+
+            InternalLog::Result {
+                id, typ, fields, ..
+            } => match typ {
+                ResultType::Progress => {
+                    let (done, expected) = match (fields.first(), fields.get(1)) {
+                        (Some(Field::Int(done)), Some(Field::Int(expected))) => (*done, *expected),
+                        _ => return,
+                    };
+
+                    if let Some(bytes) = state.downloaded.get_mut(id) {
+                        *bytes = done;
+                        if let Some(span) = state.copy_paths.and_then(|id| state.spans.get(&id)) {
+                            let downloaded = state.downloaded_bytes();
+                            span.record("downloaded_bytes", downloaded);
+                            span.pb_set_message(&format!("{} downloaded", HumanBytes(downloaded)));
+                        }
+                    } else if Some(*id) == state.builds || Some(*id) == state.copy_paths {
+                        if let Some(span) = state.spans.get(id) {
+                            span.record("done", done);
+                            span.record("expected", expected);
+                            if expected > 0 {
+                                span.pb_set_style(&counter_style());
+                                span.pb_set_length(expected);
+                                span.pb_set_position(done);
+                            }
+                        }
+                    }
+                }
+                ResultType::BuildLogLine | ResultType::PostBuildLogLine => {
+                    if let (Some(span), Some(Field::String(line))) =
+                        (state.spans.get(id), fields.first())
+                    {
+                        span.pb_set_message(line.trim());
+                    }
+                }
+                ResultType::SetPhase => {
+                    if let (Some(span), Some(Field::String(phase))) =
+                        (state.spans.get(id), fields.first())
+                    {
+                        span.record("phase", phase.as_str());
+                        span.pb_set_message(&format!("[{phase}]"));
+                    }
+                }
+                _ => {}
+            },
+
+            InternalLog::Stop { id } => {
+                // Dropping the span closes it and clears its progress bar.
+                state.spans.remove(id);
+                if state.builds == Some(*id) {
+                    state.builds = None;
+                }
+                if state.copy_paths == Some(*id) {
+                    state.copy_paths = None;
+                }
+            }
+
+            _ => {}
+        }
+    }
+}
+
+/// Show the number of completed derivations or paths, followed by an optional message.
+fn counter_style() -> ProgressStyle {
+    ProgressStyle::with_template(
+        "{span_child_prefix}{spinner:.blue} {span_fields} {pos}/{len} {msg:.dim}",
+    )
+    .unwrap()
+    .tick_strings(&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"])
+}
+
+/// Show the derivation name followed by its latest log line.
+fn build_style() -> ProgressStyle {
+    ProgressStyle::with_template("{span_child_prefix}{spinner:.blue} {span_fields} {wide_msg:.dim}")
+        .unwrap()
+        .tick_strings(&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"])
+}
+
+/// Turn `/nix/store/<hash>-<name>.drv` into `<name>`.
+fn drv_name(drv: &str) -> &str {
+    let name = drv.rsplit('/').next().unwrap_or(drv);
+    let name = name.split_once('-').map(|(_, name)| name).unwrap_or(name);
+    name.strip_suffix(".drv").unwrap_or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use devenv_eval_cache::internal_log::Verbosity;
+
+    fn start(id: u64, typ: ActivityType, fields: Vec<Field>) -> InternalLog {
+        InternalLog::Start {
+            id,
+            level: Verbosity::Info,
+            typ,
+            text: String::new(),
+            parent: 0,
+            fields,
+        }
+    }
+
+    fn progress(id: u64, done: u64, expected: u64) -> InternalLog {
+        InternalLog::Result {
+            id,
+            typ: ResultType::Progress,
+            fields: vec![
+                Field::Int(done),
+                Field::Int(expected),
+                Field::Int(0),
+                Field::Int(0),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_drv_name() {
+        assert_eq!(
+            drv_name("/nix/store/0c3bbm7yxf9ah2ny2yi1a1xvs1zdhd2i-hello-2.12.1.drv"),
+            "hello-2.12.1"
+        );
+        assert_eq!(drv_name("hello.drv"), "hello");
+    }
+
+    #[test]
+    fn test_tracks_downloaded_bytes() {
+        let progress_bars = NixProgress::new(Span::none());
+        progress_bars.handle(&start(1, ActivityType::CopyPaths, vec![]));
+        progress_bars.handle(&start(2, ActivityType::FileTransfer, vec![]));
+        progress_bars.handle(&start(3, ActivityType::FileTransfer, vec![]));
+        progress_bars.handle(&progress(2, 100, 200));
+        progress_bars.handle(&progress(3, 50, 50));
+        progress_bars.handle(&InternalLog::Stop { id: 3 });
+
+        let state = progress_bars.state.lock().unwrap();
+        assert_eq!(state.downloaded_bytes(), 150);
+    }
+
+    #[test]
+    fn test_tracks_running_builds() {
+        let progress_bars = NixProgress::new(Span::none());
+        progress_bars.handle(&start(1, ActivityType::Builds, vec![]));
+        progress_bars.handle(&start(
+            2,
+            ActivityType::Build,
+            vec![Field::String("/nix/store/abc-hello.drv".to_string())],
+        ));
+        assert_eq!(progress_bars.state.lock().unwrap().spans.len(), 2);
+
+        progress_bars.handle(&InternalLog::Stop { id: 2 });
+        progress_bars.handle(&InternalLog::Stop { id: 1 });
+
+        let state = progress_bars.state.lock().unwrap();
+        assert!(state.spans.is_empty());
+        assert_eq!(state.builds, None);
+    }
+}
@@ -0,0 +1,973 @@
+//! Write container images built by nix2container as OCI image layouts and archives,
+//! without going through skopeo or a Docker daemon.
+//!
+//! nix2container doesn't build layer tarballs, it describes them in a JSON image spec
+//! that lists the store paths of each layer. The tarballs are created here from those paths,
+//! so the digests are our own and don't have to match the ones in the spec.
+
+use miette::{bail, IntoDiagnostic, Result, WrapErr};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+pub const INDEX_MEDIA_TYPE: &str = "application/vnd.oci.image.index.v1+json";
+pub const CONFIG_MEDIA_TYPE: &str = "application/vnd.oci.image.config.v1+json";
+pub const LAYER_MEDIA_TYPE: &str = "application/vnd.oci.image.layer.v1.tar";
+
+/// The annotation that names an image in an OCI layout.
+const REF_NAME_ANNOTATION: &str = "org.opencontainers.image.ref.name";
+
+/// The modification time of every file in the layers, the same as in the Nix store.
+const MTIME: u64 = 1;
+
+/// The creation time of images whose spec doesn't have one, for reproducibility.
+const DEFAULT_CREATED: &str = "1970-01-01T00:00:01Z";
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ExportFormat {
+    /// A directory with an OCI image layout.
+    #[default]
+    OciDir,
+    /// A tarball with an OCI image layout.
+    OciArchive,
+    /// A tarball that can be loaded with `docker load`.
+    DockerArchive,
+}
+
+/// The image spec written by nix2container's `buildImage`.
+#[derive(Debug, Deserialize)]
+pub struct ImageSpec {
+    #[serde(rename = "image-config", default)]
+    pub config: serde_json::Value,
+    #[serde(default)]
+    pub layers: Vec<LayerSpec>,
+    #[serde(default = "default_arch")]
+    pub arch: String,
+    #[serde(default)]
+    pub created: Option<String>,
+}
+
+fn default_arch() -> String {
+    "amd64".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LayerSpec {
+    /// The uncompressed digest of a prebuilt layer.
+    #[serde(rename = "diff_ids", default)]
+    pub diff_id: String,
+    #[serde(default)]
+    pub paths: Vec<PathSpec>,
+    #[serde(rename = "mediatype", default)]
+    pub media_type: String,
+    /// A prebuilt layer tarball, for layers that come from a base image.
+    #[serde(rename = "layer-path", default)]
+    pub layer_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PathSpec {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub options: Option<PathOptions>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PathOptions {
+    #[serde(default)]
+    pub rewrite: Option<Rewrite>,
+    #[serde(default)]
+    pub perms: Vec<Perm>,
+}
+
+/// Relocate the files of a store path, for example to copy them to the root of the image.
+#[derive(Debug, Deserialize)]
+pub struct Rewrite {
+    pub regex: String,
+    #[serde(default)]
+    pub repl: String,
+}
+
+/// Ownership and permissions for the files that match `regex`.
+#[derive(Debug, Deserialize)]
+pub struct Perm {
+    pub regex: String,
+    /// Either an octal string, like `"0755"`, or a number.
+    #[serde(default)]
+    pub mode: Option<serde_json::Value>,
+    #[serde(default)]
+    pub uid: Option<u64>,
+    #[serde(default)]
+    pub gid: Option<u64>,
+    #[serde(default)]
+    pub uname: Option<String>,
+    #[serde(default)]
+    pub gname: Option<String>,
+}
+
+impl ImageSpec {
+    pub fn read(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read the image spec {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to parse the image spec {}", path.display()))
+    }
+}
+
+/// A reference to a blob in an image layout or registry.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Descriptor {
+    pub media_type: String,
+    pub digest: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+    /// The platform of an image, in the manifests of an image index.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<Platform>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Platform {
+    pub architecture: String,
+    pub os: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Manifest {
+    pub schema_version: u32,
+    pub media_type: String,
+    pub config: Descriptor,
+    pub layers: Vec<Descriptor>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Index {
+    pub schema_version: u32,
+    pub media_type: String,
+    pub manifests: Vec<Descriptor>,
+}
+
+/// An entry of the `manifest.json` that `docker load` reads.
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct DockerManifest {
+    config: String,
+    repo_tags: Vec<String>,
+    layers: Vec<String>,
+}
+
+/// An image written to the blobs of an OCI layout.
+#[derive(Debug)]
+pub struct Image {
+    pub manifest: Descriptor,
+    pub config: Descriptor,
+    pub layers: Vec<Descriptor>,
+}
+
+/// Export the image described by `spec` to `output`. `reference` is the `name:tag` of the image.
+///
+/// Returns the digest of the image manifest.
+pub fn export(
+    spec: &ImageSpec,
+    reference: &str,
+    format: ExportFormat,
+    output: &Path,
+) -> Result<String> {
+    let tag = reference.rsplit_once(':').map_or("latest", |(_, tag)| tag);
+
+    if format == ExportFormat::OciDir {
+        if output.exists() && fs::read_dir(output).into_diagnostic()?.next().is_some() {
+            bail!("{} already exists and is not empty.", output.display());
+        }
+        let image = write_image(spec, output)?;
+        write_index(output, &image.manifest, tag)?;
+        return Ok(image.manifest.digest);
+    }
+
+    // Write the layout next to the output, so large blobs don't have to cross filesystems.
+    let parent = match output.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let staging = tempfile::TempDir::with_prefix_in(".devenv-export.", parent)
+        .into_diagnostic()
+        .wrap_err("Failed to create a temporary directory")?;
+    let image = write_image(spec, staging.path())?;
+    write_index(staging.path(), &image.manifest, tag)?;
+
+    if format == ExportFormat::DockerArchive {
+        if let Some(layer) = image
+            .layers
+            .iter()
+            .find(|layer| layer.media_type != LAYER_MEDIA_TYPE)
+        {
+            bail!(
+                "The docker-archive format doesn't support the compressed layer {} from the base image. Use oci-dir or oci-archive instead.",
+                layer.digest
+            );
+        }
+        let manifest = [DockerManifest {
+            config: blob_name(&image.config.digest),
+            repo_tags: vec![reference.to_string()],
+            layers: image
+                .layers
+                .iter()
+                .map(|layer| blob_name(&layer.digest))
+                .collect(),
+        }];
+        fs::write(
+            staging.path().join("manifest.json"),
+            serde_json::to_vec(&manifest).into_diagnostic()?,
+        )
+        .into_diagnostic()
+        .wrap_err("Failed to write manifest.json")?;
+    }
+
+    let file = File::create(output)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to create {}", output.display()))?;
+    let mut tar = tar::Builder::new(BufWriter::new(file));
+    archive_dir(&mut tar, staging.path())
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to write {}", output.display()))?;
+    tar.into_inner()
+        .and_then(|mut out| out.flush())
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to write {}", output.display()))?;
+
+    Ok(image.manifest.digest)
+}
+
+/// Write the layers, config and manifest of an image to the blobs of an OCI layout in `dir`.
+pub fn write_image(spec: &ImageSpec, dir: &Path) -> Result<Image> {
+    let blobs = dir.join("blobs/sha256");
+    fs::create_dir_all(&blobs)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to create {}", blobs.display()))?;
+
+    let mut layers = Vec::new();
+    let mut diff_ids = Vec::new();
+    for layer in &spec.layers {
+        let (descriptor, diff_id) = write_layer(&blobs, layer)?;
+        tracing::debug!("Wrote layer {}", descriptor.digest);
+        layers.push(descriptor);
+        diff_ids.push(diff_id);
+    }
+
+    let created = spec.created.as_deref().unwrap_or(DEFAULT_CREATED);
+    let config = serde_json::json!({
+        "created": created,
+        "architecture": spec.arch,
+        "os": "linux",
+        "config": spec.config,
+        "rootfs": {
+            "type": "layers",
+            "diff_ids": diff_ids,
+        },
+    });
+    let config = write_blob(
+        &blobs,
+        CONFIG_MEDIA_TYPE,
+        &serde_json::to_vec(&config).into_diagnostic()?,
+    )?;
+
+    let manifest = Manifest {
+        schema_version: 2,
+        media_type: MANIFEST_MEDIA_TYPE.to_string(),
+        config: config.clone(),
+        layers: layers.clone(),
+    };
+    let mut manifest = write_blob(
+        &blobs,
+        MANIFEST_MEDIA_TYPE,
+        &serde_json::to_vec(&manifest).into_diagnostic()?,
+    )?;
+    manifest.platform = Some(Platform {
+        architecture: spec.arch.clone(),
+        os: "linux".to_string(),
+    });
+
+    Ok(Image {
+        manifest,
+        config,
+        layers,
+    })
+}
+
+/// Write the images for several platforms and an image index that lists them to an OCI layout in `dir`.
+///
+/// Returns the images and the descriptor of the index.
+pub fn write_multi_platform_image(
+    specs: &[ImageSpec],
+    dir: &Path,
+    tag: &str,
+) -> Result<(Vec<Image>, Descriptor)> {
+    let images = specs
+        .iter()
+        .map(|spec| write_image(spec, dir))
+        .collect::<Result<Vec<_>>>()?;
+    let mut platforms = BTreeSet::new();
+    for image in &images {
+        if let Some(platform) = &image.manifest.platform {
+            if !platforms.insert((&platform.os, &platform.architecture)) {
+                bail!(
+                    "More than one of the images is for {}/{}.",
+                    platform.os,
+                    platform.architecture
+                );
+            }
+        }
+    }
+
+    let index = Index {
+        schema_version: 2,
+        media_type: INDEX_MEDIA_TYPE.to_string(),
+        manifests: images.iter().map(|image| image.manifest.clone()).collect(),
+    };
+    let index = write_blob(
+        &dir.join("blobs/sha256"),
+        INDEX_MEDIA_TYPE,
+        &serde_json::to_vec(&index).into_diagnostic()?,
+    )?;
+    write_index(dir, &index, tag)?;
+    Ok((images, index))
+}
+
+/// Write the `oci-layout` marker and an `index.json` that points at an image manifest or index.
+fn write_index(dir: &Path, manifest: &Descriptor, tag: &str) -> Result<()> {
+    let mut manifest = manifest.clone();
+    manifest
+        .annotations
+        .insert(REF_NAME_ANNOTATION.to_string(), tag.to_string());
+    let index = Index {
+        schema_version: 2,
+        media_type: INDEX_MEDIA_TYPE.to_string(),
+        manifests: vec![manifest],
+    };
+    fs::write(dir.join("oci-layout"), r#"{"imageLayoutVersion":"1.0.0"}"#)
+        .into_diagnostic()
+        .wrap_err("Failed to write oci-layout")?;
+    fs::write(
+        dir.join("index.json"),
+        serde_json::to_vec(&index).into_diagnostic()?,
+    )
+    .into_diagnostic()
+    .wrap_err("Failed to write index.json")
+}
+
+/// Write a layer to the blobs directory. Returns its descriptor and uncompressed digest.
+fn write_layer(blobs: &Path, layer: &LayerSpec) -> Result<(Descriptor, String)> {
+    let file = tempfile::NamedTempFile::new_in(blobs)
+        .into_diagnostic()
+        .wrap_err("Failed to create a temporary file")?;
+    let mut writer = DigestWriter::new(BufWriter::new(file));
+
+    let media_type = if let Some(layer_path) = &layer.layer_path {
+        let mut source = File::open(layer_path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to open {}", layer_path.display()))?;
+        io::copy(&mut source, &mut writer)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to copy {}", layer_path.display()))?;
+        if layer.media_type.is_empty() {
+            LAYER_MEDIA_TYPE.to_string()
+        } else {
+            layer.media_type.clone()
+        }
+    } else {
+        let mut tar = tar::Builder::new(&mut writer);
+        let mut dirs = BTreeSet::new();
+        for path in &layer.paths {
+            append_store_path(&mut tar, path, &mut dirs)?;
+        }
+        tar.into_inner()
+            .into_diagnostic()
+            .wrap_err("Failed to write layer")?;
+        LAYER_MEDIA_TYPE.to_string()
+    };
+
+    let (file, digest, size) = writer.finish().into_diagnostic()?;
+    let file = file.into_inner().into_diagnostic()?;
+    file.persist(blobs.join(digest_hex(&digest)))
+        .into_diagnostic()
+        .wrap_err("Failed to write layer")?;
+
+    // Uncompressed layers are their own diff ID.
+    let diff_id = if media_type == LAYER_MEDIA_TYPE {
+        digest.clone()
+    } else {
+        layer.diff_id.clone()
+    };
+    let descriptor = Descriptor {
+        media_type,
+        digest,
+        size,
+        annotations: BTreeMap::new(),
+        platform: None,
+    };
+    Ok((descriptor, diff_id))
+}
+
+/// Write `data` to the blobs directory.
+pub fn write_blob(blobs: &Path, media_type: &str, data: &[u8]) -> Result<Descriptor> {
+    let digest = sha256_digest(data);
+    let path = blobs.join(digest_hex(&digest));
+    fs::write(&path, data)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+    Ok(Descriptor {
+        media_type: media_type.to_string(),
+        digest,
+        size: data.len() as u64,
+        annotations: BTreeMap::new(),
+        platform: None,
+    })
+}
+
+pub fn sha256_digest(data: &[u8]) -> String {
+    format!("sha256:{}", hex::encode(Sha256::digest(data)))
+}
+
+fn digest_hex(digest: &str) -> &str {
+    digest.strip_prefix("sha256:").unwrap_or(digest)
+}
+
+fn blob_name(digest: &str) -> String {
+    format!("blobs/sha256/{}", digest_hex(digest))
+}
+
+/// Add a store path to a layer, applying its rewrite and permissions.
+///
+/// `dirs` keeps track of the directories in the layer, so that each one is only added once
+/// and missing parents, like `nix/store`, can be created.
+fn append_store_path<W: Write>(
+    tar: &mut tar::Builder<W>,
+    spec: &PathSpec,
+    dirs: &mut BTreeSet<String>,
+) -> Result<()> {
+    let options = spec.options.as_ref();
+    let rewrite = options
+        .and_then(|options| options.rewrite.as_ref())
+        .map(|rewrite| {
+            Regex::new(&rewrite.regex)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Invalid rewrite regex {}", rewrite.regex))
+                .map(|regex| (regex, rewrite.repl.as_str()))
+        })
+        .transpose()?;
+    let perms = options
+        .map(|options| options.perms.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .map(|perm| {
+            Regex::new(&perm.regex)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Invalid permissions regex {}", perm.regex))
+                .map(|regex| (regex, perm))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut pending = vec![spec.path.clone()];
+    while let Some(source) = pending.pop() {
+        let metadata = fs::symlink_metadata(&source)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read {}", source.display()))?;
+        let Some(source_name) = source.to_str() else {
+            bail!("{} is not valid UTF-8", source.display());
+        };
+
+        let name = match &rewrite {
+            Some((regex, repl)) => regex.replace_all(source_name, *repl).into_owned(),
+            None => source_name.to_string(),
+        };
+        let name = name
+            .trim_start_matches('/')
+            .trim_end_matches('/')
+            .to_string();
+
+        let kind = if metadata.is_dir() {
+            // Walk the directory in reverse, so that entries are popped in order.
+            let mut children = fs::read_dir(&source)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to read {}", source.display()))?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<_>>>()
+                .into_diagnostic()?;
+            children.sort();
+            pending.extend(children.into_iter().rev());
+            EntryKind::Directory
+        } else if metadata.is_symlink() {
+            let target = fs::read_link(&source).into_diagnostic()?;
+            let Some(target) = target.to_str() else {
+                bail!("The target of {} is not valid UTF-8", source.display());
+            };
+            EntryKind::Symlink(target.to_string())
+        } else if metadata.is_file() {
+            EntryKind::File(metadata.len())
+        } else {
+            tracing::debug!("Skipping {}, which is not a regular file", source.display());
+            continue;
+        };
+
+        // The root of a path that's rewritten to `/`.
+        if name.is_empty() {
+            continue;
+        }
+
+        let mut entry = TarEntry {
+            name,
+            kind,
+            mode: metadata.permissions().mode() & 0o7777,
+            uid: 0,
+            gid: 0,
+            uname: String::new(),
+            gname: String::new(),
+        };
+        for (regex, perm) in &perms {
+            if !regex.is_match(source_name) {
+                continue;
+            }
+            if let Some(mode) = perm.mode.as_ref().and_then(parse_mode) {
+                entry.mode = mode;
+            }
+            if let Some(uid) = perm.uid {
+                entry.uid = uid;
+            }
+            if let Some(gid) = perm.gid {
+                entry.gid = gid;
+            }
+            if let Some(uname) = &perm.uname {
+                entry.uname = uname.clone();
+            }
+            if let Some(gname) = &perm.gname {
+                entry.gname = gname.clone();
+            }
+        }
+
+        append_parents(tar, &entry.name, dirs).into_diagnostic()?;
+        if matches!(entry.kind, EntryKind::Directory) && !dirs.insert(entry.name.clone()) {
+            continue;
+        }
+
+        let result = match entry.kind {
+            EntryKind::File(_) => {
+                let file = File::open(&source)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to open {}", source.display()))?;
+                append_entry(tar, &entry, file)
+            }
+            _ => append_entry(tar, &entry, io::empty()),
+        };
+        result
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to add {} to the layer", source.display()))?;
+    }
+    Ok(())
+}
+
+/// Add the parent directories of `name` that aren't in the layer yet.
+fn append_parents<W: Write>(
+    tar: &mut tar::Builder<W>,
+    name: &str,
+    dirs: &mut BTreeSet<String>,
+) -> io::Result<()> {
+    let mut parent = String::new();
+    let components = name.split('/').collect::<Vec<_>>();
+    for component in &components[..components.len() - 1] {
+        if !parent.is_empty() {
+            parent.push('/');
+        }
+        parent.push_str(component);
+        if dirs.insert(parent.clone()) {
+            append_entry(tar, &TarEntry::directory(&parent, 0o755), io::empty())?;
+        }
+    }
+    Ok(())
+}
+
+fn parse_mode(mode: &serde_json::Value) -> Option<u32> {
+    match mode {
+        serde_json::Value::String(mode) => u32::from_str_radix(mode, 8).ok(),
+        serde_json::Value::Number(mode) => mode.as_u64().map(|mode| mode as u32),
+        _ => None,
+    }
+}
+
+/// Add every file under `dir` to the archive, relative to `dir`.
+fn archive_dir<W: Write>(tar: &mut tar::Builder<W>, dir: &Path) -> io::Result<()> {
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let mut entries = fs::read_dir(&current)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+        for path in entries.into_iter().rev() {
+            let name = path
+                .strip_prefix(dir)
+                .expect("entries are under the archived directory")
+                .to_string_lossy()
+                .to_string();
+            if path.is_dir() {
+                append_entry(tar, &TarEntry::directory(&name, 0o755), io::empty())?;
+                pending.push(path);
+            } else {
+                let file = File::open(&path)?;
+                let entry = TarEntry {
+                    kind: EntryKind::File(file.metadata()?.len()),
+                    mode: 0o644,
+                    ..TarEntry::directory(&name, 0)
+                };
+                append_entry(tar, &entry, file)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Hashes everything written through it.
+struct DigestWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<W: Write> DigestWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    /// Flush the writer and return it, along with the digest and size of what was written.
+    fn finish(mut self) -> io::Result<(W, String, u64)> {
+        self.inner.flush()?;
+        let digest = format!("sha256:{}", hex::encode(self.hasher.finalize()));
+        Ok((self.inner, digest, self.size))
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+enum EntryKind {
+    Directory,
+    File(u64),
+    Symlink(String),
+}
+
+struct TarEntry {
+    name: String,
+    kind: EntryKind,
+    mode: u32,
+    uid: u64,
+    gid: u64,
+    uname: String,
+    gname: String,
+}
+
+impl TarEntry {
+    fn directory(name: &str, mode: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            kind: EntryKind::Directory,
+            mode,
+            uid: 0,
+            gid: 0,
+            uname: String::new(),
+            gname: String::new(),
+        }
+    }
+}
+
+/// Add an entry with a fixed mtime, so that archives of the same files are identical.
+fn append_entry<W: Write>(
+    tar: &mut tar::Builder<W>,
+    entry: &TarEntry,
+    data: impl Read,
+) -> io::Result<()> {
+    let mut header = tar::Header::new_ustar();
+    header.set_mtime(MTIME);
+    header.set_mode(entry.mode);
+    header.set_uid(entry.uid);
+    header.set_gid(entry.gid);
+    header.set_size(0);
+
+    // Names that don't fit in the header go in a PAX header instead.
+    let mut extensions = Vec::new();
+    if header.set_username(&entry.uname).is_err() {
+        extensions.push(("uname", entry.uname.as_bytes()));
+    }
+    if header.set_groupname(&entry.gname).is_err() {
+        extensions.push(("gname", entry.gname.as_bytes()));
+    }
+    tar.append_pax_extensions(extensions)?;
+
+    match &entry.kind {
+        EntryKind::Directory => {
+            header.set_entry_type(tar::EntryType::Directory);
+            tar.append_data(&mut header, format!("{}/", entry.name), io::empty())
+        }
+        EntryKind::File(size) => {
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(*size);
+            tar.append_data(&mut header, &entry.name, data.take(*size))
+        }
+        EntryKind::Symlink(target) => {
+            header.set_entry_type(tar::EntryType::Symlink);
+            tar.append_link(&mut header, &entry.name, target)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    /// The name, type and mode of each entry in a tar archive.
+    fn list_tar(data: &[u8]) -> Vec<(String, u8, u32)> {
+        tar::Archive::new(data)
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let header = entry.header();
+                (
+                    entry.path().unwrap().to_str().unwrap().to_string(),
+                    header.entry_type().as_byte(),
+                    header.mode().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keeps_large_ids_and_long_names() {
+        let mut entry = TarEntry::directory("nix", 0o755);
+        entry.uid = 100_000_000;
+        entry.gid = 1000;
+        entry.uname = "u".repeat(40);
+        entry.gname = "nixbld".to_string();
+        let mut builder = tar::Builder::new(Vec::new());
+        append_entry(&mut builder, &entry, io::empty()).unwrap();
+        let data = builder.into_inner().unwrap();
+
+        let mut archive = tar::Archive::new(data.as_slice());
+        let mut entries = archive.entries().unwrap();
+        let mut entry = entries.next().unwrap().unwrap();
+        let uname = entry
+            .pax_extensions()
+            .unwrap()
+            .unwrap()
+            .map(Result::unwrap)
+            .find(|extension| extension.key() == Ok("uname"))
+            .map(|extension| extension.value().unwrap().to_string());
+        assert_eq!(uname, Some("u".repeat(40)));
+        let header = entry.header();
+        assert_eq!(header.mtime().unwrap(), MTIME);
+        assert_eq!(header.uid().unwrap(), 100_000_000);
+        assert_eq!(header.gid().unwrap(), 1000);
+        assert_eq!(header.groupname().unwrap(), Some("nixbld"));
+        assert!(entries.next().is_none());
+    }
+
+    #[test]
+    fn writes_layers_from_store_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join("store");
+        let package = store.join("abc-hello");
+        let root = store.join("def-root");
+        fs::create_dir_all(package.join("bin")).unwrap();
+        fs::write(package.join("bin/hello"), "#!/bin/sh\necho hello\n").unwrap();
+        fs::set_permissions(package.join("bin/hello"), fs::Permissions::from_mode(0o555)).unwrap();
+        let long_name = "l".repeat(120);
+        fs::write(package.join(&long_name), "").unwrap();
+        fs::create_dir_all(root.join("data")).unwrap();
+        symlink(package.join("bin/hello"), root.join("hello")).unwrap();
+
+        let root_name = root.to_str().unwrap();
+        let layer = LayerSpec {
+            diff_id: String::new(),
+            media_type: String::new(),
+            layer_path: None,
+            paths: vec![
+                PathSpec {
+                    path: package.clone(),
+                    options: None,
+                },
+                PathSpec {
+                    path: root.clone(),
+                    options: Some(PathOptions {
+                        rewrite: Some(Rewrite {
+                            regex: format!("^{}", regex::escape(root_name)),
+                            repl: String::new(),
+                        }),
+                        perms: vec![Perm {
+                            regex: "/data$".to_string(),
+                            mode: Some(serde_json::json!("1777")),
+                            uid: None,
+                            gid: None,
+                            uname: None,
+                            gname: None,
+                        }],
+                    }),
+                },
+            ],
+        };
+
+        let blobs = dir.path().join("blobs");
+        fs::create_dir_all(&blobs).unwrap();
+        let (descriptor, diff_id) = write_layer(&blobs, &layer).unwrap();
+        let data = fs::read(blobs.join(digest_hex(&descriptor.digest))).unwrap();
+        assert_eq!(descriptor.digest, sha256_digest(&data));
+        assert_eq!(descriptor.size, data.len() as u64);
+        assert_eq!(diff_id, descriptor.digest);
+
+        let package_name = package.to_str().unwrap().trim_start_matches('/');
+        let entries = list_tar(&data);
+        let names = entries
+            .iter()
+            .map(|(name, _, _)| name.as_str())
+            .collect::<Vec<_>>();
+        // The parents of the store path are created once.
+        let first = package_name.split('/').next().unwrap();
+        assert_eq!(
+            names
+                .iter()
+                .filter(|name| **name == format!("{first}/"))
+                .count(),
+            1
+        );
+        assert!(names.contains(&format!("{package_name}/").as_str()));
+        assert!(names.contains(&format!("{package_name}/{long_name}").as_str()));
+        assert!(entries.contains(&(format!("{package_name}/bin/hello"), b'0', 0o555)));
+        // The root is relocated to `/`, with its permissions.
+        assert!(entries.contains(&("data/".to_string(), b'5', 0o1777)));
+        assert!(entries
+            .iter()
+            .any(|(name, kind, _)| name == "hello" && *kind == b'2'));
+        assert!(!names.iter().any(|name| name.contains("def-root")));
+    }
+
+    #[test]
+    fn exports_oci_layouts_and_docker_archives() {
+        let dir = tempfile::tempdir().unwrap();
+        let package = dir.path().join("abc-hello");
+        fs::create_dir_all(&package).unwrap();
+        fs::write(package.join("hello"), "hello").unwrap();
+        let spec: ImageSpec = serde_json::from_value(serde_json::json!({
+            "version": 1,
+            "image-config": { "Cmd": ["hello"] },
+            "layers": [{
+                "digest": "sha256:ignored",
+                "size": 1,
+                "diff_ids": "sha256:ignored",
+                "paths": [{ "path": package }],
+                "mediatype": LAYER_MEDIA_TYPE,
+            }],
+            "arch": "arm64",
+        }))
+        .unwrap();
+
+        let layout = dir.path().join("layout");
+        let digest = export(&spec, "myapp:1.0", ExportFormat::OciDir, &layout).unwrap();
+        let index: Index =
+            serde_json::from_slice(&fs::read(layout.join("index.json")).unwrap()).unwrap();
+        assert_eq!(index.manifests[0].digest, digest);
+        assert_eq!(index.manifests[0].annotations[REF_NAME_ANNOTATION], "1.0");
+        let manifest_data = fs::read(layout.join(blob_name(&digest))).unwrap();
+        assert_eq!(sha256_digest(&manifest_data), digest);
+        let manifest: Manifest = serde_json::from_slice(&manifest_data).unwrap();
+        let config: serde_json::Value = serde_json::from_slice(
+            &fs::read(layout.join(blob_name(&manifest.config.digest))).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(config["architecture"], "arm64");
+        assert_eq!(config["config"]["Cmd"][0], "hello");
+        assert_eq!(
+            config["rootfs"]["diff_ids"][0],
+            manifest.layers[0].digest.as_str()
+        );
+
+        // Exporting is reproducible.
+        let archive = dir.path().join("myapp.tar");
+        let archived = export(&spec, "myapp:1.0", ExportFormat::DockerArchive, &archive).unwrap();
+        assert_eq!(archived, digest);
+        let names = list_tar(&fs::read(&archive).unwrap())
+            .into_iter()
+            .map(|(name, _, _)| name)
+            .collect::<Vec<_>>();
+        assert!(names.contains(&"manifest.json".to_string()));
+        assert!(names.contains(&"oci-layout".to_string()));
+        assert!(names.contains(&blob_name(&manifest.layers[0].digest)));
+
+        assert!(export(&spec, "myapp:1.0", ExportFormat::OciDir, &layout).is_err());
+    }
+
+    #[test]
+    fn writes_image_indexes() {
+        let dir = tempfile::tempdir().unwrap();
+        let spec = |arch: &str| -> ImageSpec {
+            serde_json::from_value(serde_json::json!({
+                "image-config": { "Cmd": [format!("hello-{arch}")] },
+                "arch": arch,
+            }))
+            .unwrap()
+        };
+
+        let layout = dir.path().join("layout");
+        let (images, index) =
+            write_multi_platform_image(&[spec("amd64"), spec("arm64")], &layout, "1.0").unwrap();
+        assert_eq!(index.media_type, INDEX_MEDIA_TYPE);
+
+        let layout_index: Index =
+            serde_json::from_slice(&fs::read(layout.join("index.json")).unwrap()).unwrap();
+        assert_eq!(layout_index.manifests[0].digest, index.digest);
+        assert_eq!(
+            layout_index.manifests[0].annotations[REF_NAME_ANNOTATION],
+            "1.0"
+        );
+
+        let image_index: Index =
+            serde_json::from_slice(&fs::read(layout.join(blob_name(&index.digest))).unwrap())
+                .unwrap();
+        let platforms = image_index
+            .manifests
+            .iter()
+            .map(|manifest| manifest.platform.clone().unwrap().architecture)
+            .collect::<Vec<_>>();
+        assert_eq!(platforms, vec!["amd64", "arm64"]);
+        assert_eq!(image_index.manifests[1].digest, images[1].manifest.digest);
+
+        assert!(write_multi_platform_image(
+            &[spec("amd64"), spec("amd64")],
+            &dir.path().join("duplicates"),
+            "1.0"
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,111 @@
+//! Export devenv's tracing spans to an OpenTelemetry collector over OTLP/HTTP.
+//!
+//! Enabled with the `otel` cargo feature and the `--otlp-endpoint` flag.
+//! `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` takes precedence over the flag.
+
+use miette::{IntoDiagnostic, Result};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::error::OTelSdkError;
+use opentelemetry_sdk::trace::{SdkTracerProvider, Tracer};
+use opentelemetry_sdk::Resource;
+use std::sync::OnceLock;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::Registry;
+
+/// The provider of the current guard, so that spans can be flushed from wherever devenv
+/// replaces its process with `exec`, which never drops the guard.
+static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+/// Flushes and shuts down the exporter when dropped.
+pub struct OtelGuard {
+    provider: SdkTracerProvider,
+}
+
+impl OtelGuard {
+    /// Flush pending spans and stop exporting.
+    pub fn shutdown(&self) {
+        shutdown_provider(&self.provider);
+    }
+}
+
+impl Drop for OtelGuard {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Flush pending spans and stop exporting, if an exporter was set up.
+pub fn shutdown() {
+    if let Some(provider) = PROVIDER.get() {
+        shutdown_provider(provider);
+    }
+}
+
+fn shutdown_provider(provider: &SdkTracerProvider) {
+    match provider.shutdown() {
+        Ok(()) | Err(OTelSdkError::AlreadyShutdown) => {}
+        Err(err) => eprintln!("Failed to flush OpenTelemetry traces: {err}"),
+    }
+}
+
+/// Create a layer that exports spans to the collector at `endpoint`.
+///
+/// The endpoint is the base URL of the collector's OTLP/HTTP receiver, e.g. `http://localhost:4318`.
+pub fn layer(endpoint: &str) -> Result<(OpenTelemetryLayer<Registry, Tracer>, OtelGuard)> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(traces_endpoint(endpoint))
+        .build()
+        .into_diagnostic()?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name("devenv")
+                .with_attribute(opentelemetry::KeyValue::new(
+                    "service.version",
+                    clap::crate_version!(),
+                ))
+                .build(),
+        )
+        .build();
+
+    let _ = PROVIDER.set(provider.clone());
+    let tracer = provider.tracer("devenv");
+    let layer = tracing_opentelemetry::layer().with_tracer(tracer);
+
+    Ok((layer, OtelGuard { provider }))
+}
+
+/// Append the traces path to the collector's base URL, unless it's already there.
+fn traces_endpoint(endpoint: &str) -> String {
+    let endpoint = endpoint.trim_end_matches('/');
+    if endpoint.ends_with("/v1/traces") {
+        endpoint.to_string()
+    } else {
+        format!("{endpoint}/v1/traces")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traces_endpoint() {
+        assert_eq!(
+            traces_endpoint("http://localhost:4318"),
+            "http://localhost:4318/v1/traces"
+        );
+        assert_eq!(
+            traces_endpoint("http://localhost:4318/"),
+            "http://localhost:4318/v1/traces"
+        );
+        assert_eq!(
+            traces_endpoint("http://localhost:4318/v1/traces"),
+            "http://localhost:4318/v1/traces"
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! A registry of the projects that devenv has been used in, kept in devenv's data home.
+//!
+//! The links in the GC directory of the data home don't say which project they came from,
+//! so the registry remembers the environments that each project has built.
+
+use crate::gc::{self, GcRoot};
+use miette::{IntoDiagnostic, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const REGISTRY_FILE: &str = "projects.json";
+
+#[derive(Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Project {
+    /// The `.devenv` directory of the project.
+    pub dotfile: PathBuf,
+    /// Seconds since the Unix epoch.
+    pub last_used: u64,
+    /// The store paths of the shells that the project has built.
+    #[serde(default)]
+    pub environments: BTreeSet<PathBuf>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Registry {
+    #[serde(default)]
+    pub projects: BTreeMap<PathBuf, Project>,
+}
+
+impl Registry {
+    /// Read the registry at `path`, which is empty if it doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Self::parse(&contents, path),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Change the registry at `path` while holding a lock on it, so that concurrent
+    /// devenv commands don't lose each other's changes.
+    pub fn update<T>(path: &Path, change: impl FnOnce(&mut Self) -> T) -> Result<T> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+        let mut lock = fd_lock::RwLock::new(file);
+        let mut file = lock
+            .write()
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to lock {}", path.display()))?;
+
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+        let mut registry = Self::parse(&contents, path)?;
+        let result = change(&mut registry);
+
+        let contents = serde_json::to_string_pretty(&registry).into_diagnostic()?;
+        file.set_len(0)
+            .and_then(|_| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| file.write_all(contents.as_bytes()))
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+        Ok(result)
+    }
+
+    fn parse(contents: &str, path: &Path) -> Result<Self> {
+        if contents.trim().is_empty() {
+            return Ok(Self::default());
+        }
+        serde_json::from_str(contents)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Record that the project at `root` was used, and the environment it built, if any.
+    ///
+    /// Environments that have been garbage collected are dropped.
+    pub fn record(
+        &mut self,
+        root: &Path,
+        dotfile: &Path,
+        environment: Option<&Path>,
+        now: SystemTime,
+    ) {
+        let project = self.projects.entry(root.to_path_buf()).or_default();
+        project.dotfile = dotfile.to_path_buf();
+        project.last_used = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        project.environments.retain(|path| path.exists());
+        if let Some(environment) = environment {
+            project.environments.insert(environment.to_path_buf());
+        }
+    }
+
+    pub fn forget(&mut self, root: &Path) -> Option<Project> {
+        self.projects.remove(root)
+    }
+}
+
+impl Project {
+    pub fn last_used(&self) -> SystemTime {
+        UNIX_EPOCH + std::time::Duration::from_secs(self.last_used)
+    }
+
+    /// The links that keep the environments of the project alive: the ones in its `.devenv`,
+    /// including those of its profiles, and those among `home_roots` that point at its environments.
+    pub fn gc_roots(&self, home_roots: &[GcRoot]) -> Result<Vec<GcRoot>> {
+        let mut gc_dirs = vec![self.dotfile.join("gc")];
+        if let Ok(profiles) = fs::read_dir(self.dotfile.join("profiles")) {
+            gc_dirs.extend(profiles.flatten().map(|profile| profile.path().join("gc")));
+        }
+
+        let mut roots = Vec::new();
+        for dir in gc_dirs {
+            let (dir_roots, _) = gc::read_roots(&dir)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to read {}", dir.display()))?;
+            roots.extend(dir_roots);
+        }
+        roots.extend(
+            home_roots
+                .iter()
+                .filter(|root| self.environments.contains(&root.target))
+                .cloned(),
+        );
+        Ok(roots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use std::time::Duration;
+
+    #[test]
+    fn records_and_forgets_projects() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(REGISTRY_FILE);
+        let root = dir.path().join("project");
+        let environment = dir.path().join("environment");
+        fs::create_dir(&environment).unwrap();
+        let gone = dir.path().join("gone");
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+
+        Registry::update(&path, |registry| {
+            registry.record(&root, &root.join(".devenv"), Some(&gone), now)
+        })
+        .unwrap();
+        Registry::update(&path, |registry| {
+            registry.record(&root, &root.join(".devenv"), Some(&environment), now)
+        })
+        .unwrap();
+
+        let registry = Registry::load(&path).unwrap();
+        assert_eq!(
+            registry.projects[&root],
+            Project {
+                dotfile: root.join(".devenv"),
+                last_used: 1000,
+                environments: BTreeSet::from([environment]),
+            }
+        );
+
+        let forgotten = Registry::update(&path, |registry| registry.forget(&root)).unwrap();
+        assert!(forgotten.is_some());
+        assert!(Registry::load(&path).unwrap().projects.is_empty());
+        assert!(Registry::load(&dir.path().join("missing.json"))
+            .unwrap()
+            .projects
+            .is_empty());
+    }
+
+    #[test]
+    fn finds_the_gc_roots_of_a_project() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = dir.path().join("store");
+        let (shell, profile_shell, other) =
+            (store.join("shell"), store.join("dev"), store.join("other"));
+        for path in [&shell, &profile_shell, &other] {
+            fs::create_dir_all(path).unwrap();
+        }
+        let dotfile = dir.path().join("project/.devenv");
+        fs::create_dir_all(dotfile.join("gc")).unwrap();
+        fs::create_dir_all(dotfile.join("profiles/dev/gc")).unwrap();
+        symlink(&shell, dotfile.join("gc/shell")).unwrap();
+        symlink(&profile_shell, dotfile.join("profiles/dev/gc/shell")).unwrap();
+
+        let home_gc = dir.path().join("home-gc");
+        fs::create_dir(&home_gc).unwrap();
+        symlink(&shell, home_gc.join("1.0-shell")).unwrap();
+        symlink(&other, home_gc.join("2.0-shell")).unwrap();
+        let (home_roots, _) = gc::read_roots(&home_gc).unwrap();
+
+        let project = Project {
+            dotfile: dotfile.clone(),
+            last_used: 0,
+            environments: BTreeSet::from([shell.canonicalize().unwrap()]),
+        };
+        let mut links: Vec<_> = project
+            .gc_roots(&home_roots)
+            .unwrap()
+            .into_iter()
+            .map(|root| root.link)
+            .collect();
+        links.sort();
+        assert_eq!(
+            links,
+            vec![
+                home_gc.join("1.0-shell"),
+                dotfile.join("gc/shell"),
+                dotfile.join("profiles/dev/gc/shell"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,904 @@
+//! Push images to container registries with the OCI distribution API,
+//! without going through skopeo or a Docker daemon.
+
+use crate::oci::{Descriptor, Image};
+use base64::Engine;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use indicatif::ProgressStyle;
+use miette::{bail, IntoDiagnostic, Result, WrapErr};
+use reqwest::{header, Method, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tokio::io::AsyncReadExt;
+use tracing::{debug, info, info_span, warn, Instrument, Span};
+use tracing_indicatif::span_ext::IndicatifSpanExt;
+
+/// The username to push with, instead of the one from the Docker config.
+pub const USERNAME_ENV: &str = "DEVENV_REGISTRY_USERNAME";
+/// The password or token to push with, instead of the one from the Docker config.
+pub const PASSWORD_ENV: &str = "DEVENV_REGISTRY_PASSWORD";
+
+const DOCKER_HUB: &str = "docker.io";
+const DOCKER_HUB_API: &str = "registry-1.docker.io";
+/// The key of Docker Hub in the Docker config.
+const DOCKER_HUB_SERVER: &str = "https://index.docker.io/v1/";
+
+const MAX_PARALLEL_UPLOADS: usize = 4;
+const UPLOAD_CHUNK_SIZE: usize = 256 * 1024;
+
+/// The image to push to, like `ghcr.io/owner/app:1.0`.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Reference {
+    pub registry: String,
+    pub repository: String,
+    pub tag: Option<String>,
+}
+
+impl Reference {
+    /// Parse a reference, with or without the `docker://` prefix that skopeo uses.
+    ///
+    /// References without a registry, like `owner/app`, point at Docker Hub.
+    pub fn parse(reference: &str) -> Result<Self> {
+        let reference = reference.strip_prefix("docker://").unwrap_or(reference);
+        if reference.contains('@') {
+            bail!("Can't push to {reference}: pushing to a digest isn't supported, use a tag.");
+        }
+
+        let (name, tag) = match reference.rsplit_once(':') {
+            Some((name, tag)) if !tag.contains('/') => (name, Some(tag.to_string())),
+            _ => (reference, None),
+        };
+        let (registry, repository) = match name.split_once('/') {
+            Some((host, repository))
+                if host.contains('.') || host.contains(':') || host == "localhost" =>
+            {
+                (host.to_string(), repository.to_string())
+            }
+            Some(_) => (DOCKER_HUB.to_string(), name.to_string()),
+            None => (DOCKER_HUB.to_string(), format!("library/{name}")),
+        };
+
+        if repository.is_empty() || repository.ends_with('/') {
+            bail!("{reference} is missing the name of the image.");
+        }
+        if repository.chars().any(|c| c.is_ascii_uppercase()) {
+            bail!("Can't push to {reference}: image names must be lowercase.");
+        }
+        Ok(Self {
+            registry,
+            repository,
+            tag,
+        })
+    }
+
+    /// The URL of the registry API.
+    fn base_url(&self) -> String {
+        let host = self
+            .registry
+            .rsplit_once(':')
+            .map_or(self.registry.as_str(), |(host, _)| host);
+        if self.registry == DOCKER_HUB {
+            format!("https://{DOCKER_HUB_API}")
+        } else if matches!(host, "localhost" | "127.0.0.1" | "[::1]") {
+            format!("http://{}", self.registry)
+        } else {
+            format!("https://{}", self.registry)
+        }
+    }
+}
+
+impl std::fmt::Display for Reference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.registry, self.repository)?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{tag}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// Find the credentials for a registry.
+///
+/// [USERNAME_ENV] and [PASSWORD_ENV] take precedence over the Docker config
+/// in `$DOCKER_CONFIG/config.json` or `~/.docker/config.json`, which is written by `docker login`.
+pub fn credentials(registry: &str) -> Result<Option<Credentials>> {
+    if let (Ok(username), Ok(password)) = (env::var(USERNAME_ENV), env::var(PASSWORD_ENV)) {
+        return Ok(Some(Credentials { username, password }));
+    }
+
+    let Some(path) = docker_config_path() else {
+        return Ok(None);
+    };
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(e)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to read {}", path.display()))
+        }
+    };
+    let config: DockerConfig = serde_json::from_str(&contents)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to parse {}", path.display()))?;
+    config.credentials(registry)
+}
+
+fn docker_config_path() -> Option<PathBuf> {
+    match env::var_os("DOCKER_CONFIG") {
+        Some(dir) => Some(PathBuf::from(dir).join("config.json")),
+        None => env::var_os("HOME").map(|home| PathBuf::from(home).join(".docker/config.json")),
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DockerConfig {
+    #[serde(default)]
+    auths: BTreeMap<String, DockerAuth>,
+    #[serde(default)]
+    creds_store: Option<String>,
+    #[serde(default)]
+    cred_helpers: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DockerAuth {
+    /// Base64 of `username:password`.
+    #[serde(default)]
+    auth: Option<String>,
+    #[serde(default)]
+    username: Option<String>,
+    #[serde(default)]
+    password: Option<String>,
+}
+
+impl DockerConfig {
+    fn credentials(&self, registry: &str) -> Result<Option<Credentials>> {
+        let server = if registry == DOCKER_HUB {
+            DOCKER_HUB_SERVER
+        } else {
+            registry
+        };
+
+        let helper = self
+            .cred_helpers
+            .iter()
+            .find(|(key, _)| server_host(key) == server_host(server))
+            .map(|(_, helper)| helper)
+            .or(self.creds_store.as_ref());
+        if let Some(helper) = helper {
+            if let Some(credentials) = credential_helper(helper, server)? {
+                return Ok(Some(credentials));
+            }
+        }
+
+        let Some(auth) = self
+            .auths
+            .iter()
+            .find(|(key, _)| server_host(key) == server_host(server))
+            .map(|(_, auth)| auth)
+        else {
+            return Ok(None);
+        };
+        if let (Some(username), Some(password)) = (&auth.username, &auth.password) {
+            return Ok(Some(Credentials {
+                username: username.clone(),
+                password: password.clone(),
+            }));
+        }
+        let Some(encoded) = auth.auth.as_deref().filter(|auth| !auth.is_empty()) else {
+            return Ok(None);
+        };
+        let decoded = base64::engine::general_purpose::STANDARD
+            .decode(encoded.trim())
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to decode the Docker credentials for {registry}"))?;
+        let decoded = String::from_utf8(decoded).into_diagnostic()?;
+        let Some((username, password)) = decoded.split_once(':') else {
+            bail!("The Docker credentials for {registry} are not in the `username:password` form.");
+        };
+        Ok(Some(Credentials {
+            username: username.to_string(),
+            password: password.to_string(),
+        }))
+    }
+}
+
+/// The host of a server in the Docker config, which may be a URL like `https://index.docker.io/v1/`.
+fn server_host(server: &str) -> &str {
+    let server = server
+        .strip_prefix("https://")
+        .or_else(|| server.strip_prefix("http://"))
+        .unwrap_or(server);
+    server.split('/').next().unwrap_or(server)
+}
+
+/// Ask a Docker credential helper, like `docker-credential-osxkeychain`, for credentials.
+fn credential_helper(helper: &str, server: &str) -> Result<Option<Credentials>> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "PascalCase")]
+    struct HelperCredentials {
+        username: String,
+        secret: String,
+    }
+
+    let program = format!("docker-credential-{helper}");
+    let child = Command::new(&program)
+        .arg("get")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("Failed to run the Docker credential helper {program}: {e}");
+            return Ok(None);
+        }
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(server.as_bytes()).into_diagnostic()?;
+    }
+    let output = child.wait_with_output().into_diagnostic()?;
+    if !output.status.success() {
+        debug!(
+            "{program} has no credentials for {server}: {}",
+            String::from_utf8_lossy(&output.stdout).trim()
+        );
+        return Ok(None);
+    }
+    let credentials: HelperCredentials =
+        serde_json::from_slice(&output.stdout)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to parse the output of {program}"))?;
+    Ok(Some(Credentials {
+        username: credentials.username,
+        password: credentials.secret,
+    }))
+}
+
+/// Parse a `WWW-Authenticate` header, like `Bearer realm="https://auth.docker.io/token",service="registry.docker.io"`.
+fn parse_challenge(header: &str) -> Option<(String, BTreeMap<String, String>)> {
+    let (scheme, rest) = header.trim().split_once(' ').unwrap_or((header.trim(), ""));
+    let mut params = BTreeMap::new();
+    let mut rest = rest.trim();
+    while !rest.is_empty() {
+        let (key, value) = rest.split_once('=')?;
+        let key = key.trim().to_lowercase();
+        let value = value.trim_start();
+        let (value, remaining) = if let Some(quoted) = value.strip_prefix('"') {
+            let end = quoted.find('"')?;
+            (&quoted[..end], &quoted[end + 1..])
+        } else {
+            value
+                .split_once(',')
+                .map_or((value, ""), |(value, rest)| (value, rest))
+        };
+        params.insert(key, value.to_string());
+        rest = remaining.trim_start_matches([',', ' ']);
+    }
+    Some((scheme.to_lowercase(), params))
+}
+
+/// A client for one repository in a registry.
+pub struct Client {
+    http: reqwest::Client,
+    base: String,
+    repository: String,
+    /// The value of the `Authorization` header.
+    authorization: Option<String>,
+}
+
+impl Client {
+    /// Connect to the registry of `reference`, and log in to push to its repository.
+    pub async fn connect(reference: &Reference, credentials: Option<Credentials>) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .use_preconfigured_tls(http_client_tls::tls_config())
+            .user_agent(format!("devenv/{}", env!("CARGO_PKG_VERSION")))
+            .build()
+            .into_diagnostic()
+            .wrap_err("Failed to create HTTP client")?;
+        let mut client = Self {
+            http,
+            base: reference.base_url(),
+            repository: reference.repository.clone(),
+            authorization: None,
+        };
+
+        let response = client
+            .http
+            .get(format!("{}/v2/", client.base))
+            .send()
+            .await
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to connect to {}", reference.registry))?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            check(response, &format!("connect to {}", reference.registry)).await?;
+            return Ok(client);
+        }
+
+        let login_hint = || match &credentials {
+            Some(_) => String::new(),
+            None => format!(
+                " Log in with `docker login {}`, or set {USERNAME_ENV} and {PASSWORD_ENV}.",
+                reference.registry
+            ),
+        };
+        let challenge = response
+            .headers()
+            .get(header::WWW_AUTHENTICATE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_challenge);
+        match challenge {
+            Some((scheme, params)) if scheme == "bearer" => {
+                let Some(realm) = params.get("realm") else {
+                    bail!(
+                        "{} asked for a token without saying where to get it.",
+                        reference.registry
+                    );
+                };
+                let scope = format!("repository:{}:pull,push", client.repository);
+                let mut query = vec![("scope", scope.as_str())];
+                if let Some(service) = params.get("service") {
+                    query.push(("service", service));
+                }
+                let mut request = client.http.get(realm).query(&query);
+                if let Some(credentials) = &credentials {
+                    request =
+                        request.basic_auth(&credentials.username, Some(&credentials.password));
+                }
+                let response = request
+                    .send()
+                    .await
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to log in to {}", reference.registry))?;
+                if !response.status().is_success() {
+                    bail!(
+                        "Failed to log in to {}: {}.{}",
+                        reference.registry,
+                        response.status(),
+                        login_hint()
+                    );
+                }
+
+                #[derive(Deserialize)]
+                struct Token {
+                    token: Option<String>,
+                    access_token: Option<String>,
+                }
+                let token: Token = response
+                    .json()
+                    .await
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to parse the token from {realm}"))?;
+                let Some(token) = token.token.or(token.access_token) else {
+                    bail!("{realm} didn't return a token.");
+                };
+                client.authorization = Some(format!("Bearer {token}"));
+            }
+            Some((scheme, _)) if scheme == "basic" => {
+                let Some(credentials) = &credentials else {
+                    bail!("{} requires a login.{}", reference.registry, login_hint());
+                };
+                let encoded = base64::engine::general_purpose::STANDARD
+                    .encode(format!("{}:{}", credentials.username, credentials.password));
+                client.authorization = Some(format!("Basic {encoded}"));
+            }
+            _ => bail!(
+                "{} requires an unsupported kind of authentication.",
+                reference.registry
+            ),
+        }
+        Ok(client)
+    }
+
+    fn request(&self, method: Method, url: &str) -> RequestBuilder {
+        let request = self.http.request(method, url);
+        match &self.authorization {
+            Some(authorization) => request.header(header::AUTHORIZATION, authorization),
+            None => request,
+        }
+    }
+
+    async fn has_blob(&self, digest: &str) -> Result<bool> {
+        let url = format!("{}/v2/{}/blobs/{digest}", self.base, self.repository);
+        let response = self
+            .request(Method::HEAD, &url)
+            .send()
+            .await
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to check for {digest}"))?;
+        Ok(response.status().is_success())
+    }
+
+    /// Upload a blob from `path`, unless the registry already has it.
+    ///
+    /// Returns whether the blob was uploaded. Progress is reported on the current span.
+    pub async fn push_blob(&self, blob: &Descriptor, path: &Path) -> Result<bool> {
+        if self.has_blob(&blob.digest).await? {
+            debug!("{} already exists", blob.digest);
+            return Ok(false);
+        }
+
+        let url = format!("{}/v2/{}/blobs/uploads/", self.base, self.repository);
+        let response = self
+            .request(Method::POST, &url)
+            .send()
+            .await
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to start the upload of {}", blob.digest))?;
+        let response = check(response, &format!("start the upload of {}", blob.digest)).await?;
+        let Some(location) = response
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|value| value.to_str().ok())
+        else {
+            bail!("The registry didn't say where to upload {}.", blob.digest);
+        };
+        let location = if location.starts_with('/') {
+            format!("{}{location}", self.base)
+        } else {
+            location.to_string()
+        };
+        let separator = if location.contains('?') { '&' } else { '?' };
+        let url = format!("{location}{separator}digest={}", blob.digest);
+
+        let file = tokio::fs::File::open(path)
+            .await
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to open {}", path.display()))?;
+        let span = Span::current();
+        let body = stream::try_unfold(file, move |mut file| {
+            let span = span.clone();
+            async move {
+                let mut chunk = vec![0; UPLOAD_CHUNK_SIZE];
+                let read = file.read(&mut chunk).await?;
+                if read == 0 {
+                    return Ok::<_, std::io::Error>(None);
+                }
+                chunk.truncate(read);
+                span.pb_inc(read as u64);
+                Ok(Some((chunk, file)))
+            }
+        });
+
+        let response = self
+            .request(Method::PUT, &url)
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .header(header::CONTENT_LENGTH, blob.size)
+            .body(reqwest::Body::wrap_stream(body))
+            .send()
+            .await
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to upload {}", blob.digest))?;
+        check(response, &format!("upload {}", blob.digest)).await?;
+        Ok(true)
+    }
+
+    /// Upload a manifest under `tag`.
+    pub async fn push_manifest(&self, tag: &str, media_type: &str, manifest: &[u8]) -> Result<()> {
+        let url = format!("{}/v2/{}/manifests/{tag}", self.base, self.repository);
+        let response = self
+            .request(Method::PUT, &url)
+            .header(header::CONTENT_TYPE, media_type)
+            .body(manifest.to_vec())
+            .send()
+            .await
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to push the manifest for {tag}"))?;
+        check(response, &format!("push the manifest for {tag}")).await?;
+        Ok(())
+    }
+
+    /// Push an image from the OCI layout in `dir`, tagged with each of `tags`.
+    pub async fn push_image(&self, dir: &Path, image: &Image, tags: &[String]) -> Result<()> {
+        self.push_blobs(dir, image).await?;
+        let manifest = read_blob(dir, &image.manifest).await?;
+        for tag in tags {
+            self.push_manifest(tag, &image.manifest.media_type, &manifest)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Push the images of an image index from the OCI layout in `dir`,
+    /// and the index itself tagged with each of `tags`.
+    pub async fn push_index(
+        &self,
+        dir: &Path,
+        images: &[Image],
+        index: &Descriptor,
+        tags: &[String],
+    ) -> Result<()> {
+        // The manifests are pushed by digest, so that the index can refer to them.
+        for image in images {
+            self.push_blobs(dir, image).await?;
+            let manifest = read_blob(dir, &image.manifest).await?;
+            self.push_manifest(
+                &image.manifest.digest,
+                &image.manifest.media_type,
+                &manifest,
+            )
+            .await?;
+        }
+        let index_data = read_blob(dir, index).await?;
+        for tag in tags {
+            self.push_manifest(tag, &index.media_type, &index_data)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Upload the layers and config of an image.
+    async fn push_blobs(&self, dir: &Path, image: &Image) -> Result<()> {
+        let uploads = stream::iter(image.layers.iter().chain([&image.config]))
+            .map(|blob| {
+                let span = info_span!(
+                    "pushing_blob",
+                    devenv.user_message = format!("Pushing {}", short_digest(&blob.digest))
+                );
+                span.pb_set_style(&upload_style());
+                span.pb_set_length(blob.size);
+                span.pb_start();
+                async move { self.push_blob(blob, &blob_path(dir, blob)).await }.instrument(span)
+            })
+            .buffer_unordered(MAX_PARALLEL_UPLOADS)
+            .try_collect::<Vec<_>>()
+            .await?;
+        let uploaded = uploads.iter().filter(|uploaded| **uploaded).count();
+        info!(
+            "Uploaded {uploaded} blobs, {} already existed",
+            uploads.len() - uploaded
+        );
+        Ok(())
+    }
+}
+
+/// Connect to the registry of `reference` with the credentials from [credentials].
+pub async fn login(reference: &Reference) -> Result<Client> {
+    let credentials = credentials(&reference.registry)?;
+    Client::connect(reference, credentials).await
+}
+
+async fn check(response: Response, action: &str) -> Result<Response> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    bail!("Failed to {action}: {status} {}", body.trim())
+}
+
+async fn read_blob(dir: &Path, blob: &Descriptor) -> Result<Vec<u8>> {
+    let path = blob_path(dir, blob);
+    tokio::fs::read(&path)
+        .await
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read {}", path.display()))
+}
+
+fn blob_path(dir: &Path, blob: &Descriptor) -> PathBuf {
+    let hex = blob.digest.strip_prefix("sha256:").unwrap_or(&blob.digest);
+    dir.join("blobs/sha256").join(hex)
+}
+
+fn short_digest(digest: &str) -> &str {
+    let hex = digest.strip_prefix("sha256:").unwrap_or(digest);
+    &hex[..hex.len().min(12)]
+}
+
+fn upload_style() -> ProgressStyle {
+    ProgressStyle::with_template(
+        "{span_child_prefix}{spinner:.blue} {span_fields} {bytes}/{total_bytes} {msg:.dim}",
+    )
+    .unwrap()
+    .tick_strings(&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oci;
+    use sha2::{Digest, Sha256};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn parses_references() {
+        let parse = |reference| Reference::parse(reference).unwrap();
+        assert_eq!(
+            parse("docker://ghcr.io/owner/app:1.0"),
+            Reference {
+                registry: "ghcr.io".to_string(),
+                repository: "owner/app".to_string(),
+                tag: Some("1.0".to_string()),
+            }
+        );
+        assert_eq!(parse("localhost:5000/app").registry, "localhost:5000");
+        assert_eq!(parse("localhost:5000/app").tag, None);
+        assert_eq!(parse("owner/app").registry, "docker.io");
+        assert_eq!(parse("app:latest").repository, "library/app");
+        assert_eq!(
+            parse("localhost:5000/app").base_url(),
+            "http://localhost:5000"
+        );
+        assert_eq!(parse("app").base_url(), "https://registry-1.docker.io");
+        assert!(Reference::parse("ghcr.io/owner/App").is_err());
+        assert!(Reference::parse("ghcr.io/owner/app@sha256:abc").is_err());
+        assert!(Reference::parse("ghcr.io/").is_err());
+    }
+
+    #[test]
+    fn reads_docker_credentials() {
+        let config: DockerConfig = serde_json::from_value(serde_json::json!({
+            "auths": {
+                "https://index.docker.io/v1/": { "auth": "dXNlcjpwYXNzOndvcmQ=" },
+                "https://ghcr.io": { "username": "owner", "password": "token" },
+                "quay.io": {},
+            }
+        }))
+        .unwrap();
+        assert_eq!(
+            config.credentials("docker.io").unwrap(),
+            Some(Credentials {
+                username: "user".to_string(),
+                password: "pass:word".to_string(),
+            })
+        );
+        assert_eq!(
+            config.credentials("ghcr.io").unwrap().unwrap().password,
+            "token"
+        );
+        assert_eq!(config.credentials("quay.io").unwrap(), None);
+        assert_eq!(config.credentials("registry.example.com").unwrap(), None);
+    }
+
+    #[test]
+    fn parses_challenges() {
+        let (scheme, params) = parse_challenge(
+            r#"Bearer realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:a/b:pull,push""#,
+        )
+        .unwrap();
+        assert_eq!(scheme, "bearer");
+        assert_eq!(params["realm"], "https://auth.docker.io/token");
+        assert_eq!(params["service"], "registry.docker.io");
+        assert_eq!(params["scope"], "repository:a/b:pull,push");
+
+        let (scheme, params) = parse_challenge(r#"Basic realm="Registry""#).unwrap();
+        assert_eq!(scheme, "basic");
+        assert_eq!(params["realm"], "Registry");
+    }
+
+    /// A registry that keeps blobs and manifests in memory and requires a bearer token.
+    #[derive(Clone, Default)]
+    struct MockRegistry {
+        blobs: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+        manifests: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+        uploads: Arc<Mutex<usize>>,
+    }
+
+    const TOKEN: &str = "secret-token";
+
+    impl MockRegistry {
+        async fn start(&self) -> String {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            let registry = self.clone();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let registry = registry.clone();
+                    tokio::spawn(async move { registry.serve(stream, address).await });
+                }
+            });
+            format!("127.0.0.1:{}", address.port())
+        }
+
+        async fn serve(&self, stream: tokio::net::TcpStream, address: std::net::SocketAddr) {
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader);
+            loop {
+                let mut request_line = String::new();
+                if reader.read_line(&mut request_line).await.unwrap_or(0) == 0 {
+                    return;
+                }
+                let mut parts = request_line.split_whitespace();
+                let method = parts.next().unwrap().to_string();
+                let target = parts.next().unwrap().to_string();
+
+                let mut headers = HashMap::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).await.unwrap();
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    let (name, value) = line.split_once(':').unwrap();
+                    headers.insert(name.to_lowercase(), value.trim().to_string());
+                }
+                let length = headers
+                    .get("content-length")
+                    .map_or(0, |length| length.parse().unwrap());
+                let mut body = vec![0; length];
+                tokio::io::AsyncReadExt::read_exact(&mut reader, &mut body)
+                    .await
+                    .unwrap();
+
+                let (status, extra, response) =
+                    self.handle(&method, &target, &headers, body, address);
+                let mut head = format!(
+                    "HTTP/1.1 {status} Mock\r\nContent-Length: {}\r\n",
+                    response.len()
+                );
+                for (name, value) in extra {
+                    head.push_str(&format!("{name}: {value}\r\n"));
+                }
+                head.push_str("\r\n");
+                writer.write_all(head.as_bytes()).await.unwrap();
+                if method != "HEAD" {
+                    writer.write_all(&response).await.unwrap();
+                }
+            }
+        }
+
+        fn handle(
+            &self,
+            method: &str,
+            target: &str,
+            headers: &HashMap<String, String>,
+            body: Vec<u8>,
+            address: std::net::SocketAddr,
+        ) -> (u16, Vec<(&'static str, String)>, Vec<u8>) {
+            let (path, query) = target.split_once('?').unwrap_or((target, ""));
+            if path == "/token" {
+                let expected = format!(
+                    "Basic {}",
+                    base64::engine::general_purpose::STANDARD.encode("user:password")
+                );
+                if headers.get("authorization") != Some(&expected)
+                    || !query.contains("scope=repository%3Aowner%2Fapp%3Apull%2Cpush")
+                {
+                    return (401, vec![], vec![]);
+                }
+                return (
+                    200,
+                    vec![],
+                    format!(r#"{{"token":"{TOKEN}"}}"#).into_bytes(),
+                );
+            }
+            if headers.get("authorization") != Some(&format!("Bearer {TOKEN}")) {
+                let challenge = format!(r#"Bearer realm="http://{address}/token",service="mock""#);
+                return (401, vec![("WWW-Authenticate", challenge)], vec![]);
+            }
+
+            let blobs = "/v2/owner/app/blobs/";
+            match (method, path) {
+                ("GET", "/v2/") => (200, vec![], vec![]),
+                ("HEAD", path) if path.starts_with(blobs) => {
+                    let digest = &path[blobs.len()..];
+                    let status = if self.blobs.lock().unwrap().contains_key(digest) {
+                        200
+                    } else {
+                        404
+                    };
+                    (status, vec![], vec![])
+                }
+                ("POST", "/v2/owner/app/blobs/uploads/") => {
+                    (202, vec![("Location", "/upload/1".to_string())], vec![])
+                }
+                ("PUT", "/upload/1") => {
+                    let digest = query.strip_prefix("digest=").unwrap().replace("%3A", ":");
+                    let actual = format!("sha256:{}", hex::encode(Sha256::digest(&body)));
+                    if digest != actual {
+                        return (400, vec![], b"digest mismatch".to_vec());
+                    }
+                    *self.uploads.lock().unwrap() += 1;
+                    self.blobs.lock().unwrap().insert(digest, body);
+                    (201, vec![], vec![])
+                }
+                ("PUT", path) if path.starts_with("/v2/owner/app/manifests/") => {
+                    let tag = path.rsplit('/').next().unwrap().to_string();
+                    self.manifests.lock().unwrap().insert(tag, body);
+                    (201, vec![], vec![])
+                }
+                _ => (404, vec![], vec![]),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn pushes_images_to_a_registry() {
+        let registry = MockRegistry::default();
+        let address = registry.start().await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let package = dir.path().join("abc-hello");
+        std::fs::create_dir_all(&package).unwrap();
+        std::fs::write(package.join("hello"), "hello").unwrap();
+        let spec: oci::ImageSpec = serde_json::from_value(serde_json::json!({
+            "image-config": { "Cmd": ["hello"] },
+            "layers": [{ "paths": [{ "path": package }] }],
+        }))
+        .unwrap();
+        let layout = dir.path().join("layout");
+        let image = oci::write_image(&spec, &layout).unwrap();
+
+        let reference = Reference::parse(&format!("{address}/owner/app")).unwrap();
+        assert!(Client::connect(&reference, None).await.is_err());
+
+        let credentials = Credentials {
+            username: "user".to_string(),
+            password: "password".to_string(),
+        };
+        let client = Client::connect(&reference, Some(credentials))
+            .await
+            .unwrap();
+        let tags = vec!["1.0".to_string(), "latest".to_string()];
+        client.push_image(&layout, &image, &tags).await.unwrap();
+        assert_eq!(*registry.uploads.lock().unwrap(), 2);
+        assert!(registry
+            .blobs
+            .lock()
+            .unwrap()
+            .contains_key(&image.layers[0].digest));
+        let manifest = registry.manifests.lock().unwrap()["latest"].clone();
+        assert_eq!(oci::sha256_digest(&manifest), image.manifest.digest);
+        assert!(registry.manifests.lock().unwrap().contains_key("1.0"));
+
+        // Blobs that the registry already has are skipped.
+        client.push_image(&layout, &image, &tags).await.unwrap();
+        assert_eq!(*registry.uploads.lock().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn pushes_image_indexes_to_a_registry() {
+        let registry = MockRegistry::default();
+        let address = registry.start().await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let spec = |arch: &str| -> oci::ImageSpec {
+            serde_json::from_value(serde_json::json!({
+                "image-config": { "Cmd": [format!("hello-{arch}")] },
+                "arch": arch,
+            }))
+            .unwrap()
+        };
+        let layout = dir.path().join("layout");
+        let (images, index) =
+            oci::write_multi_platform_image(&[spec("amd64"), spec("arm64")], &layout, "1.0")
+                .unwrap();
+
+        let reference = Reference::parse(&format!("{address}/owner/app")).unwrap();
+        let credentials = Credentials {
+            username: "user".to_string(),
+            password: "password".to_string(),
+        };
+        let client = Client::connect(&reference, Some(credentials))
+            .await
+            .unwrap();
+        client
+            .push_index(&layout, &images, &index, &["1.0".to_string()])
+            .await
+            .unwrap();
+
+        let manifests = registry.manifests.lock().unwrap();
+        assert_eq!(oci::sha256_digest(&manifests["1.0"]), index.digest);
+        for image in &images {
+            assert!(manifests.contains_key(&image.manifest.digest));
+        }
+    }
+}
@@ -0,0 +1,420 @@
+//! A local full-text index of packages and options for `devenv search`.
+//!
+//! Every package set and set of option declarations is indexed once, under a key that changes
+//! when they do, like the locked revision of nixpkgs. Searches then only query the index.
+
+use devenv_cache_core::db::Database;
+use miette::{IntoDiagnostic, Result, WrapErr};
+use serde::Serialize;
+use sqlx::Row;
+use std::path::Path;
+
+pub const MIGRATIONS: sqlx::migrate::Migrator = sqlx::migrate!();
+
+pub const INDEX_FILE: &str = "search.db";
+
+/// Sources that haven't been searched for this long are removed when something new is indexed.
+const MAX_UNUSED_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// How long to reuse sources whose key doesn't change with their contents,
+/// like the packages of nixpkgs without a lock.
+pub const MAX_UNLOCKED_AGE_SECS: i64 = 24 * 60 * 60;
+
+#[derive(Clone, Debug, Default)]
+pub struct SearchOptions {
+    /// Print the results as JSON.
+    pub json: bool,
+    /// Only search options.
+    pub options_only: bool,
+    /// Only search packages.
+    pub packages_only: bool,
+    /// The maximum number of results of each kind.
+    pub limit: usize,
+    /// Show everything about the packages that are found.
+    pub details: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Package,
+    Option,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Kind::Package => "package",
+            Kind::Option => "option",
+        }
+    }
+}
+
+/// A package or an option.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Entry {
+    pub kind: Kind,
+    pub name: String,
+    pub description: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub type_: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+}
+
+pub struct SearchIndex {
+    db: Database,
+}
+
+impl SearchIndex {
+    pub async fn open(path: &Path) -> Result<Self> {
+        let db = Database::new(path.to_path_buf(), &MIGRATIONS)
+            .await
+            .map_err(|e| miette::miette!("Failed to open the search index: {}", e))?;
+        Ok(Self { db })
+    }
+
+    /// The id of the source indexed under `key`, if it has been indexed,
+    /// and at most `max_age` seconds ago if given.
+    pub async fn source(&self, key: &str, max_age: Option<i64>) -> Result<Option<i64>> {
+        sqlx::query(
+            "UPDATE search_source SET used_at = strftime('%s', 'now')
+             WHERE key = ?1 AND (?2 IS NULL OR indexed_at >= strftime('%s', 'now') - ?2)
+             RETURNING id",
+        )
+        .bind(key)
+        .bind(max_age)
+        .fetch_optional(self.db.pool())
+        .await
+        .into_diagnostic()
+        .wrap_err("Failed to read the search index")
+        .map(|row| row.map(|row| row.get("id")))
+    }
+
+    /// Index `entries` under `key`, replacing whatever was indexed under it before.
+    pub async fn index(&self, key: &str, entries: &[Entry]) -> Result<i64> {
+        let mut tx = self
+            .db
+            .pool()
+            .begin()
+            .await
+            .into_diagnostic()
+            .wrap_err("Failed to update the search index")?;
+
+        let unused: Vec<i64> = sqlx::query_scalar(
+            "DELETE FROM search_source WHERE key = ? OR used_at < strftime('%s', 'now') - ? RETURNING id",
+        )
+        .bind(key)
+        .bind(MAX_UNUSED_SECS)
+        .fetch_all(&mut *tx)
+        .await
+        .into_diagnostic()?;
+        for id in unused {
+            sqlx::query("DELETE FROM search_entry WHERE source_id = ?")
+                .bind(id)
+                .execute(&mut *tx)
+                .await
+                .into_diagnostic()?;
+        }
+
+        let source_id: i64 =
+            sqlx::query_scalar("INSERT INTO search_source (key) VALUES (?) RETURNING id")
+                .bind(key)
+                .fetch_one(&mut *tx)
+                .await
+                .into_diagnostic()?;
+        for entry in entries {
+            sqlx::query(
+                "INSERT INTO search_entry (name, description, source_id, kind, version, type, default_value) VALUES (?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&entry.name)
+            .bind(&entry.description)
+            .bind(source_id)
+            .bind(entry.kind.as_str())
+            .bind(&entry.version)
+            .bind(&entry.type_)
+            .bind(&entry.default)
+            .execute(&mut *tx)
+            .await
+            .into_diagnostic()?;
+        }
+
+        tx.commit()
+            .await
+            .into_diagnostic()
+            .wrap_err("Failed to update the search index")?;
+        Ok(source_id)
+    }
+
+    /// Search the given sources, best matches first.
+    ///
+    /// Entries that contain every word of the query in their name or description are preferred.
+    /// If there are none, entries are ranked by how many three letter sequences of the query they
+    /// contain, so that misspelled queries still find something.
+    pub async fn search(&self, sources: &[i64], query: &str, limit: usize) -> Result<Vec<Entry>> {
+        let words: Vec<&str> = query.split_whitespace().collect();
+        let exact = self
+            .query(sources, query, &words, MatchMode::All, limit)
+            .await?;
+        if !exact.is_empty() || words.iter().all(|word| word.chars().count() < 3) {
+            return Ok(exact);
+        }
+        self.query(sources, query, &words, MatchMode::Trigrams, limit)
+            .await
+    }
+
+    async fn query(
+        &self,
+        sources: &[i64],
+        query: &str,
+        words: &[&str],
+        mode: MatchMode,
+        limit: usize,
+    ) -> Result<Vec<Entry>> {
+        if sources.is_empty() {
+            return Ok(Vec::new());
+        }
+        // The trigram tokenizer can't match words shorter than three letters
+        let (long, short): (Vec<&str>, Vec<&str>) =
+            words.iter().partition(|word| word.chars().count() >= 3);
+        let fts_query = match mode {
+            MatchMode::All => long
+                .iter()
+                .map(|word| quote(word))
+                .collect::<Vec<_>>()
+                .join(" AND "),
+            MatchMode::Trigrams => trigrams(&long)
+                .iter()
+                .map(|trigram| quote(trigram))
+                .collect::<Vec<_>>()
+                .join(" OR "),
+        };
+
+        let mut conditions = vec![format!(
+            "source_id IN ({})",
+            vec!["?"; sources.len()].join(", ")
+        )];
+        if !fts_query.is_empty() {
+            conditions.push("search_entry MATCH ?".to_string());
+        }
+        if mode == MatchMode::All {
+            conditions.extend(short.iter().map(|_| {
+                "(name LIKE ? ESCAPE '\\' OR description LIKE ? ESCAPE '\\')".to_string()
+            }));
+        }
+        let rank = if fts_query.is_empty() {
+            "0"
+        } else {
+            "bm25(search_entry, 10.0, 1.0)"
+        };
+        // Names, or parts of attribute paths, that are the query come first, then those that
+        // start with it, shortest first. The rest are ranked by relevance.
+        let sql = format!(
+            "SELECT name, description, kind, version, type, default_value,
+                 (name LIKE ? ESCAPE '\\' OR name LIKE ? ESCAPE '\\') AS exact,
+                 (name LIKE ? ESCAPE '\\' OR name LIKE ? ESCAPE '\\') AS prefix,
+                 {rank} AS score
+             FROM search_entry
+             WHERE {}
+             ORDER BY exact DESC, prefix DESC, CASE WHEN prefix THEN length(name) END, score, length(name)
+             LIMIT ?",
+            conditions.join(" AND ")
+        );
+
+        // The query is matched literally, even if it contains `%` or `_`.
+        let literal = escape_like(query);
+        let mut statement = sqlx::query(&sql)
+            .bind(&literal)
+            .bind(format!("%.{literal}"))
+            .bind(format!("{literal}%"))
+            .bind(format!("%.{literal}%"));
+        for source in sources {
+            statement = statement.bind(source);
+        }
+        if !fts_query.is_empty() {
+            statement = statement.bind(fts_query);
+        }
+        if mode == MatchMode::All {
+            for word in short {
+                let pattern = format!("%{}%", escape_like(word));
+                statement = statement.bind(pattern.clone()).bind(pattern);
+            }
+        }
+        let rows = statement
+            .bind(limit as i64)
+            .fetch_all(self.db.pool())
+            .await
+            .into_diagnostic()
+            .wrap_err("Failed to search the index")?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| Entry {
+                kind: match row.get::<&str, _>("kind") {
+                    "option" => Kind::Option,
+                    _ => Kind::Package,
+                },
+                name: row.get("name"),
+                description: row.get("description"),
+                version: row.get("version"),
+                type_: row.get("type"),
+                default: row.get("default_value"),
+            })
+            .collect())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MatchMode {
+    /// Match entries that contain every word.
+    All,
+    /// Match entries that contain any three letter sequence of the words.
+    Trigrams,
+}
+
+/// Quote a string for an FTS5 query, so that it's matched as a phrase.
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+/// Escape the wildcards of a LIKE pattern, for patterns that use `ESCAPE '\'`.
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn trigrams(words: &[&str]) -> Vec<String> {
+    let mut trigrams = Vec::new();
+    for word in words {
+        let chars: Vec<char> = word.chars().collect();
+        for trigram in chars.windows(3) {
+            let trigram: String = trigram.iter().collect();
+            if !trigrams.contains(&trigram) {
+                trigrams.push(trigram);
+            }
+        }
+    }
+    trigrams
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(name: &str, description: &str) -> Entry {
+        Entry {
+            kind: Kind::Package,
+            name: name.to_string(),
+            description: description.to_string(),
+            version: Some("1.0".to_string()),
+            type_: None,
+            default: None,
+        }
+    }
+
+    fn option(name: &str, description: &str) -> Entry {
+        Entry {
+            kind: Kind::Option,
+            name: name.to_string(),
+            description: description.to_string(),
+            version: None,
+            type_: Some("boolean".to_string()),
+            default: Some("false".to_string()),
+        }
+    }
+
+    fn names(entries: Vec<Entry>) -> Vec<String> {
+        entries.into_iter().map(|entry| entry.name).collect()
+    }
+
+    #[tokio::test]
+    async fn searches_the_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let index = SearchIndex::open(&dir.path().join(INDEX_FILE))
+            .await
+            .unwrap();
+        assert_eq!(index.source("packages", None).await.unwrap(), None);
+
+        let packages = index
+            .index(
+                "packages",
+                &[
+                    package("pkgs.python3Packages.pytest", "Framework for writing tests"),
+                    package(
+                        "pkgs.python3",
+                        "A high-level dynamically-typed programming language",
+                    ),
+                    package(
+                        "pkgs.jq",
+                        "Lightweight and flexible command-line JSON processor",
+                    ),
+                    package(
+                        "pkgs.ripgrep",
+                        "A utility that searches for a regex pattern",
+                    ),
+                ],
+            )
+            .await
+            .unwrap();
+        let options = index
+            .index(
+                "options",
+                &[option(
+                    "languages.python.enable",
+                    "Whether to enable tools for Python development.",
+                )],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            index.source("packages", None).await.unwrap(),
+            Some(packages)
+        );
+        assert_eq!(
+            index.source("packages", Some(60)).await.unwrap(),
+            Some(packages)
+        );
+
+        let both = [packages, options];
+        assert_eq!(
+            names(index.search(&both, "python", 10).await.unwrap()),
+            vec![
+                "pkgs.python3",
+                "languages.python.enable",
+                "pkgs.python3Packages.pytest"
+            ]
+        );
+        assert_eq!(
+            names(index.search(&[options], "python", 10).await.unwrap()),
+            vec!["languages.python.enable"]
+        );
+        assert_eq!(
+            names(index.search(&both, "json processor", 10).await.unwrap()),
+            vec!["pkgs.jq"]
+        );
+        assert_eq!(
+            names(index.search(&both, "jq", 10).await.unwrap()),
+            vec!["pkgs.jq"]
+        );
+        assert_eq!(
+            names(index.search(&both, "ripgrpe", 1).await.unwrap()),
+            vec!["pkgs.ripgrep"]
+        );
+        assert!(index.search(&both, "zz", 10).await.unwrap().is_empty());
+        // `_` isn't a wildcard, so it doesn't match the `n3` of python3.
+        assert!(index.search(&both, "n_", 10).await.unwrap().is_empty());
+
+        // Indexing a source again replaces its entries
+        let packages = index
+            .index("packages", &[package("pkgs.jq", "JSON processor")])
+            .await
+            .unwrap();
+        assert_eq!(
+            index.search(&[packages], "jq", 10).await.unwrap(),
+            vec![package("pkgs.jq", "JSON processor")]
+        );
+    }
+}
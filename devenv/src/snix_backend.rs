@@ -185,6 +185,11 @@ impl NixBackend for SnixBackend {
         Ok(())
     }
 
+    async fn substituters(&self) -> Result<Vec<String>> {
+        // TODO: Read the substituters from the Snix configuration
+        Ok(Vec::new())
+    }
+
     fn name(&self) -> &'static str {
         "snix"
     }
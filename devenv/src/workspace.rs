@@ -0,0 +1,247 @@
+//! Find devenv projects: the one a directory belongs to, or all of the projects in a monorepo.
+
+use crate::config;
+use cli_table::{print_stderr, Table, WithTitle};
+use futures::stream::{self, StreamExt};
+use miette::{IntoDiagnostic, Result, WrapErr};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// The file that marks the root of a project.
+pub const PROJECT_FILE: &str = "devenv.nix";
+
+/// Directories that never contain projects of their own.
+const SKIPPED_DIRS: &[&str] = &["node_modules", "target", "vendor"];
+
+/// The closest directory at or above `dir` that contains a project.
+pub fn find_root(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .find(|dir| dir.join(PROJECT_FILE).is_file())
+        .map(Path::to_path_buf)
+}
+
+/// All of the projects at or below `root`, sorted by path.
+///
+/// Hidden directories are skipped, as are directories that another project imports in its
+/// devenv.yaml, since those are modules rather than projects.
+pub fn find_projects(root: &Path) -> Result<Vec<PathBuf>> {
+    let root = root
+        .canonicalize()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to resolve {}", root.display()))?;
+
+    let mut projects = Vec::new();
+    let mut modules = HashSet::new();
+    let mut dirs = vec![root];
+    while let Some(dir) = dirs.pop() {
+        if dir.join(PROJECT_FILE).is_file() {
+            modules.extend(local_imports(&dir));
+            projects.push(dir.clone());
+        }
+
+        let entries = std::fs::read_dir(&dir)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read {}", dir.display()))?;
+        for entry in entries {
+            let entry = entry.into_diagnostic()?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            // Don't follow symlinks, to avoid visiting a project twice.
+            let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+            if is_dir && !name.starts_with('.') && !SKIPPED_DIRS.contains(&name.as_ref()) {
+                dirs.push(entry.path());
+            }
+        }
+    }
+
+    projects.retain(|project| !modules.contains(project));
+    projects.sort();
+    Ok(projects)
+}
+
+/// The directories that a project imports with relative paths in its config files.
+fn local_imports(project: &Path) -> Vec<PathBuf> {
+    let files = [
+        project.join(config::YAML_CONFIG),
+        project.join(config::YAML_LOCAL_CONFIG),
+    ];
+    let layers = match config::read_layers(&files) {
+        Ok(layers) => layers,
+        Err(e) => {
+            warn!("Failed to read the imports of {}: {e}", project.display());
+            return Vec::new();
+        }
+    };
+    let merged = config::merge_layers(&layers);
+    merged
+        .get("imports")
+        .and_then(|imports| imports.as_sequence())
+        .into_iter()
+        .flatten()
+        .filter_map(|import| import.as_str())
+        .filter(|import| import.starts_with("./") || import.starts_with("../"))
+        .filter(|import| !import.ends_with(".nix"))
+        .filter_map(|import| project.join(import).canonicalize().ok())
+        .collect()
+}
+
+/// The outcome of running a command in one project.
+pub struct ProjectResult {
+    pub project: PathBuf,
+    pub success: bool,
+    pub duration: Duration,
+}
+
+#[derive(Table)]
+struct ProjectSummary {
+    #[table(title = "Project")]
+    project: String,
+    #[table(title = "Result")]
+    result: &'static str,
+    #[table(title = "Duration")]
+    duration: String,
+}
+
+/// Run devenv with the given arguments in each project, with at most `max_parallel` at a time.
+///
+/// The output of each project is printed as a whole once it finishes, so it isn't interleaved.
+pub async fn run_in_projects(
+    root: &Path,
+    projects: &[PathBuf],
+    args: &[String],
+    max_parallel: usize,
+) -> Result<Vec<ProjectResult>> {
+    let exe = std::env::current_exe()
+        .into_diagnostic()
+        .wrap_err("Failed to find the devenv executable")?;
+
+    let mut results = stream::iter(projects)
+        .map(|project| {
+            let exe = &exe;
+            async move {
+                let start = Instant::now();
+                let output = tokio::process::Command::new(exe)
+                    .args(args)
+                    .current_dir(project)
+                    .stdin(Stdio::null())
+                    .output()
+                    .await
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to run devenv in {}", project.display()))?;
+                let result = ProjectResult {
+                    project: project.clone(),
+                    success: output.status.success(),
+                    duration: start.elapsed(),
+                };
+
+                let name = display_project(root, project);
+                eprintln!("==> {name}");
+                std::io::stderr()
+                    .write_all(&output.stderr)
+                    .into_diagnostic()?;
+                std::io::stdout()
+                    .write_all(&output.stdout)
+                    .into_diagnostic()?;
+                if result.success {
+                    info!("{name} succeeded");
+                } else {
+                    warn!("{name} failed");
+                }
+                Ok::<_, miette::Report>(result)
+            }
+        })
+        .buffer_unordered(max_parallel.max(1))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>>>()?;
+
+    results.sort_by(|a, b| a.project.cmp(&b.project));
+    Ok(results)
+}
+
+/// Print a table with the result of each project.
+pub fn print_summary(root: &Path, results: &[ProjectResult]) {
+    let rows = results
+        .iter()
+        .map(|result| ProjectSummary {
+            project: display_project(root, &result.project),
+            result: if result.success { "ok" } else { "failed" },
+            duration: format!("{:.1}s", result.duration.as_secs_f64()),
+        })
+        .collect::<Vec<_>>();
+    print_stderr(rows.with_title()).expect("Failed to print the summary");
+}
+
+fn display_project(root: &Path, project: &Path) -> String {
+    match project.strip_prefix(root) {
+        Ok(relative) if relative.as_os_str().is_empty() => ".".to_string(),
+        Ok(relative) => relative.display().to_string(),
+        Err(_) => project.display().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(dir: &Path, yaml: Option<&str>) {
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(dir.join(PROJECT_FILE), "{ }").unwrap();
+        if let Some(yaml) = yaml {
+            std::fs::write(dir.join(config::YAML_CONFIG), yaml).unwrap();
+        }
+    }
+
+    #[test]
+    fn finds_the_nearest_root() {
+        let dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let root = dir.path().join("services/api");
+        project(&root, None);
+        std::fs::create_dir_all(root.join("src/handlers")).unwrap();
+
+        assert_eq!(find_root(&root.join("src/handlers")), Some(root.clone()));
+        assert_eq!(find_root(&root), Some(root));
+        assert_eq!(find_root(&dir.path().join("services")), None);
+    }
+
+    #[test]
+    fn finds_projects_but_not_their_modules() {
+        let dir = tempfile::tempdir().expect("Failed to create temporary directory");
+        let root = dir.path().canonicalize().unwrap();
+        project(&root, Some("imports:\n  - ./shared\n  - ./extra.nix\n"));
+        project(&root.join("shared"), None);
+        project(&root.join("services/api"), None);
+        project(
+            &root.join("services/web"),
+            Some("imports:\n  - ../../lib/web\n"),
+        );
+        project(&root.join("lib/web"), None);
+        project(&root.join("services/web/node_modules/pkg"), None);
+        project(&root.join(".devenv/state"), None);
+
+        assert_eq!(
+            find_projects(&root).expect("Failed to find projects"),
+            vec![
+                root.clone(),
+                root.join("services/api"),
+                root.join("services/web"),
+            ]
+        );
+    }
+
+    #[test]
+    fn displays_projects_relative_to_the_root() {
+        let root = Path::new("/repo");
+        assert_eq!(display_project(root, root), ".");
+        assert_eq!(
+            display_project(root, &root.join("services/api")),
+            "services/api"
+        );
+        assert_eq!(display_project(root, Path::new("/elsewhere")), "/elsewhere");
+    }
+}
@@ -0,0 +1,696 @@
+//! Edit YAML documents in place, keeping comments, formatting, key order and unknown keys.
+//!
+//! serde_yaml only round-trips the data, so re-serializing devenv.yaml loses everything else.
+//! Instead, we find the lines of the block mapping entries that changed and rewrite only those.
+//! Values written in flow style, like `inputs: {}`, are re-rendered as a whole.
+
+use miette::{IntoDiagnostic, Result};
+use serde_yaml::{Mapping, Value};
+use std::fmt;
+
+/// A YAML document as a list of lines.
+#[derive(Clone, Debug, Default)]
+pub struct YamlDocument {
+    lines: Vec<String>,
+    trailing_newline: bool,
+}
+
+/// The lines of a block mapping entry: the key line and the lines of its value.
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    line: usize,
+    indent: usize,
+    /// One past the last line of the value. Trailing blank lines and comments are not included.
+    end: usize,
+}
+
+/// The deepest entry matching a key path.
+struct Found {
+    /// The number of keys of the path that were matched.
+    depth: usize,
+    /// The entry of the last matched key, or `None` for the document root.
+    entry: Option<Entry>,
+}
+
+impl YamlDocument {
+    pub fn parse(text: &str) -> Self {
+        Self {
+            lines: text.lines().map(String::from).collect(),
+            trailing_newline: text.is_empty() || text.ends_with('\n'),
+        }
+    }
+
+    /// The data in the document. An empty document is an empty mapping.
+    pub fn value(&self) -> Result<Value> {
+        let value: Value = serde_yaml::from_str(&self.to_string()).into_diagnostic()?;
+        Ok(match value {
+            Value::Null => Value::Mapping(Mapping::new()),
+            value => value,
+        })
+    }
+
+    /// Set the value at a key path, creating any missing parent mappings.
+    pub fn set(&mut self, path: &[&str], value: &Value) -> Result<()> {
+        let Some((key, _)) = path.split_last() else {
+            return Err(miette::miette!("Can't replace the whole document"));
+        };
+        let found = self.find(path);
+
+        if found.depth == path.len() {
+            let entry = found.entry.expect("a matched path has an entry");
+            let comment = match value {
+                Value::Mapping(_) | Value::Sequence(_) => None,
+                _ => inline_comment(&self.lines[entry.line]),
+            };
+            let mut lines = render_entry(key, value, entry.indent);
+            if let (Some(comment), Some(line)) = (comment, lines.first_mut()) {
+                line.push(' ');
+                line.push_str(comment);
+            }
+            self.lines.splice(entry.line..entry.end, lines);
+            return Ok(());
+        }
+
+        let rest = &path[found.depth..];
+        match found.entry {
+            None if !self.root_is_block() => {
+                // The root is written in flow style, like `{}`.
+                let mut root = self.value()?;
+                set_in_value(&mut root, path, value.clone());
+                self.lines.retain(|line| is_ignorable(line));
+                self.insert_entries(self.lines.len(), 0, &root);
+            }
+            None => {
+                let (indent, at) = match self.children(0, self.lines.len()).last() {
+                    Some((_, last)) => (last.indent, last.end),
+                    None => (0, self.lines.len()),
+                };
+                self.insert_entries(at, indent, &nest(rest, value.clone()));
+            }
+            Some(entry) if self.is_block_mapping(entry) => {
+                let (indent, at) = match self.children(entry.line + 1, entry.end).last() {
+                    Some((_, last)) => (last.indent, last.end),
+                    None => (entry.indent + 2, entry.end),
+                };
+                self.insert_entries(at, indent, &nest(rest, value.clone()));
+            }
+            Some(entry) => {
+                // The parent is a scalar or written in flow style: merge and render it again.
+                let parent_path = &path[..found.depth];
+                let mut parent = get_in_value(&self.value()?, parent_path)
+                    .cloned()
+                    .unwrap_or(Value::Null);
+                set_in_value(&mut parent, rest, value.clone());
+                let lines = render_entry(parent_path[found.depth - 1], &parent, entry.indent);
+                self.lines.splice(entry.line..entry.end, lines);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the value at a key path, along with any parent mappings that become empty.
+    pub fn remove(&mut self, path: &[&str]) -> Result<()> {
+        if path.is_empty() {
+            return Err(miette::miette!("Can't remove the whole document"));
+        }
+        let found = self.find(path);
+
+        if found.depth == path.len() {
+            let entry = found.entry.expect("a matched path has an entry");
+            self.remove_entry(entry);
+
+            // Drop parents that are left without any values.
+            for depth in (1..path.len()).rev() {
+                let parent = self.find(&path[..depth]);
+                let Some(entry) = parent.entry.filter(|_| parent.depth == depth) else {
+                    break;
+                };
+                let empty = inline_value(&self.lines[entry.line]).is_empty()
+                    && self.lines[entry.line + 1..entry.end]
+                        .iter()
+                        .all(|line| is_ignorable(line));
+                if !empty {
+                    break;
+                }
+                self.remove_entry(entry);
+            }
+            return Ok(());
+        }
+
+        match found.entry {
+            Some(entry) if !self.is_block_mapping(entry) => {
+                let parent_path = &path[..found.depth];
+                let mut parent = get_in_value(&self.value()?, parent_path)
+                    .cloned()
+                    .unwrap_or(Value::Null);
+                if remove_in_value(&mut parent, &path[found.depth..]) {
+                    let lines = render_entry(parent_path[found.depth - 1], &parent, entry.indent);
+                    self.lines.splice(entry.line..entry.end, lines);
+                }
+            }
+            None if !self.root_is_block() => {
+                let mut root = self.value()?;
+                if remove_in_value(&mut root, path) {
+                    self.lines.retain(|line| is_ignorable(line));
+                    self.insert_entries(self.lines.len(), 0, &root);
+                }
+            }
+            // The key doesn't exist.
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Remove the lines of an entry and the comment lines directly above it, which describe it.
+    fn remove_entry(&mut self, entry: Entry) {
+        let mut start = entry.line;
+        while start > 0 {
+            let line = &self.lines[start - 1];
+            let trimmed = line.trim_start();
+            if !trimmed.starts_with('#')
+                || indent_of(line) != entry.indent
+                || trimmed.starts_with("# yaml-language-server:")
+            {
+                break;
+            }
+            start -= 1;
+        }
+        self.lines.drain(start..entry.end);
+    }
+
+    /// Apply the differences between two versions of the document's data.
+    ///
+    /// Keys that are in neither version are left alone.
+    pub fn apply_diff(&mut self, old: &Value, new: &Value) -> Result<()> {
+        self.apply_diff_at(&mut Vec::new(), old, new)
+    }
+
+    fn apply_diff_at(&mut self, path: &mut Vec<String>, old: &Value, new: &Value) -> Result<()> {
+        match (old, new) {
+            (Value::Mapping(old), Value::Mapping(new)) => {
+                for key in old.keys().filter_map(Value::as_str) {
+                    if !new.contains_key(key) {
+                        path.push(key.to_string());
+                        self.remove(&as_strs(path))?;
+                        path.pop();
+                    }
+                }
+                for (key, new_value) in new.iter() {
+                    let Some(key) = key.as_str() else {
+                        continue;
+                    };
+                    path.push(key.to_string());
+                    match old.get(key) {
+                        Some(old_value) => self.apply_diff_at(path, old_value, new_value)?,
+                        None => self.set(&as_strs(path), new_value)?,
+                    }
+                    path.pop();
+                }
+                Ok(())
+            }
+            (old, new) if old != new => {
+                if path.is_empty() {
+                    *self = Self::parse(&serde_yaml::to_string(new).into_diagnostic()?);
+                    Ok(())
+                } else {
+                    self.set(&as_strs(path), new)
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Find the deepest block mapping entry along a key path.
+    fn find(&self, path: &[&str]) -> Found {
+        let mut found = Found {
+            depth: 0,
+            entry: None,
+        };
+        let (mut start, mut end) = (0, self.lines.len());
+        for key in path {
+            if let Some(entry) = found.entry {
+                if !self.is_block_mapping(entry) {
+                    break;
+                }
+            }
+            let Some((_, entry)) = self
+                .children(start, end)
+                .into_iter()
+                .find(|(name, _)| name == key)
+            else {
+                break;
+            };
+            found.depth += 1;
+            found.entry = Some(entry);
+            (start, end) = (entry.line + 1, entry.end);
+        }
+        found
+    }
+
+    /// The block mapping entries between two lines, at the indentation of the first one.
+    fn children(&self, start: usize, end: usize) -> Vec<(String, Entry)> {
+        let mut entries = Vec::new();
+        let mut indent = None;
+        let mut i = start;
+        while i < end {
+            let line = &self.lines[i];
+            if is_ignorable(line) {
+                i += 1;
+                continue;
+            }
+            let line_indent = indent_of(line);
+            let child_indent = *indent.get_or_insert(line_indent);
+            match parse_key(line).filter(|_| line_indent == child_indent) {
+                Some(key) => {
+                    let entry = Entry {
+                        line: i,
+                        indent: line_indent,
+                        end: self.entry_end(i, line_indent, end),
+                    };
+                    i = entry.end;
+                    entries.push((key, entry));
+                }
+                None => i += 1,
+            }
+        }
+        entries
+    }
+
+    fn entry_end(&self, start: usize, indent: usize, limit: usize) -> usize {
+        let mut end = start + 1;
+        for (i, line) in self.lines.iter().enumerate().take(limit).skip(start + 1) {
+            if is_ignorable(line) {
+                continue;
+            }
+            let line_indent = indent_of(line);
+            // Sequences are allowed at the same indentation as their key.
+            if line_indent > indent || (line_indent == indent && is_sequence_item(line)) {
+                end = i + 1;
+            } else {
+                break;
+            }
+        }
+        end
+    }
+
+    /// Whether the entry's value is a block mapping that we can insert keys into.
+    fn is_block_mapping(&self, entry: Entry) -> bool {
+        if !inline_value(&self.lines[entry.line]).is_empty() {
+            return false;
+        }
+        let body = &self.lines[entry.line + 1..entry.end];
+        match body.iter().find(|line| !is_ignorable(line)) {
+            Some(line) => parse_key(line).is_some(),
+            // An empty value, e.g. `inputs:`.
+            None => true,
+        }
+    }
+
+    fn root_is_block(&self) -> bool {
+        match self.lines.iter().find(|line| !is_ignorable(line)) {
+            Some(line) => parse_key(line).is_some(),
+            None => true,
+        }
+    }
+
+    /// Insert the entries of a mapping at the given line.
+    fn insert_entries(&mut self, at: usize, indent: usize, value: &Value) {
+        let Value::Mapping(mapping) = value else {
+            return;
+        };
+        let lines = mapping
+            .iter()
+            .filter_map(|(key, value)| Some(render_entry(key.as_str()?, value, indent)))
+            .flatten()
+            .collect::<Vec<_>>();
+        self.lines.splice(at..at, lines);
+    }
+}
+
+impl fmt::Display for YamlDocument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.lines.join("\n"))?;
+        if self.trailing_newline && !self.lines.is_empty() {
+            f.write_str("\n")?;
+        }
+        Ok(())
+    }
+}
+
+fn as_strs(path: &[String]) -> Vec<&str> {
+    path.iter().map(String::as_str).collect()
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+/// Blank lines, comments and document markers.
+fn is_ignorable(line: &str) -> bool {
+    let line = line.trim();
+    line.is_empty() || line.starts_with('#') || line == "---" || line == "..."
+}
+
+fn is_sequence_item(line: &str) -> bool {
+    let line = line.trim_start();
+    line == "-" || line.starts_with("- ")
+}
+
+/// Parse the key of a block mapping entry, e.g. `nixpkgs` in `  nixpkgs:`.
+fn parse_key(line: &str) -> Option<String> {
+    let rest = line.trim_start();
+    if is_sequence_item(rest) || rest.starts_with(['{', '[', '#']) {
+        return None;
+    }
+
+    let (key, after) = match rest.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let close = rest[1..].find(quote)? + 1;
+            let key: String = serde_yaml::from_str(&rest[..=close]).ok()?;
+            (key, &rest[close + 1..])
+        }
+        _ => {
+            let colon = rest
+                .match_indices(':')
+                .map(|(i, _)| i)
+                .find(|&i| rest[i + 1..].is_empty() || rest[i + 1..].starts_with(' '))?;
+            (rest[..colon].trim_end().to_string(), &rest[colon..])
+        }
+    };
+
+    let after = after.trim_start();
+    (after == ":" || after.starts_with(": ")).then_some(key)
+}
+
+/// The value on the key's line, without any comment.
+fn inline_value(line: &str) -> &str {
+    let Some(key) = parse_key(line) else {
+        return "";
+    };
+    let rest = line.trim_start();
+    // Skip past the (possibly quoted) key and the colon.
+    let skip = if rest.starts_with(['"', '\'']) {
+        rest[1..]
+            .find(&rest[..1])
+            .map(|i| i + 2)
+            .unwrap_or(rest.len())
+    } else {
+        key.len()
+    };
+    let value = rest[skip..].trim_start().trim_start_matches(':').trim();
+    match value.find(" #") {
+        Some(i) if !value.starts_with(['"', '\'']) => value[..i].trim_end(),
+        _ if value.starts_with('#') => "",
+        _ => value,
+    }
+}
+
+/// The trailing comment on a line, like `# pinned` in `url: github:foo # pinned`.
+fn inline_comment(line: &str) -> Option<&str> {
+    let value = inline_value(line);
+    if value.starts_with(['"', '\'']) {
+        return None;
+    }
+    let i = line.find(" #")?;
+    Some(line[i + 1..].trim_end())
+}
+
+fn render_key(key: &str) -> String {
+    serde_yaml::to_string(&Value::String(key.to_string()))
+        .map(|key| key.trim_end().to_string())
+        .unwrap_or_else(|_| key.to_string())
+}
+
+/// Render a `key: value` entry in block style at the given indentation.
+fn render_entry(key: &str, value: &Value, indent: usize) -> Vec<String> {
+    let prefix = " ".repeat(indent);
+    let key = render_key(key);
+    let rendered = serde_yaml::to_string(value).unwrap_or_default();
+
+    let is_block = match value {
+        Value::Mapping(mapping) => !mapping.is_empty(),
+        Value::Sequence(sequence) => !sequence.is_empty(),
+        _ => rendered.trim_end().contains('\n'),
+    };
+    if !is_block {
+        return vec![format!("{prefix}{key}: {}", rendered.trim_end())];
+    }
+
+    match value {
+        Value::Mapping(_) | Value::Sequence(_) => std::iter::once(format!("{prefix}{key}:"))
+            .chain(rendered.lines().map(|line| format!("{prefix}  {line}")))
+            .collect(),
+        // Block scalars start on the key's line, e.g. `key: |-`.
+        _ => {
+            let mut lines = rendered.lines();
+            let header = lines.next().unwrap_or_default();
+            std::iter::once(format!("{prefix}{key}: {header}"))
+                .chain(lines.map(|line| format!("{prefix}{line}")))
+                .collect()
+        }
+    }
+}
+
+/// Wrap a value in mappings for each key of the path.
+fn nest(path: &[&str], value: Value) -> Value {
+    path.iter().rev().fold(value, |value, key| {
+        let mut mapping = Mapping::new();
+        mapping.insert(Value::String(key.to_string()), value);
+        Value::Mapping(mapping)
+    })
+}
+
+fn get_in_value<'a>(value: &'a Value, path: &[&str]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, key| value.get(key))
+}
+
+fn set_in_value(value: &mut Value, path: &[&str], new: Value) {
+    let Some((key, rest)) = path.split_first() else {
+        *value = new;
+        return;
+    };
+    if !value.is_mapping() {
+        *value = Value::Mapping(Mapping::new());
+    }
+    if let Value::Mapping(mapping) = value {
+        let child = mapping
+            .entry(Value::String(key.to_string()))
+            .or_insert(Value::Null);
+        set_in_value(child, rest, new);
+    }
+}
+
+fn remove_in_value(value: &mut Value, path: &[&str]) -> bool {
+    match path {
+        [] => false,
+        [key] => value
+            .as_mapping_mut()
+            .and_then(|mapping| mapping.remove(*key))
+            .is_some(),
+        [key, rest @ ..] => value
+            .get_mut(key)
+            .is_some_and(|child| remove_in_value(child, rest)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INIT: &str = indoc::indoc! {r#"
+        # yaml-language-server: $schema=https://devenv.sh/devenv.schema.json
+        inputs:
+          nixpkgs:
+            url: github:cachix/devenv-nixpkgs/rolling # keep it rolling
+          # Python versions
+          nixpkgs-python:
+            url: github:cachix/nixpkgs-python
+            inputs:
+              nixpkgs:
+                follows: nixpkgs
+
+        # If you're using non-OSS software, you can set allowUnfree to true.
+        allowUnfree: true
+        someUnknownField:
+          - a
+          - b
+    "#};
+
+    fn yaml(value: &str) -> Value {
+        serde_yaml::from_str(value).unwrap()
+    }
+
+    #[test]
+    fn parses_keys() {
+        assert_eq!(parse_key("  url: github:foo"), Some("url".to_string()));
+        assert_eq!(parse_key("inputs:"), Some("inputs".to_string()));
+        assert_eq!(parse_key("\"a b\": 1"), Some("a b".to_string()));
+        assert_eq!(
+            parse_key("url: http://example.com"),
+            Some("url".to_string())
+        );
+        assert_eq!(parse_key("- a: 1"), None);
+        assert_eq!(parse_key("# comment: 1"), None);
+        assert_eq!(parse_key("github:foo"), None);
+        assert_eq!(inline_value("  url: github:foo # comment"), "github:foo");
+        assert_eq!(inline_value("inputs: # comment"), "");
+        assert_eq!(inline_value("inputs: {}"), "{}");
+    }
+
+    #[test]
+    fn unchanged_document_round_trips() {
+        let doc = YamlDocument::parse(INIT);
+        assert_eq!(doc.to_string(), INIT);
+        let no_newline = "allowUnfree: true";
+        assert_eq!(YamlDocument::parse(no_newline).to_string(), no_newline);
+    }
+
+    #[test]
+    fn adds_a_nested_entry() {
+        let mut doc = YamlDocument::parse(INIT);
+        doc.set(
+            &["inputs", "rust-overlay", "url"],
+            &yaml("github:oxalica/rust-overlay"),
+        )
+        .unwrap();
+        assert_eq!(
+            doc.to_string(),
+            INIT.replace(
+                "        follows: nixpkgs\n",
+                "        follows: nixpkgs\n  rust-overlay:\n    url: github:oxalica/rust-overlay\n"
+            )
+        );
+    }
+
+    #[test]
+    fn replaces_a_scalar_and_keeps_its_comment() {
+        let mut doc = YamlDocument::parse(INIT);
+        doc.set(
+            &["inputs", "nixpkgs", "url"],
+            &yaml("github:NixOS/nixpkgs/nixos-24.11"),
+        )
+        .unwrap();
+        assert_eq!(
+            doc.to_string(),
+            INIT.replace(
+                "github:cachix/devenv-nixpkgs/rolling # keep it rolling",
+                "github:NixOS/nixpkgs/nixos-24.11 # keep it rolling"
+            )
+        );
+    }
+
+    #[test]
+    fn removes_an_entry_and_empty_parents() {
+        let mut doc = YamlDocument::parse(INIT);
+        doc.remove(&["inputs", "nixpkgs-python", "inputs", "nixpkgs"])
+            .unwrap();
+        assert_eq!(
+            doc.to_string(),
+            INIT.replace(
+                "    inputs:\n      nixpkgs:\n        follows: nixpkgs\n",
+                ""
+            )
+        );
+
+        let mut doc = YamlDocument::parse(INIT);
+        doc.remove(&["inputs", "nixpkgs-python"]).unwrap();
+        assert_eq!(
+            doc.to_string(),
+            indoc::indoc! {r#"
+                # yaml-language-server: $schema=https://devenv.sh/devenv.schema.json
+                inputs:
+                  nixpkgs:
+                    url: github:cachix/devenv-nixpkgs/rolling # keep it rolling
+
+                # If you're using non-OSS software, you can set allowUnfree to true.
+                allowUnfree: true
+                someUnknownField:
+                  - a
+                  - b
+            "#}
+        );
+    }
+
+    #[test]
+    fn removes_the_comments_of_an_entry() {
+        let mut doc = YamlDocument::parse(INIT);
+        doc.remove(&["allowUnfree"]).unwrap();
+        assert_eq!(
+            doc.to_string(),
+            INIT.replace(
+                "# If you're using non-OSS software, you can set allowUnfree to true.\nallowUnfree: true\n",
+                ""
+            )
+        );
+
+        let mut doc = YamlDocument::parse(INIT);
+        doc.remove(&["inputs"]).unwrap();
+        assert!(doc
+            .to_string()
+            .starts_with("# yaml-language-server: $schema=https://devenv.sh/devenv.schema.json\n"));
+    }
+
+    #[test]
+    fn adds_top_level_keys_to_documents() {
+        let mut doc = YamlDocument::parse(INIT);
+        doc.set(&["impure"], &Value::Bool(true)).unwrap();
+        assert_eq!(doc.to_string(), format!("{INIT}impure: true\n"));
+
+        let mut doc = YamlDocument::parse("");
+        doc.set(&["inputs", "nixpkgs", "url"], &yaml("github:foo"))
+            .unwrap();
+        assert_eq!(
+            doc.to_string(),
+            "inputs:\n  nixpkgs:\n    url: github:foo\n"
+        );
+
+        let mut doc = YamlDocument::parse("# comment\n{}\n");
+        doc.set(&["allowUnfree"], &Value::Bool(true)).unwrap();
+        assert_eq!(doc.to_string(), "# comment\nallowUnfree: true\n");
+    }
+
+    #[test]
+    fn merges_into_flow_values() {
+        let mut doc = YamlDocument::parse("inputs: {}\nimpure: true\n");
+        doc.set(&["inputs", "nixpkgs", "url"], &yaml("github:foo"))
+            .unwrap();
+        assert_eq!(
+            doc.to_string(),
+            "inputs:\n  nixpkgs:\n    url: github:foo\nimpure: true\n"
+        );
+
+        let mut doc = YamlDocument::parse("inputs: { nixpkgs: { url: a }, other: { url: b } }\n");
+        doc.remove(&["inputs", "other"]).unwrap();
+        assert_eq!(doc.to_string(), "inputs:\n  nixpkgs:\n    url: a\n");
+    }
+
+    #[test]
+    fn applies_a_diff() {
+        let mut doc = YamlDocument::parse(INIT);
+        let old = doc.value().unwrap();
+        let mut new = old.clone();
+        remove_in_value(&mut new, &["inputs", "nixpkgs-python"]);
+        set_in_value(&mut new, &["imports"], yaml("[./backend]"));
+        set_in_value(&mut new, &["allowUnfree"], Value::Bool(false));
+
+        doc.apply_diff(&old, &new).unwrap();
+        assert_eq!(doc.value().unwrap(), new);
+        assert_eq!(
+            doc.to_string(),
+            indoc::indoc! {r#"
+                # yaml-language-server: $schema=https://devenv.sh/devenv.schema.json
+                inputs:
+                  nixpkgs:
+                    url: github:cachix/devenv-nixpkgs/rolling # keep it rolling
+
+                # If you're using non-OSS software, you can set allowUnfree to true.
+                allowUnfree: false
+                someUnknownField:
+                  - a
+                  - b
+                imports:
+                  - ./backend
+            "#}
+        );
+    }
+}
@@ -1,5 +1,8 @@
 { ... }:
 
+# devenv can export its own traces to this collector when built with the `otel` feature:
+#
+#   $ devenv --otlp-endpoint http://localhost:4318 shell
 {
   services.clickhouse.enable = true;
 
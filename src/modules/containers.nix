@@ -394,6 +394,26 @@ in
         default = false;
         description = "Set to true when the environment is building a container.";
       };
+
+      summary = lib.mkOption {
+        type = types.attrsOf types.anything;
+        internal = true;
+        readOnly = true;
+        description = "The name, entrypoint, startup command, registry and version of each container, for `devenv container list`.";
+        default = lib.mapAttrs
+          (_: cfg:
+            let
+              # The default name throws if the project doesn't have a name.
+              name = builtins.tryEval cfg.name;
+            in
+            {
+              name = if name.success then name.value else null;
+              entrypoint = map toString cfg.entrypoint;
+              startupCommand = if cfg.startupCommand == null then null else toString cfg.startupCommand;
+              inherit (cfg) registry version;
+            })
+          config.containers;
+      };
     };
   };
 
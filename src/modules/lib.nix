@@ -41,6 +41,20 @@
       in
         inputs.${name} or (throw "To use '${attribute}', ${command}\n\n");
 
+    # Opt an evaluation out of devenv's eval cache, e.g. when it depends on network state.
+    uncacheable = reason: value: builtins.trace "devenv uncacheable: ${reason}" value;
+
+    # Builtins that devenv's eval cache can't see on its own.
+    # These trace what they access, so that the cache tracks local sources
+    # and doesn't cache evaluations that depend on remote sources or the time.
+    fetchGit = args: builtins.trace "devenv fetchGit: '${toString (args.url or args)}'" (builtins.fetchGit args);
+    fetchTarball = args: builtins.trace "devenv fetchTarball: '${toString (args.url or args)}'" (builtins.fetchTarball args);
+    fetchurl = args: builtins.trace "devenv fetchurl: '${toString (args.url or args)}'" (builtins.fetchurl args);
+    filterSource = filter: path: builtins.trace "devenv filterSource: '${toString path}'" (builtins.filterSource filter path);
+    path = args: builtins.trace "devenv filterSource: '${toString args.path}'" (builtins.path args);
+    hashFile = type: path: builtins.trace "devenv hashFile: '${toString path}'" (builtins.hashFile type path);
+    currentTime = builtins.trace "devenv currentTime" builtins.currentTime;
+
     mkTests = folder:
       let
         mk = dir: {